@@ -0,0 +1,109 @@
+use alloy_dyn_abi::{DynSolType, DynSolValue, FunctionExt, JsonAbiExt, Specifier};
+use alloy_json_abi::{Function, JsonAbi};
+use alloy_primitives::hex;
+use serde_json::Value;
+use crate::error::Error;
+
+/// Parses a JSON ABI given either as an array of items, as a string holding that array,
+/// or as a compiler artifact with an `abi` field.
+pub fn parse_abi(abi: &Value) -> Result<JsonAbi, Error> {
+    match abi {
+        Value::String(s) => Ok(serde_json::from_str::<JsonAbi>(s)?),
+        Value::Object(o) if o.contains_key("abi") => parse_abi(&o["abi"]),
+        _ => Ok(serde_json::from_value::<JsonAbi>(abi.clone())?)
+    }
+}
+
+/// Looks up a function by full signature (`transfer(address,uint256)`) or by name.
+/// A bare name is rejected when the ABI overloads it.
+pub fn find_function<'a>(abi: &'a JsonAbi, function: &str) -> Result<&'a Function, Error> {
+    let name = function.split('(').next().unwrap_or(function).trim();
+    let candidates = match abi.function(name) {
+        Some(f) => f,
+        None => return Err(Error::InvalidRequest(format!("function '{}' not found in abi", name)))
+    };
+    if function.contains('(') {
+        let signature: String = function.chars().filter(|c| !c.is_whitespace()).collect();
+        return match candidates.iter().find(|f| f.signature() == signature) {
+            Some(f) => Ok(f),
+            None => Err(Error::InvalidRequest(format!("function '{}' not found in abi", signature)))
+        };
+    }
+    match candidates.as_slice() {
+        [f] => Ok(f),
+        _ => Err(Error::InvalidRequest(format!("function '{}' is overloaded, use its full signature", name)))
+    }
+}
+
+/// ABI-encodes a call to `function` (selector included) with the JSON `args` array.
+pub fn encode_call(function: &Function, args: &Value) -> Result<Vec<u8>, Error> {
+    let args = match args {
+        Value::Null => Vec::new(),
+        Value::Array(a) => a.clone(),
+        _ => return Err(Error::InvalidRequest("args must be an array".to_string()))
+    };
+    if args.len() != function.inputs.len() {
+        return Err(Error::InvalidRequest(format!("function '{}' expects {} arguments, got {}", function.signature(), function.inputs.len(), args.len())));
+    }
+    let mut values = Vec::new();
+    for (param, arg) in function.inputs.iter().zip(args.iter()) {
+        values.push(json_to_value(&param.resolve()?, arg)?);
+    }
+    Ok(function.abi_encode_input(&values)?)
+}
+
+/// Decodes the return data of `function` into a JSON array, one entry per output.
+pub fn decode_output(function: &Function, data: &[u8]) -> Result<Value, Error> {
+    let values = function.abi_decode_output(data, true)?;
+    Ok(Value::Array(values.iter().map(value_to_json).collect()))
+}
+
+/// Converts a JSON argument to a value of type `ty`. Arrays and tuples are given as JSON arrays,
+/// scalars as strings, numbers or booleans.
+pub fn json_to_value(ty: &DynSolType, arg: &Value) -> Result<DynSolValue, Error> {
+    match (ty, arg) {
+        (DynSolType::Array(inner), Value::Array(items)) => {
+            let values: Result<Vec<_>, _> = items.iter().map(|i| json_to_value(inner, i)).collect();
+            Ok(DynSolValue::Array(values?))
+        },
+        (DynSolType::FixedArray(inner, len), Value::Array(items)) => {
+            if items.len() != *len {
+                return Err(Error::InvalidRequest(format!("expected {} items for {}, got {}", len, ty, items.len())));
+            }
+            let values: Result<Vec<_>, _> = items.iter().map(|i| json_to_value(inner, i)).collect();
+            Ok(DynSolValue::FixedArray(values?))
+        },
+        (DynSolType::Tuple(types), Value::Array(items)) => {
+            if items.len() != types.len() {
+                return Err(Error::InvalidRequest(format!("expected {} fields for {}, got {}", types.len(), ty, items.len())));
+            }
+            let values: Result<Vec<_>, _> = types.iter().zip(items.iter()).map(|(t, i)| json_to_value(t, i)).collect();
+            Ok(DynSolValue::Tuple(values?))
+        },
+        (_, Value::String(s)) => Ok(ty.coerce_str(s)?),
+        (_, Value::Number(n)) => Ok(ty.coerce_str(&n.to_string())?),
+        (_, Value::Bool(b)) => Ok(ty.coerce_str(&b.to_string())?),
+        _ => Err(Error::InvalidRequest(format!("cannot convert '{}' to {}", arg, ty)))
+    }
+}
+
+/// Converts a decoded value to JSON. Integers are rendered as decimal strings so that
+/// 256-bit values survive the round trip.
+pub fn value_to_json(value: &DynSolValue) -> Value {
+    match value {
+        DynSolValue::Bool(b) => Value::Bool(*b),
+        DynSolValue::Int(i, _) => Value::String(i.to_string()),
+        DynSolValue::Uint(u, _) => Value::String(u.to_string()),
+        DynSolValue::FixedBytes(w, size) => Value::String(hex::encode_prefixed(&w[..*size])),
+        DynSolValue::Address(a) => Value::String(a.to_string()),
+        DynSolValue::Function(f) => Value::String(hex::encode_prefixed(f)),
+        DynSolValue::Bytes(b) => Value::String(hex::encode_prefixed(b)),
+        DynSolValue::String(s) => Value::String(s.clone()),
+        DynSolValue::Array(items) | DynSolValue::FixedArray(items) | DynSolValue::Tuple(items) => {
+            Value::Array(items.iter().map(value_to_json).collect())
+        },
+        DynSolValue::CustomStruct { prop_names, tuple, .. } => {
+            Value::Object(prop_names.iter().cloned().zip(tuple.iter().map(value_to_json)).collect())
+        }
+    }
+}
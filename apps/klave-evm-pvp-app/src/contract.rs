@@ -0,0 +1,111 @@
+use std::fmt::{self, Display, Formatter};
+use alloy_json_abi::JsonAbi;
+use alloy_primitives::Address;
+use alloy_rpc_types_eth::TransactionReceipt;
+use serde::{Deserialize, Serialize};
+use serde_json::to_string;
+use klave;
+use crate::error::Error;
+use crate::klave_networks::networks::Networks;
+
+pub(crate) const CONTRACT_TABLE: &str = "contractTable";
+
+/// A named contract registered in the ledger, with the ABI used to encode calls to it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Contract {
+    pub name: String,
+    pub network_name: String,
+    /// Unset until `contract_confirm_deployment` records it from the deployment receipt.
+    pub address: Option<Address>,
+    pub abi: JsonAbi,
+    pub deployment_tx_hash: Option<String>,
+}
+
+impl Display for Contract {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match serde_json::to_string(self) {
+            Ok(s) => s,
+            Err(e) => {
+                format!("ERROR: failed to serialize Contract: {}", e)
+            }
+        })
+    }
+}
+
+impl Contract {
+    pub fn new(name: &str, network_name: &str, address: Option<Address>, abi: JsonAbi, deployment_tx_hash: Option<&str>) -> Contract {
+        Contract {
+            name: name.to_string(),
+            network_name: network_name.to_string(),
+            address,
+            abi,
+            deployment_tx_hash: deployment_tx_hash.map(|h| h.to_string()),
+        }
+    }
+
+    pub fn load(name: &str) -> Result<Contract, Error> {
+        match klave::ledger::get_table(CONTRACT_TABLE).get(name) {
+            Ok(v) => {
+                let contract: Contract = match serde_json::from_slice(&v) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        return Err(e.into());
+                    }
+                };
+                Ok(contract)
+            },
+            Err(_) => Err(Error::NotFound(format!("contract '{}' not found", name)))
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let serialized_contract = match to_string(&self) {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(e.into());
+            }
+        };
+        match klave::ledger::get_table(CONTRACT_TABLE).set(&self.name, serialized_contract.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::Ledger(format!("failed to save contract: {}", e)))
+        }
+    }
+
+    pub fn remove(&self) -> Result<(), Error> {
+        klave::ledger::get_table(CONTRACT_TABLE).remove(&self.name)?;
+        Ok(())
+    }
+
+    /// The address calls are sent to, once the deployment is confirmed.
+    pub fn get_address(&self) -> Result<Address, Error> {
+        match self.address {
+            Some(address) => Ok(address),
+            None => Err(Error::InvalidState(format!("contract '{}' is awaiting its deployment receipt, confirm it with contract_confirm_deployment", self.name)))
+        }
+    }
+
+    /// Records the address from the deployment receipt's `contractAddress`. Callers persist the update with `save`.
+    pub fn record_deployment(&mut self, nm: &Networks) -> Result<Address, Error> {
+        if let Some(address) = self.address {
+            return Ok(address);
+        }
+        let tx_hash = match &self.deployment_tx_hash {
+            Some(h) => h,
+            None => return Err(Error::InvalidState(format!("contract '{}' has neither an address nor a deployment transaction", self.name)))
+        };
+        let receipt = match nm.send::<Option<TransactionReceipt>>(&self.network_name, "eth_getTransactionReceipt", &[&format!("\"{}\"", tx_hash)])? {
+            Some(r) => r,
+            None => return Err(Error::InvalidState(format!("deployment transaction {} of contract '{}' is not mined yet", tx_hash, self.name)))
+        };
+        if !receipt.status() {
+            return Err(Error::InvalidState(format!("deployment transaction {} of contract '{}' failed on chain", tx_hash, self.name)));
+        }
+        match receipt.contract_address {
+            Some(address) => {
+                self.address = Some(address);
+                Ok(address)
+            },
+            None => Err(Error::Rpc(format!("receipt of {} has no contractAddress", tx_hash)))
+        }
+    }
+}
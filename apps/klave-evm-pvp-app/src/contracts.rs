@@ -0,0 +1,112 @@
+use std::fmt::Display;
+use serde::{Deserialize, Serialize};
+use serde_json::to_string;
+use crate::contract::{Contract, CONTRACT_TABLE};
+use klave;
+use crate::error::Error;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Contracts {
+    list: Vec<String>
+}
+
+impl Display for Contracts {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", match serde_json::to_string(self) {
+            Ok(s) => s,
+            Err(e) => {
+                format!("ERROR: failed to serialize Contracts: {}", e)
+            }
+        })
+    }
+}
+
+impl Contracts {
+    fn new() -> Contracts {
+        Contracts {
+            list: Vec::new(),
+        }
+    }
+
+    pub fn load() -> Result<Contracts, Error> {
+        match klave::ledger::get_table(CONTRACT_TABLE).get("ALL") {
+            Ok(v) if v.is_empty() => Err(Error::NotFound(String::from("contract registry not found"))),
+            Ok(v) => {
+                let contracts: Contracts = match serde_json::from_slice(&v) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        return Err(e.into());
+                    }
+                };
+                Ok(contracts)
+            },
+            Err(_) => {
+                Err(Error::NotFound(String::from("contract registry not found")))
+            }
+        }
+    }
+
+    /// Starts an empty registry only when none was ever saved. Any other failure is
+    /// returned, as saving an empty list over an unreadable one would wipe the registry.
+    pub fn get() -> Result<Contracts, Error> {
+        match Contracts::load() {
+            Err(Error::NotFound(_)) => Ok(Contracts::new()),
+            result => result
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let serialized_contracts = match to_string(&self) {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(e.into());
+            }
+        };
+        match klave::ledger::get_table(CONTRACT_TABLE).set("ALL", serialized_contracts.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::Ledger(e.to_string()))
+        }
+    }
+
+    pub fn add_contract(&mut self, contract: &Contract) -> Result<(), Error> {
+        if contract.name == "ALL" {
+            return Err(Error::InvalidRequest("contract name 'ALL' is reserved".to_string()));
+        }
+        if self.list.iter().any(|c| c == &contract.name) {
+            return Err(Error::AlreadyExists(format!("contract {} already exists", contract.name)));
+        }
+
+        contract.save()?;
+        self.list.push(contract.name.clone());
+        self.save()
+    }
+
+    pub fn remove_contract(&mut self, name: &str) -> Result<(), Error> {
+        let contract = match self.get_contract(name) {
+            Some(c) => c,
+            None => return Err(Error::NotFound(format!("contract {} not found", name)))
+        };
+        contract.remove()?;
+        self.list.retain(|c| c != name);
+        self.save()
+    }
+
+    pub fn get_contract(&self, name: &str) -> Option<Contract> {
+        for contract in &self.list {
+            if contract == name {
+                let contract = match Contract::load(contract) {
+                    Ok(c) => c,
+                    Err(_) => {
+                        return None;
+                    }
+                };
+                return Some(contract);
+            }
+        }
+        None
+    }
+
+    pub fn get_contracts(&self) -> &Vec<String> {
+        &self.list
+    }
+}
@@ -0,0 +1,384 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use alloy_primitives::{hex, Address, U256};
+use alloy_sol_types::SolCall;
+use crate::{error::Error, klave_networks::networks::Networks, solidity::{erc1155, erc721}, transaction::{self, ExpectedTransfer, NetworkTransaction, Participant, PvPstate, Transaction}, user::User, wallet::Wallet};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetStandard {
+    Erc721,
+    Erc1155,
+}
+
+/// A non-fungible token, or `amount` units of an ERC-1155 token id.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Asset {
+    pub standard: AssetStandard,
+    pub contract_address: String,
+    pub token_id: U256,
+    /// Always one for ERC-721 assets.
+    pub amount: U256,
+}
+
+/// `address` delivers `asset` on `network_name`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Delivery {
+    pub network_name: String,
+    pub address: String,
+    pub asset: Asset,
+}
+
+/// The seller delivers an asset against a payment from the buyer, through the escrow of the transaction.
+///
+/// The PvP state machine is reused: the delivery plays the source leg and the payment the destination leg.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DeliveryVsPayment {
+    pub delivery: Delivery,
+    pub payment: Participant,
+    pub state_machine: PvPstate,
+    pub network_transactions: Vec<NetworkTransaction>,
+}
+
+/// A leg held by the escrow, in the order it was funded.
+#[derive(Debug, PartialEq)]
+pub enum FundedLeg {
+    Delivery,
+    /// `applied` is set once `transaction_apply` has booked the payment.
+    Payment { applied: bool },
+}
+
+impl Asset {
+    pub fn new(standard: AssetStandard, contract_address: &str, token_id: U256, amount: Option<U256>) -> Result<Asset, Error> {
+        let amount = match (standard, amount) {
+            (AssetStandard::Erc721, None) => U256::from(1),
+            (AssetStandard::Erc721, Some(a)) if a == U256::from(1) => a,
+            (AssetStandard::Erc721, Some(a)) => return Err(Error::InvalidRequest(format!("an ERC-721 asset cannot have an amount of {}", a))),
+            (AssetStandard::Erc1155, Some(a)) if a > U256::ZERO => a,
+            (AssetStandard::Erc1155, _) => return Err(Error::InvalidRequest(String::from("an ERC-1155 asset needs a positive amount"))),
+        };
+        Ok(Asset {
+            standard,
+            contract_address: contract_address.to_string(),
+            token_id,
+            amount,
+        })
+    }
+
+    /// Input of the `safeTransferFrom` call moving the asset from `from` to `to`.
+    pub fn transfer_input(&self, from: Address, to: Address) -> Vec<u8> {
+        match self.standard {
+            AssetStandard::Erc721 => erc721::safeTransferFromCall::new((from, to, self.token_id)).abi_encode(),
+            AssetStandard::Erc1155 => erc1155::safeTransferFromCall::new((from, to, self.token_id, self.amount, Default::default())).abi_encode(),
+        }
+    }
+
+    /// Units of the asset held by `holder`, read with `ownerOf` or `balanceOf`.
+    pub fn balance_of(&self, nm: &Networks, network_name: &str, holder: Address) -> Result<U256, Error> {
+        let contract = Address::from_str(&self.contract_address)?;
+        match self.standard {
+            AssetStandard::Erc721 => {
+                let data = eth_call(nm, network_name, contract, &erc721::ownerOfCall::new((self.token_id,)).abi_encode())?;
+                let owner = erc721::ownerOfCall::abi_decode_returns(&data, true)
+                    .map_err(|e| Error::Rpc(format!("failed to decode ownerOf: {}", e)))?._0;
+                Ok(if owner == holder { U256::from(1) } else { U256::ZERO })
+            },
+            AssetStandard::Erc1155 => {
+                let data = eth_call(nm, network_name, contract, &erc1155::balanceOfCall::new((holder, self.token_id)).abi_encode())?;
+                let balance = erc1155::balanceOfCall::abi_decode_returns(&data, true)
+                    .map_err(|e| Error::Rpc(format!("failed to decode balanceOf: {}", e)))?._0;
+                Ok(balance)
+            }
+        }
+    }
+
+    /// Checks that `holder` has custody of the asset.
+    pub fn verify_custody(&self, nm: &Networks, network_name: &str, holder: &str) -> Result<(), Error> {
+        let balance = self.balance_of(nm, network_name, Address::from_str(holder)?)?;
+        if balance < self.amount {
+            return Err(Error::InvalidState(format!("{} holds {} of token {} of {}, {} expected", holder, balance, self.token_id, self.contract_address, self.amount)));
+        }
+        Ok(())
+    }
+}
+
+fn eth_call(nm: &Networks, network_name: &str, to: Address, input: &[u8]) -> Result<Vec<u8>, Error> {
+    let call = format!("{{\"to\":\"{}\",\"input\":\"{}\"}}", to, hex::encode_prefixed(input));
+    let result: String = nm.send(network_name, "eth_call", &[&call, "\"latest\""])?;
+    Ok(hex::decode(result)?)
+}
+
+impl DeliveryVsPayment {
+    /// Returns the payment transfer that the network transaction recorded in `state` must carry.
+    pub fn expected_payment(&self, state: &PvPstate, escrow_address: &str) -> Option<ExpectedTransfer> {
+        let (from, to) = match state {
+            PvPstate::AwaitingDestinationReceive => (self.payment.address.as_str(), escrow_address),
+            PvPstate::AwaitingSourceSend => (escrow_address, self.delivery.address.as_str()),
+            _ => return None
+        };
+        Some(ExpectedTransfer {
+            network_name: self.payment.network_name.clone(),
+            from: from.to_string(),
+            to: to.to_string(),
+            amount: self.payment.amount,
+            token_address: self.payment.token_address.clone(),
+        })
+    }
+
+    /// Returns the legs currently held by the escrow, delivery first.
+    ///
+    /// A leg that has only been committed is checked against the chain, since it may already be in escrow.
+    /// Fails while the chain cannot tell whether a committed leg landed.
+    pub fn funded_legs(&self, nm: &Networks, escrow_address: &str) -> Result<Vec<FundedLeg>, Error> {
+        let mut funded = Vec::new();
+        match self.state_machine {
+            PvPstate::AwaitingSourceReceiveFinalized if self.is_delivery_received(nm, escrow_address)? => {
+                funded.push(FundedLeg::Delivery);
+            },
+            PvPstate::AwaitingDestinationReceive => {
+                funded.push(FundedLeg::Delivery);
+            },
+            PvPstate::AwaitingDestinationReceiveFinalized => {
+                funded.push(FundedLeg::Delivery);
+                if self.is_payment_received(nm, escrow_address)? {
+                    funded.push(FundedLeg::Payment { applied: false });
+                }
+            },
+            PvPstate::AwaitingDestinationSend => {
+                funded.push(FundedLeg::Delivery);
+                funded.push(FundedLeg::Payment { applied: true });
+            },
+            _ => {}
+        }
+        Ok(funded)
+    }
+
+    /// Whether a committed delivery is final and left the asset with the escrow.
+    fn is_delivery_received(&self, nm: &Networks, escrow_address: &str) -> Result<bool, Error> {
+        for nt in self.network_transactions.iter().filter(|nt| nt.state == PvPstate::AwaitingSourceReceive) {
+            match transaction::verify_receipt(nm, &self.delivery.network_name, &self.delivery.address, &nt.tx_hash) {
+                Ok(_) => (),
+                Err(Error::InvalidRequest(_)) => continue,
+                Err(e) => return Err(e.context(format!("failed to check whether {} funded the escrow", nt.tx_hash)))
+            }
+            return match self.delivery.asset.verify_custody(nm, &self.delivery.network_name, escrow_address) {
+                Ok(()) => Ok(true),
+                Err(Error::InvalidState(_)) => Ok(false),
+                Err(e) => Err(e.context("failed to check the custody of the asset"))
+            };
+        }
+        Ok(false)
+    }
+
+    /// Whether a committed payment is final and carried the payment to the escrow.
+    fn is_payment_received(&self, nm: &Networks, escrow_address: &str) -> Result<bool, Error> {
+        let expected = match self.expected_payment(&PvPstate::AwaitingDestinationReceive, escrow_address) {
+            Some(e) => e,
+            None => return Ok(false)
+        };
+        for nt in self.network_transactions.iter().filter(|nt| nt.state == PvPstate::AwaitingDestinationReceive) {
+            if transaction::is_transfer_received(nm, &expected, &nt.tx_hash)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether the escrow has started delivering, after which the DvP can no longer be cancelled.
+    pub fn is_payout_started(&self) -> bool {
+        matches!(self.state_machine,
+            PvPstate::AwaitingDestinationSendFinalized |
+            PvPstate::AwaitingSourceSend |
+            PvPstate::AwaitingSourceSendFinalized |
+            PvPstate::Complete)
+    }
+
+    fn record(&mut self, state: PvPstate, network_name: &str, tx_hash: &str) {
+        self.network_transactions.push(NetworkTransaction {
+            state,
+            network_name: network_name.to_string(),
+            tx_hash: tx_hash.to_string(),
+        });
+    }
+}
+
+fn dvp_of(tx: &Transaction) -> Result<DeliveryVsPayment, Error> {
+    match &tx.delivery_vs_payment {
+        Some(d) => Ok(d.clone()),
+        None => Err(Error::InvalidState(String::from("transaction is not a delivery versus payment")))
+    }
+}
+
+fn required_tx_hash(tx_hash: Option<&str>) -> Result<String, Error> {
+    match tx_hash {
+        Some(h) => Ok(h.to_string()),
+        None => Err(Error::InvalidRequest(String::from("tx_hash is required to commit a deposit")))
+    }
+}
+
+/// Records the deposit `tx_hash` of the seller or the buyer, or has the escrow deliver the asset
+/// or pay the seller, depending on the state of the DvP. Returns the transaction hash.
+pub fn commit(tx: &mut Transaction, nm: &Networks, participant: &User, tx_hash: Option<&str>) -> Result<String, Error> {
+    let mut dvp = dvp_of(tx)?;
+    let tx_hash = match dvp.state_machine {
+        PvPstate::AwaitingSourceReceive => {
+            if !participant.owns_wallet(&dvp.delivery.address) {
+                return Err(Error::Unauthorized(format!("seller address '{}' not found in participant wallets", dvp.delivery.address)));
+            }
+            let tx_hash = required_tx_hash(tx_hash)?;
+            dvp.record(PvPstate::AwaitingSourceReceive, &dvp.delivery.network_name.clone(), &tx_hash);
+            dvp.state_machine = PvPstate::AwaitingSourceReceiveFinalized;
+            tx_hash
+        },
+        PvPstate::AwaitingDestinationReceive => {
+            if !participant.owns_wallet(&dvp.payment.address) {
+                return Err(Error::Unauthorized(format!("buyer address '{}' not found in participant wallets", dvp.payment.address)));
+            }
+            let tx_hash = required_tx_hash(tx_hash)?;
+            dvp.record(PvPstate::AwaitingDestinationReceive, &dvp.payment.network_name.clone(), &tx_hash);
+            dvp.state_machine = PvPstate::AwaitingDestinationReceiveFinalized;
+            tx_hash
+        },
+        PvPstate::AwaitingDestinationSend => {
+            if !participant.owns_wallet(&tx.escrow_address) {
+                return Err(Error::Unauthorized(format!("escrow address '{}' not found in orchestrator wallets", tx.escrow_address)));
+            }
+            let mut escrow_wallet = Wallet::load(&tx.escrow_address)?;
+            let escrow_tx = escrow_wallet.build_asset_transfer(nm, &dvp.delivery.network_name, &dvp.delivery.asset, Address::from_str(&dvp.payment.address)?)?;
+            let tx_hash = escrow_wallet.sign_and_send(nm, &dvp.delivery.network_name, escrow_tx, false, None)
+                .map_err(|e| transaction::escrow_gas_hint(e).context("failed to deliver asset"))?;
+            dvp.record(PvPstate::AwaitingDestinationSend, &dvp.delivery.network_name.clone(), &tx_hash);
+            dvp.state_machine = PvPstate::AwaitingDestinationSendFinalized;
+            tx_hash
+        },
+        PvPstate::AwaitingSourceSend => {
+            if !participant.owns_wallet(&tx.escrow_address) {
+                return Err(Error::Unauthorized(format!("escrow address '{}' not found in orchestrator wallets", tx.escrow_address)));
+            }
+            let payout = match dvp.expected_payment(&PvPstate::AwaitingSourceSend, &tx.escrow_address) {
+                Some(p) => p,
+                None => return Err(Error::InvalidState(String::from("no payment is owed to the seller")))
+            };
+            let tx_hash = tx.send_from_escrow(nm, &payout).map_err(|e| e.context("failed to send payout"))?;
+            dvp.record(PvPstate::AwaitingSourceSend, &dvp.payment.network_name.clone(), &tx_hash);
+            dvp.state_machine = PvPstate::AwaitingSourceSendFinalized;
+            tx_hash
+        },
+        PvPstate::Complete => return Ok(String::from("transaction is already complete")),
+        _ => return Err(Error::InvalidState(String::from("transaction is not in the correct state to process payment")))
+    };
+    tx.delivery_vs_payment = Some(dvp);
+    tx.save()?;
+    Ok(tx_hash)
+}
+
+/// Checks the deposit, delivery or payout `tx_hash` against the chain and moves the DvP forward.
+pub fn apply(tx: &mut Transaction, nm: &Networks, tx_hash: &str) -> Result<String, Error> {
+    let mut dvp = dvp_of(tx)?;
+    let committed_state = match dvp.state_machine {
+        PvPstate::AwaitingSourceReceiveFinalized => PvPstate::AwaitingSourceReceive,
+        PvPstate::AwaitingDestinationReceiveFinalized => PvPstate::AwaitingDestinationReceive,
+        PvPstate::AwaitingDestinationSendFinalized => PvPstate::AwaitingDestinationSend,
+        PvPstate::AwaitingSourceSendFinalized => PvPstate::AwaitingSourceSend,
+        PvPstate::Complete => return Ok(String::from("transaction is already complete")),
+        _ => return Err(Error::InvalidState(String::from("transaction is not in the correct state to process payment")))
+    };
+    match dvp.network_transactions.iter().find(|nt| nt.tx_hash == tx_hash) {
+        Some(nt) if nt.state == committed_state => (),
+        Some(_) => return Err(Error::InvalidState(format!("tx_hash '{}' is not in the correct state to process payment", tx_hash))),
+        None => return Err(Error::NotFound(format!("tx_hash '{}' not found in network transactions", tx_hash)))
+    }
+    let verify_failed = |e: Error| e.context(format!("failed to verify tx_hash '{}'", tx_hash));
+
+    let delivery_network = dvp.delivery.network_name.as_str();
+    let payment_network = dvp.payment.network_name.as_str();
+    dvp.state_machine = match committed_state {
+        PvPstate::AwaitingSourceReceive => {
+            transaction::verify_receipt(nm, delivery_network, &dvp.delivery.address, tx_hash).map_err(verify_failed)?;
+            dvp.delivery.asset.verify_custody(nm, delivery_network, &tx.escrow_address).map_err(verify_failed)?;
+            PvPstate::AwaitingDestinationReceive
+        },
+        PvPstate::AwaitingDestinationReceive => {
+            let expected = dvp.expected_payment(&committed_state, &tx.escrow_address).ok_or_else(|| Error::Internal(String::from("no payment expected")))?;
+            transaction::verify_transfer(nm, &expected, tx_hash).map_err(verify_failed)?;
+
+            let mut escrow_wallet = Wallet::load(&tx.escrow_address)?;
+            escrow_wallet.mint(payment_network, &dvp.payment.amount)?;
+            // native funds are held by the escrow until its payout releases them
+            if dvp.payment.token_address.is_none() {
+                escrow_wallet.hold(payment_network, dvp.payment.amount)?;
+            }
+            Wallet::load(&dvp.payment.address)?.burn(payment_network, &dvp.payment.amount)?;
+            PvPstate::AwaitingDestinationSend
+        },
+        PvPstate::AwaitingDestinationSend => {
+            transaction::verify_receipt(nm, delivery_network, &tx.escrow_address, tx_hash).map_err(verify_failed)?;
+            dvp.delivery.asset.verify_custody(nm, delivery_network, &dvp.payment.address).map_err(verify_failed)?;
+            PvPstate::AwaitingSourceSend
+        },
+        _ => {
+            let expected = dvp.expected_payment(&committed_state, &tx.escrow_address).ok_or_else(|| Error::Internal(String::from("no payout expected")))?;
+            transaction::verify_transfer(nm, &expected, tx_hash).map_err(verify_failed)?;
+
+            Wallet::load(&tx.escrow_address)?.burn(payment_network, &dvp.payment.amount)?;
+            Wallet::load(&dvp.delivery.address)?.mint(payment_network, &dvp.payment.amount)?;
+            PvPstate::Complete
+        }
+    };
+
+    tx.delivery_vs_payment = Some(dvp);
+    tx.save()?;
+    Ok(format!("transaction '{}' finalized", tx.id))
+}
+
+/// Returns the asset to the seller and the payment to the buyer, for the legs held by the escrow.
+///
+/// Refunds already sent by a previous attempt are recorded in order and skipped.
+pub fn cancel(tx: &mut Transaction, nm: &Networks) -> Result<(), Error> {
+    let mut dvp = dvp_of(tx)?;
+    if dvp.state_machine == PvPstate::Cancelled {
+        return Err(Error::InvalidState(String::from("transaction is already cancelled")));
+    }
+    if dvp.is_payout_started() {
+        return Err(Error::InvalidState(String::from("transaction payout has started and cannot be cancelled")));
+    }
+
+    let already_refunded = dvp.network_transactions.iter().filter(|nt| nt.state == PvPstate::Cancelled).count();
+    for leg in dvp.funded_legs(nm, &tx.escrow_address).map_err(|e| e.context("cannot cancel before every committed leg is settled on chain"))?.into_iter().skip(already_refunded) {
+        let (network_name, refunded) = match leg {
+            FundedLeg::Delivery => {
+                let network_name = dvp.delivery.network_name.clone();
+                let refunded = Wallet::load(&tx.escrow_address).and_then(|mut escrow_wallet| {
+                    let escrow_tx = escrow_wallet.build_asset_transfer(nm, &network_name, &dvp.delivery.asset, Address::from_str(&dvp.delivery.address)?)?;
+                    escrow_wallet.sign_and_send(nm, &network_name, escrow_tx, false, None).map_err(transaction::escrow_gas_hint)
+                });
+                (network_name, refunded)
+            },
+            FundedLeg::Payment { .. } => (dvp.payment.network_name.clone(), tx.send_refund(nm, &dvp.payment))
+        };
+        let refund_hash = match refunded {
+            Ok(h) => h,
+            Err(e) => {
+                tx.delivery_vs_payment = Some(dvp);
+                tx.save()?;
+                return Err(e.context(format!("failed to refund {:?} leg on {}", leg, network_name)));
+            }
+        };
+        dvp.record(PvPstate::Cancelled, &network_name, &refund_hash);
+
+        // The refund is already broadcast, bookkeeping failures must not abort the loop.
+        if leg == (FundedLeg::Payment { applied: true }) {
+            if let Ok(mut escrow_wallet) = Wallet::load(&tx.escrow_address) {
+                let _ = escrow_wallet.burn(&network_name, &dvp.payment.amount);
+            }
+            if let Ok(mut buyer_wallet) = Wallet::load(&dvp.payment.address) {
+                let _ = buyer_wallet.mint(&network_name, &dvp.payment.amount);
+            }
+        }
+    }
+
+    dvp.state_machine = PvPstate::Cancelled;
+    tx.delivery_vs_payment = Some(dvp);
+    tx.save()
+}
@@ -0,0 +1,161 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Errors reported by the app. Each kind maps to the stable `code` of the JSON error envelope.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The command is not valid json, or a field is missing or cannot be parsed.
+    InvalidRequest(String),
+    /// The sender is not registered or is not allowed to perform the operation.
+    Unauthorized(String),
+    /// A wallet, network, transaction, user or other record does not exist.
+    NotFound(String),
+    /// The record to create already exists.
+    AlreadyExists(String),
+    /// The operation is not allowed in the current state of the record.
+    InvalidState(String),
+    /// The spendable or locked balance does not cover the operation.
+    InsufficientFunds(String),
+    /// The JSON-RPC node could not be reached or returned an error.
+    Rpc(String),
+    /// Key handling, signing or signature recovery failed.
+    Crypto(String),
+    /// Reading from or writing to the ledger failed.
+    Ledger(String),
+    /// A record or a response could not be (de)serialized.
+    Serialization(String),
+    Internal(String),
+}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::InvalidRequest(_) => "invalid_request",
+            Error::Unauthorized(_) => "unauthorized",
+            Error::NotFound(_) => "not_found",
+            Error::AlreadyExists(_) => "already_exists",
+            Error::InvalidState(_) => "invalid_state",
+            Error::InsufficientFunds(_) => "insufficient_funds",
+            Error::Rpc(_) => "rpc_error",
+            Error::Crypto(_) => "crypto_error",
+            Error::Ledger(_) => "ledger_error",
+            Error::Serialization(_) => "serialization_error",
+            Error::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::InvalidRequest(m) | Error::Unauthorized(m) | Error::NotFound(m) | Error::AlreadyExists(m)
+            | Error::InvalidState(m) | Error::InsufficientFunds(m) | Error::Rpc(m) | Error::Crypto(m)
+            | Error::Ledger(m) | Error::Serialization(m) | Error::Internal(m) => m,
+        }
+    }
+
+    /// Prefixes the message with `context`, keeping the kind.
+    pub fn context(self, context: impl Display) -> Error {
+        let message = format!("{}: {}", context, self.message());
+        match self {
+            Error::InvalidRequest(_) => Error::InvalidRequest(message),
+            Error::Unauthorized(_) => Error::Unauthorized(message),
+            Error::NotFound(_) => Error::NotFound(message),
+            Error::AlreadyExists(_) => Error::AlreadyExists(message),
+            Error::InvalidState(_) => Error::InvalidState(message),
+            Error::InsufficientFunds(_) => Error::InsufficientFunds(message),
+            Error::Rpc(_) => Error::Rpc(message),
+            Error::Crypto(_) => Error::Crypto(message),
+            Error::Ledger(_) => Error::Ledger(message),
+            Error::Serialization(_) => Error::Serialization(message),
+            Error::Internal(_) => Error::Internal(message),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Boxed errors returned by the SDK keep their kind when they wrap an `Error`.
+impl From<Box<dyn std::error::Error>> for Error {
+    fn from(e: Box<dyn std::error::Error>) -> Self {
+        match e.downcast::<Error>() {
+            Ok(e) => *e,
+            Err(e) => Error::Internal(e.to_string())
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Serialization(e.to_string())
+    }
+}
+
+impl From<alloy_primitives::hex::FromHexError> for Error {
+    fn from(e: alloy_primitives::hex::FromHexError) -> Self {
+        Error::InvalidRequest(e.to_string())
+    }
+}
+
+impl From<alloy_primitives::ruint::ParseError> for Error {
+    fn from(e: alloy_primitives::ruint::ParseError) -> Self {
+        Error::InvalidRequest(e.to_string())
+    }
+}
+
+impl From<std::num::ParseIntError> for Error {
+    fn from(e: std::num::ParseIntError) -> Self {
+        Error::InvalidRequest(e.to_string())
+    }
+}
+
+impl From<alloy_signer::k256::elliptic_curve::Error> for Error {
+    fn from(e: alloy_signer::k256::elliptic_curve::Error) -> Self {
+        Error::Crypto(e.to_string())
+    }
+}
+
+impl From<alloy_signer::Error> for Error {
+    fn from(e: alloy_signer::Error) -> Self {
+        Error::Crypto(e.to_string())
+    }
+}
+
+impl From<alloy_signer_local::LocalSignerError> for Error {
+    fn from(e: alloy_signer_local::LocalSignerError) -> Self {
+        Error::Crypto(e.to_string())
+    }
+}
+
+impl From<alloy_primitives::SignatureError> for Error {
+    fn from(e: alloy_primitives::SignatureError) -> Self {
+        Error::Crypto(e.to_string())
+    }
+}
+
+impl From<alloy_dyn_abi::Error> for Error {
+    fn from(e: alloy_dyn_abi::Error) -> Self {
+        Error::InvalidRequest(e.to_string())
+    }
+}
+
+impl From<alloy_signer_local::coins_bip39::MnemonicError> for Error {
+    fn from(e: alloy_signer_local::coins_bip39::MnemonicError) -> Self {
+        Error::Crypto(e.to_string())
+    }
+}
+
+impl From<hmac::digest::InvalidLength> for Error {
+    fn from(e: hmac::digest::InvalidLength) -> Self {
+        Error::Crypto(e.to_string())
+    }
+}
+
+impl From<::http::Error> for Error {
+    fn from(e: ::http::Error) -> Self {
+        Error::InvalidRequest(e.to_string())
+    }
+}
@@ -1,63 +1,50 @@
-use alloy_primitives::{hex, Address, U256};
+use alloy_primitives::hex;
 use serde_json::Value;
+use crate::abi;
+use crate::error::Error;
+use crate::request;
+use crate::response;
+use crate::contract::Contract;
 use crate::klave_networks::networks::Networks;
 use crate::solidity::{balanceOfCall, burnCall, decimalsCall, mintCall, nameCall, ownerCall, symbolCall, totalSupplyCall};
 use alloy_sol_types::SolCall;
 
 pub fn eth_get_block_by_number(cmd: String){        
-    let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-        klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-        return
+    let req = match request::parse::<request::EthGetBlockByNumber>(&cmd) {
+        Ok(r) => r,
+        Err(e) => {
+            response::error(e);
+            return;
+        }
     };
 
     let network = match Networks::load() {
         Ok(nm) => nm,
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+            response::error(e.context("failed to load network manager"));                
             return
         }
     };
-    let network_name = match v["network_name"].as_str() {
-        Some(c) => c,
-        None => {
-            klave::notifier::send_string(&format!("ERROR: network_name not found"));
-            return;
-        }
-    };
+    let network_name = req.network_name.as_str();
 
-    let block_number = match v["block_number"].as_str() {
-        Some(bn) => format!("\"{}\"", bn),
-        None => {
-            klave::notifier::send_string(&format!("ERROR: 'block_number' field is required"));
-            return
-        }
-    };
+    let block_number = format!("\"{}\"", req.block_number);
 
-    let trace = match v["trace"].as_bool() {
-        Some(c) => c,
-        None => false
-    };
+    let trace = req.trace.unwrap_or(false);
 
     match trace {
         true => {
             match network.send::<alloy_rpc_types_eth::Block>(network_name, "trace_block", &[&block_number]) {
-                Ok(result) => klave::notifier::send_string(&match serde_json::to_string(&result) {
-                    Ok(s) => s,
-                    Err(e) => format!("ERROR: failed to serialize response: {}", e)
-                }),
+                Ok(result) => response::success(&result),
                 Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to send request: {}", e));
+                    response::error(e.context("failed to send request"));
                 }
             }
         },
         false => {
             match network.send::<alloy_rpc_types_eth::Block>(network_name, "eth_getBlockByNumber", &[&block_number, "false"]) {
-                Ok(result) => klave::notifier::send_string(&match serde_json::to_string(&result) {
-                    Ok(s) => s,
-                    Err(e) => format!("ERROR: failed to serialize response: {}", e)
-                }),
+                Ok(result) => response::success(&result),
                 Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to send request: {}", e));
+                    response::error(e.context("failed to send request"));
                 }
             }
         }    
@@ -65,91 +52,81 @@ pub fn eth_get_block_by_number(cmd: String){
 }
 
 pub fn eth_block_number(cmd: String){    
-    let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-        klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-        return
+    let req = match request::parse::<request::EthBlockNumber>(&cmd) {
+        Ok(r) => r,
+        Err(e) => {
+            response::error(e);
+            return;
+        }
     };
 
     let network = match Networks::load() {
         Ok(nm) => nm,
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+            response::error(e.context("failed to load network manager"));                
             return
         }
     };
-    let network_name = match v["network_name"].as_str() {
-        Some(c) => c,
-        None => {
-            klave::notifier::send_string(&format!("ERROR: network_name not found"));
-            return;
-        }
-    };
-    match network.send::<String>(network_name, &"eth_blockNumber", &[]) {
-        Ok(result) => klave::notifier::send_string(&format!("{}", result)),
+    let network_name = req.network_name.as_str();
+    match network.send::<String>(network_name, "eth_blockNumber", &[]) {
+        Ok(result) => response::success(result),
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to send request: {}", e));
+            response::error(e.context("failed to send request"));
         }
     }
 }
 
 pub fn eth_gas_price(cmd: String){        
-    let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-        klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-        return
+    let req = match request::parse::<request::EthGasPrice>(&cmd) {
+        Ok(r) => r,
+        Err(e) => {
+            response::error(e);
+            return;
+        }
     };
 
     let network = match Networks::load() {
         Ok(nm) => nm,
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+            response::error(e.context("failed to load network manager"));                
             return
         }
     };
 
-    let network_name = match v["network_name"].as_str() {
-        Some(c) => c,
-        None => {
-            klave::notifier::send_string(&format!("ERROR: network_name not found"));
-            return;
-        }
-    };
-    match network.send::<String>(network_name, &"eth_gasPrice", &[]) {
-        Ok(result) => klave::notifier::send_string(&format!("{}", result)),
+    let network_name = req.network_name.as_str();
+    match network.send::<String>(network_name, "eth_gasPrice", &[]) {
+        Ok(result) => response::success(result),
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to send request: {}", e));
+            response::error(e.context("failed to send request"));
         }
     }
 }
 
 pub fn eth_estimate_gas(cmd: String){        
-    let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-        klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-        return
+    let req = match request::parse::<request::EthEstimateGas>(&cmd) {
+        Ok(r) => r,
+        Err(e) => {
+            response::error(e);
+            return;
+        }
     };
 
     let network = match Networks::load() {
         Ok(nm) => nm,
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+            response::error(e.context("failed to load network manager"));                
             return
         }
     };
 
-    let network_name = match v["network_name"].as_str() {
-        Some(c) => c,
-        None => {
-            klave::notifier::send_string(&format!("ERROR: network_name not found"));
-            return;
-        }
-    };
+    let network_name = req.network_name.as_str();
 
     let mut potential_tx: Vec<String> = Vec::new();
 
-    match v["to"].as_str() {
-        Some(t) => potential_tx.push(format!("\"to\":\"{}\"", t)),
-        None => {}
-    };
-    match v["input"].as_str() {
+    if let Some(t) = req.to {
+        potential_tx.push(format!("\"to\":\"{}\"", t));
+    }
+    match req.input.as_deref() {
         Some(d) => {
             match d {
                 "name" => {
@@ -169,19 +146,13 @@ pub fn eth_estimate_gas(cmd: String){
                     potential_tx.push(format!("\"input\":\"0x{}\"", hex_encoded_call))
                 },
                 "balanceOf" => {
-                        let from_address = match v["from"].as_str() {
-                            Some(f) => match f.parse::<Address>() {
-                                Ok(a) => {
-                                    potential_tx.push(format!("\"from\":\"{}\"", a.to_string()));
-                                    a
-                                },
-                                Err(e) => {
-                                    klave::notifier::send_string(&format!("ERROR: failed to parse contract address: {}", e));
-                                    return;
-                                }            
+                        let from_address = match req.from {
+                            Some(a) => {
+                                potential_tx.push(format!("\"from\":\"{}\"", a));
+                                a
                             },
                             None => {
-                                klave::notifier::send_string(&format!("ERROR: 'from' field is required"));            
+                                response::error(Error::InvalidRequest(String::from("'from' field is required")));
                                 return
                             }
                         };
@@ -190,34 +161,20 @@ pub fn eth_estimate_gas(cmd: String){
                         potential_tx.push(format!("\"input\":\"0x{}\"", hex_encoded_call))
                 },
                 "mint" => {
-                    let value = match v["value"].as_str() {
-                        Some(v) => {
-                            match U256::from_str_radix(v.trim_start_matches("0x"), 16) {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    klave::notifier::send_string(&format!("ERROR: failed to parse value: {}", e));
-                                    return;
-                                }
-                            }
-                        },
+                    let value = match req.value {
+                        Some(v) => v,
                         None => {
-                            klave::notifier::send_string(&format!("ERROR: value not found"));
+                            response::error(Error::InvalidRequest(String::from("value not found")));
                             return;
                         }
                     };
-                    let from_address = match v["from"].as_str() {
-                        Some(f) => match f.parse::<Address>() {
-                            Ok(a) => {
-                                potential_tx.push(format!("\"from\":\"{}\"", a.to_string()));
-                                a
-                            },
-                            Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to parse contract address: {}", e));
-                                return;
-                            }            
+                    let from_address = match req.from {
+                        Some(a) => {
+                            potential_tx.push(format!("\"from\":\"{}\"", a));
+                            a
                         },
                         None => {
-                            klave::notifier::send_string(&format!("ERROR: 'from' field is required"));            
+                            response::error(Error::InvalidRequest(String::from("'from' field is required")));
                             return
                         }
                     };
@@ -226,34 +183,20 @@ pub fn eth_estimate_gas(cmd: String){
                     potential_tx.push(format!("\"input\":\"0x{}\"", hex_encoded_call))
                 },
                 "burn" => {
-                    let value = match v["value"].as_str() {
-                        Some(v) => {
-                            match U256::from_str_radix(v.trim_start_matches("0x"), 16) {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    klave::notifier::send_string(&format!("ERROR: failed to parse value: {}", e));
-                                    return;
-                                }
-                            }
-                        },
+                    let value = match req.value {
+                        Some(v) => v,
                         None => {
-                            klave::notifier::send_string(&format!("ERROR: value not found"));
+                            response::error(Error::InvalidRequest(String::from("value not found")));
                             return;
                         }
                     };
-                    let from_address = match v["from"].as_str() {
-                        Some(f) => match f.parse::<Address>() {
-                            Ok(a) => {
-                                potential_tx.push(format!("\"from\":\"{}\"", a.to_string()));
-                                a
-                            },
-                            Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to parse contract address: {}", e));
-                                return;
-                            }            
+                    let from_address = match req.from {
+                        Some(a) => {
+                            potential_tx.push(format!("\"from\":\"{}\"", a));
+                            a
                         },
                         None => {
-                            klave::notifier::send_string(&format!("ERROR: 'from' field is required"));            
+                            response::error(Error::InvalidRequest(String::from("'from' field is required")));
                             return
                         }
                     };
@@ -268,84 +211,57 @@ pub fn eth_estimate_gas(cmd: String){
 
         },
         None => {
-            match v["value"].as_str() {
-                Some(c) => {
-                    match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                        Ok(v) => {
-                            potential_tx.push(format!("\"value\":\"{}\"", v));                            
-                        },
-                        Err(e) => {
-                            klave::notifier::send_string(&format!("ERROR: failed to parse value: {}", e));
-                            return;
-                        }
-                    }
-                },
-                None => {}
-            };        
-            match v["from"].as_str() {
-                Some(f) => match f.parse::<Address>() {
-                    Ok(a) => {
-                        potential_tx.push(format!("\"from\":\"{}\"", a.to_string()));
-                    },
-                    Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to parse contract address: {}", e));
-                        return;
-                    }            
-                },
-                None => {}
-            };
+            if let Some(v) = req.value {
+                potential_tx.push(format!("\"value\":\"{}\"", v));
+            }
+            if let Some(a) = req.from {
+                potential_tx.push(format!("\"from\":\"{}\"", a));
+            }
 
         }
     };
-    match v["gas_price"].as_str() {
-        Some(gp) => potential_tx.push(format!("\"gasPrice\":\"{}\"", gp)),
-        None => {}
-    };
-    match v["gas"].as_str() {
-        Some(g) => potential_tx.push(format!("\"gas\":\"{}\"", g)),
-        None => {}
-    };
-    match v["nonce"].as_str() {
-        Some(n) => potential_tx.push(format!("\"nonce\":\"{}\"", n)),
-        None => {}
-    };
+    if let Some(gp) = req.gas_price.as_deref() {
+        potential_tx.push(format!("\"gasPrice\":\"{}\"", gp));
+    }
+    if let Some(g) = req.gas.as_deref() {
+        potential_tx.push(format!("\"gas\":\"{}\"", g));
+    }
+    if let Some(n) = req.nonce.as_deref() {
+        potential_tx.push(format!("\"nonce\":\"{}\"", n));
+    }
 
-    match network.send::<String>(network_name, &"eth_estimateGas", &[&format!("{{{}}}", &potential_tx.join(","))]) {
-        Ok(result) => klave::notifier::send_string(&format!("{}", result)),
+    match network.send::<String>(network_name, "eth_estimateGas", &[&format!("{{{}}}", &potential_tx.join(","))]) {
+        Ok(result) => response::success(result),
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to send request: {}", e));
+            response::error(e.context("failed to send request"));
         }
     }
 }
 
 pub fn eth_call_contract(cmd: String){        
-    let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-        klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-        return
+    let req = match request::parse::<request::EthCallContract>(&cmd) {
+        Ok(r) => r,
+        Err(e) => {
+            response::error(e);
+            return;
+        }
     };
 
     let network = match Networks::load() {
         Ok(nm) => nm,
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+            response::error(e.context("failed to load network manager"));                
             return
         }
     };
 
-    let network_name = match v["network_name"].as_str() {
-        Some(c) => c,
-        None => {
-            klave::notifier::send_string(&format!("ERROR: network_name not found"));
-            return;
-        }
-    };
+    let network_name = req.network_name.as_str();
 
     let mut potential_tx: Vec<String> = Vec::new();
-    match v["to"].as_str() {
-        Some(t) => potential_tx.push(format!("\"to\":\"{}\"", t)),
-        None => {}
-    };
-    match v["input"].as_str() {
+    if let Some(t) = req.to {
+        potential_tx.push(format!("\"to\":\"{}\"", t));
+    }
+    match req.input.as_deref() {
         Some(d) => {
             match d {
                 "name" => {
@@ -369,19 +285,13 @@ pub fn eth_call_contract(cmd: String){
                     potential_tx.push(format!("\"input\":\"0x{}\"", hex_encoded_call))
                 },
                 "balanceOf" => {
-                    let from_address = match v["from"].as_str() {
-                        Some(f) => match f.parse::<Address>() {
-                            Ok(a) => {
-                                potential_tx.push(format!("\"from\":\"{}\"", a.to_string()));
-                                a
-                            },
-                            Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to parse contract address: {}", e));
-                                return;
-                            }            
+                    let from_address = match req.from {
+                        Some(a) => {
+                            potential_tx.push(format!("\"from\":\"{}\"", a));
+                            a
                         },
                         None => {
-                            klave::notifier::send_string(&format!("ERROR: 'from' field is required"));            
+                            response::error(Error::InvalidRequest(String::from("'from' field is required")));
                             return
                         }
                     };
@@ -390,34 +300,20 @@ pub fn eth_call_contract(cmd: String){
                     potential_tx.push(format!("\"input\":\"0x{}\"", hex_encoded_call))
                 },
                 "mint" => {
-                    let value = match v["value"].as_str() {
-                        Some(c) => {
-                            match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    klave::notifier::send_string(&format!("ERROR: failed to parse value: {}", e));
-                                    return;
-                                }
-                            }
-                        },
+                    let value = match req.value {
+                        Some(v) => v,
                         None => {
-                            klave::notifier::send_string(&format!("ERROR: value not found"));
+                            response::error(Error::InvalidRequest(String::from("value not found")));
                             return;
                         }
-                    };     
-                    let from_address = match v["from"].as_str() {
-                        Some(f) => match f.parse::<Address>() {
-                            Ok(a) => {
-                                potential_tx.push(format!("\"from\":\"{}\"", a.to_string()));
-                                a
-                            },
-                            Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to parse contract address: {}", e));
-                                return;
-                            }            
+                    };
+                    let from_address = match req.from {
+                        Some(a) => {
+                            potential_tx.push(format!("\"from\":\"{}\"", a));
+                            a
                         },
                         None => {
-                            klave::notifier::send_string(&format!("ERROR: 'from' field is required"));            
+                            response::error(Error::InvalidRequest(String::from("'from' field is required")));
                             return
                         }
                     };
@@ -426,34 +322,20 @@ pub fn eth_call_contract(cmd: String){
                     potential_tx.push(format!("\"input\":\"0x{}\"", hex_encoded_call))
                 },
                 "burn" => {
-                    let value = match v["value"].as_str() {
-                        Some(c) => {
-                            match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    klave::notifier::send_string(&format!("ERROR: failed to parse value: {}", e));
-                                    return;
-                                }
-                            }
-                        },
+                    let value = match req.value {
+                        Some(v) => v,
                         None => {
-                            klave::notifier::send_string(&format!("ERROR: value not found"));
+                            response::error(Error::InvalidRequest(String::from("value not found")));
                             return;
                         }
-                    };     
-                    let from_address = match v["from"].as_str() {
-                        Some(f) => match f.parse::<Address>() {
-                            Ok(a) => {
-                                potential_tx.push(format!("\"from\":\"{}\"", a.to_string()));
-                                a
-                            },
-                            Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to parse contract address: {}", e));
-                                return;
-                            }            
+                    };
+                    let from_address = match req.from {
+                        Some(a) => {
+                            potential_tx.push(format!("\"from\":\"{}\"", a));
+                            a
                         },
                         None => {
-                            klave::notifier::send_string(&format!("ERROR: 'from' field is required"));            
+                            response::error(Error::InvalidRequest(String::from("'from' field is required")));
                             return
                         }
                     };
@@ -462,266 +344,303 @@ pub fn eth_call_contract(cmd: String){
                     potential_tx.push(format!("\"input\":\"0x{}\"", hex_encoded_call))
                 },
                 _ => {
-                    klave::notifier::send_string(&format!("ERROR: unsupported function call"));
+                    response::error(Error::InvalidRequest(String::from("unsupported function call")));
                     return;
                 }
             }
         },
         None => {
-            match v["value"].as_str() {
-                Some(c) => {
-                    match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                        Ok(v) => {
-                            potential_tx.push(format!("\"value\":\"{}\"", v));                            
-                        },
-                        Err(e) => {
-                            klave::notifier::send_string(&format!("ERROR: failed to parse value: {}", e));
-                            return;
-                        }
-                    }
-                },
-                None => {}
-            }; 
-            match v["from"].as_str() {
-                Some(f) => match f.parse::<Address>() {
-                    Ok(a) => {
-                        potential_tx.push(format!("\"from\":\"{}\"", a.to_string()));
-                    },
-                    Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to parse contract address: {}", e));
-                        return;
-                    }            
-                },
-                None => {}
-            };
+            if let Some(v) = req.value {
+                potential_tx.push(format!("\"value\":\"{}\"", v));
+            }
+            if let Some(a) = req.from {
+                potential_tx.push(format!("\"from\":\"{}\"", a));
+            }
         }
     };
 
-    match v["gas_price"].as_str() {
-        Some(gp) => potential_tx.push(format!("\"gasPrice\":\"{}\"", gp)),
-        None => {}
-    };
-    match v["gas"].as_str() {
-        Some(g) => potential_tx.push(format!("\"gas\":\"{}\"", g)),
-        None => {}
-    };
-    match v["nonce"].as_str() {
-        Some(n) => potential_tx.push(format!("\"nonce\":\"{}\"", n)),
-        None => {}
-    };
-    match v["maxFeePerGas"].as_u64() {
-        Some(m) => potential_tx.push(format!("\"maxFeePerGas\":\"{}\"", m)),
-        None => {}
-    };
-    match v["maxPriorityFeePerGas"].as_u64() {
-        Some(m) => potential_tx.push(format!("\"maxPriorityFeePerGas\":\"{}\"", m)),
-        None => {}
-    };
+    if let Some(gp) = req.gas_price.as_deref() {
+        potential_tx.push(format!("\"gasPrice\":\"{}\"", gp));
+    }
+    if let Some(g) = req.gas.as_deref() {
+        potential_tx.push(format!("\"gas\":\"{}\"", g));
+    }
+    if let Some(n) = req.nonce.as_deref() {
+        potential_tx.push(format!("\"nonce\":\"{}\"", n));
+    }
+    if let Some(m) = req.max_fee_per_gas {
+        potential_tx.push(format!("\"maxFeePerGas\":\"{}\"", m));
+    }
+    if let Some(m) = req.max_priority_fee_per_gas {
+        potential_tx.push(format!("\"maxPriorityFeePerGas\":\"{}\"", m));
+    }
 
-    let trace = match v["trace"].as_bool() {
-        Some(c) => c,
-        None => false
-    };
+    let trace = req.trace.unwrap_or(false);
 
     match trace {
         true => {
-            match network.send::<String>(network_name, &"trace_call", &[&format!("{{{}}}", &potential_tx.join(",")), "[\"trace\", \"vmTrace\", \"stateDiff\"]", "\"latest\""]) {
-                Ok(result) => klave::notifier::send_string(&format!("{}", result)),
+            match network.send::<String>(network_name, "trace_call", &[&format!("{{{}}}", &potential_tx.join(",")), "[\"trace\", \"vmTrace\", \"stateDiff\"]", "\"latest\""]) {
+                Ok(result) => response::success(result),
                 Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to send request: {}", e));
+                    response::error(e.context("failed to send request"));
                 }
             }
         },
         false => {
-            match network.send::<String>(network_name, &"eth_call", &[&format!("{{{}}}", &potential_tx.join(",")), "\"latest\""]) {
-                Ok(result) => klave::notifier::send_string(&format!("{}", result)),
+            match network.send::<String>(network_name, "eth_call", &[&format!("{{{}}}", &potential_tx.join(",")), "\"latest\""]) {
+                Ok(result) => response::success(result),
                 Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to send request: {} - {:?}", e, potential_tx));
+                    response::error(Error::Rpc(format!("failed to send request: {} - {:?}", e, potential_tx)));
                 }
             }
         }
     }
 }
 
-pub fn eth_protocol_version(cmd: String){        
-    let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-        klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-        return
+pub fn eth_call_function(cmd: String){        
+    let req = match request::parse::<request::EthCallFunction>(&cmd) {
+        Ok(r) => r,
+        Err(e) => {
+            response::error(e);
+            return;
+        }
     };
 
     let network = match Networks::load() {
         Ok(nm) => nm,
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+            response::error(e.context("failed to load network manager"));                
             return
         }
     };
-    let network_name = match v["network_name"].as_str() {
-        Some(c) => c,
-        None => {
-            klave::notifier::send_string(&format!("ERROR: network_name not found"));
+
+    // A registered contract supplies the network, the address and the abi.
+    let contract = match &req.contract_name {
+        Some(name) => match Contract::load(name) {
+            Ok(c) => Some(c),
+            Err(e) => {
+                response::error(e.context(format!("failed to load contract '{}'", name)));
+                return;
+            }
+        },
+        None => None
+    };
+
+    let network_name = match (&req.network_name, &contract) {
+        (Some(c), _) => c.clone(),
+        (None, Some(c)) => c.network_name.clone(),
+        (None, None) => {
+            response::error(Error::InvalidRequest(String::from("network_name not found")));
+            return;
+        }
+    };
+    let to = match &contract {
+        Some(c) => match c.get_address() {
+            Ok(a) => a,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        },
+        None => match req.to {
+            Some(a) => a,
+            None => {
+                response::error(Error::InvalidRequest(String::from("'to' field is required")));
+                return;
+            }
+        }
+    };
+    let json_abi = match (req.abi, contract) {
+        (Some(a), _) => a,
+        (None, Some(c)) => c.abi,
+        (None, None) => {
+            response::error(Error::InvalidRequest(String::from("abi is required without a contract_name")));
+            return;
+        }
+    };
+    let function = match abi::find_function(&json_abi, &req.function) {
+        Ok(f) => f,
+        Err(e) => {
+            response::error(e);
             return;
         }
     };
-    match network.send::<String>(network_name, &"eth_protocolVersion", &[]) {
-        Ok(result) => klave::notifier::send_string(&format!("{}", result)),
+    let input = match abi::encode_call(function, req.args.as_ref().unwrap_or(&Value::Null)) {
+        Ok(i) => i,
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to send request: {}", e));
+            response::error(e.context("failed to encode call"));
+            return;
         }
+    };
+
+    let mut potential_tx: Vec<String> = Vec::new();
+    potential_tx.push(format!("\"to\":\"{}\"", to));
+    potential_tx.push(format!("\"input\":\"{}\"", hex::encode_prefixed(&input)));
+    if let Some(a) = req.from {
+        potential_tx.push(format!("\"from\":\"{}\"", a));
+    }
+    let block = match req.block.as_deref() {
+        Some(b) => format!("\"{}\"", b),
+        None => String::from("\"latest\"")
+    };
+
+    let result = match network.send::<String>(&network_name, "eth_call", &[&format!("{{{}}}", potential_tx.join(",")), &block]) {
+        Ok(r) => r,
+        Err(e) => {
+            response::error(e.context("failed to send request"));
+            return;
+        }
+    };
+    let data = match hex::decode(result.trim_start_matches("0x")) {
+        Ok(d) => d,
+        Err(e) => {
+            response::error(Error::from(e).context(format!("failed to decode result '{}'", result)));
+            return;
+        }
+    };
+    match abi::decode_output(function, &data) {
+        Ok(decoded) => response::success(decoded),
+        Err(e) => response::error(e.context(format!("failed to decode output '{}'", result)))
     }
 }
 
-pub fn eth_chain_id(cmd: String){     
-    let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-        klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-        return
+pub fn eth_protocol_version(cmd: String){        
+    let req = match request::parse::<request::EthProtocolVersion>(&cmd) {
+        Ok(r) => r,
+        Err(e) => {
+            response::error(e);
+            return;
+        }
     };
 
     let network = match Networks::load() {
         Ok(nm) => nm,
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+            response::error(e.context("failed to load network manager"));                
             return
         }
     };
-    let network_name = match v["network_name"].as_str() {
-        Some(c) => c,
-        None => {
-            klave::notifier::send_string(&format!("ERROR: network_name not found"));
+    let network_name = req.network_name.as_str();
+    match network.send::<String>(network_name, "eth_protocolVersion", &[]) {
+        Ok(result) => response::success(result),
+        Err(e) => {
+            response::error(e.context("failed to send request"));
+        }
+    }
+}
+
+pub fn eth_chain_id(cmd: String){     
+    let req = match request::parse::<request::EthChainId>(&cmd) {
+        Ok(r) => r,
+        Err(e) => {
+            response::error(e);
             return;
         }
     };
-    match network.send::<String>(network_name, &"eth_chainId", &[]) {
-        Ok(result) => klave::notifier::send_string(&format!("{}", result)),
+
+    let network = match Networks::load() {
+        Ok(nm) => nm,
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to send request: {}", e));
+            response::error(e.context("failed to load network manager"));                
+            return
+        }
+    };
+    let network_name = req.network_name.as_str();
+    match network.send::<String>(network_name, "eth_chainId", &[]) {
+        Ok(result) => response::success(result),
+        Err(e) => {
+            response::error(e.context("failed to send request"));
         }
     }
 }
 
 pub fn eth_get_transaction_by_hash(cmd: String){        
-    let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-        klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-        return
-    };
-
-    let network_name = match v["network_name"].as_str() {
-        Some(c) => c,
-        None => {
-            klave::notifier::send_string(&format!("ERROR: network_name not found"));
+    let req = match request::parse::<request::EthGetTransactionByHash>(&cmd) {
+        Ok(r) => r,
+        Err(e) => {
+            response::error(e);
             return;
         }
     };
-    let tx_hash = match v["tx_hash"].as_str() {
-        Some(t) => format!("\"{}\"",t),
-        None => {
-            klave::notifier::send_string(&format!("ERROR: 'tx_hash' field is required"));
-            return
-        }
-    };
+
+    let network_name = req.network_name.as_str();
+    let tx_hash = format!("\"{}\"", req.tx_hash);
     let network = match Networks::load() {
         Ok(nm) => nm,
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+            response::error(e.context("failed to load network manager"));                
             return
         }
     };
 
-    let trace = match v["trace"].as_bool() {
-        Some(c) => c,
-        None => false
-    };
+    let trace = req.trace.unwrap_or(false);
 
     match network.send::<alloy_rpc_types_eth::Transaction>(network_name, match trace {
-        true => &"trace_transaction",
-        false => &"eth_getTransactionByHash"
+        true => "trace_transaction",
+        false => "eth_getTransactionByHash"
     }, &[&tx_hash]) {
-        Ok(result) => klave::notifier::send_string(&match serde_json::to_string(&result) {
-            Ok(s) => s,
-            Err(e) => format!("ERROR: failed to serialize response: {}", e)
-        }),
+        Ok(result) => response::success(&result),
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to send request: {}", e));
+            response::error(e.context("failed to send request"));
         }
     }
 }
 
 pub fn eth_get_transaction_receipt(cmd: String){        
-    let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-        klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-        return
+    let req = match request::parse::<request::EthGetTransactionReceipt>(&cmd) {
+        Ok(r) => r,
+        Err(e) => {
+            response::error(e);
+            return;
+        }
     };
 
     let network = match Networks::load() {
         Ok(nm) => nm,
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+            response::error(e.context("failed to load network manager"));                
             return
         }
     };
 
-    let network_name = match v["network_name"].as_str() {
-        Some(c) => c,
-        None => {
-            klave::notifier::send_string(&format!("ERROR: network_name not found"));
-            return;
-        }
-    };
-    let tx_hash = match v["tx_hash"].as_str() {
-        Some(t) => format!("\"{}\"",t),
-        None => {
-            klave::notifier::send_string(&format!("ERROR: 'tx_hash' field is required"));
-            return
-        }
-    };
+    let network_name = req.network_name.as_str();
+    let tx_hash = format!("\"{}\"", req.tx_hash);
 
-    match network.send::<alloy_rpc_types_eth::TransactionReceipt>(network_name, &"eth_getTransactionReceipt", &[&tx_hash]) {
-        Ok(result) => klave::notifier::send_string(&match serde_json::to_string(&result) {
-            Ok(s) => s,
-            Err(e) => format!("ERROR: failed to serialize response: {}", e)
-        }),
+    match network.send::<alloy_rpc_types_eth::TransactionReceipt>(network_name, "eth_getTransactionReceipt", &[&tx_hash]) {
+        Ok(result) => response::success(&result),
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to send request: {}", e));
+            response::error(e.context("failed to send request"));
         }
     }
 }
 
 pub fn eth_get_transaction_count(cmd: String){        
-    let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-        klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-        return
-    };
-
-    let network_name = match v["network_name"].as_str() {
-        Some(c) => c,
-        None => {
-            klave::notifier::send_string(&format!("ERROR: network_name not found"));
+    let req = match request::parse::<request::EthGetTransactionCount>(&cmd) {
+        Ok(r) => r,
+        Err(e) => {
+            response::error(e);
             return;
         }
     };
-    let address = match v["address"].as_str() {
-        Some(a) => format!("\"{}\"",a),
-        None => {
-            klave::notifier::send_string(&format!("ERROR: 'address' field is required"));
-            return
-        }
-    };
-    let block = match v["block"].as_str() {
+
+    let network_name = req.network_name.as_str();
+    let address = format!("\"{}\"", req.address);
+    let block = match req.block.as_deref() {
         Some(b) => format!("\"{}\"",b),
-        None => format!("\"latest\"")
+        None => "\"latest\"".to_string()
     };
     let network = match Networks::load() {
         Ok(nm) => nm,
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+            response::error(e.context("failed to load network manager"));                
             return
         }
     };
 
-    match network.send::<String>(network_name, &"eth_getTransactionCount", &[&address, &block]) {
-        Ok(result) => klave::notifier::send_string(&format!("{}", result)),
+    match network.send::<String>(network_name, "eth_getTransactionCount", &[&address, &block]) {
+        Ok(result) => response::success(result),
         Err(e) => {
-            klave::notifier::send_string(&format!("ERROR: failed to send request: {}", e));
+            response::error(e.context("failed to send request"));
         }
     }
 }
\ No newline at end of file
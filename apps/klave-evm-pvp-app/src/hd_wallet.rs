@@ -0,0 +1,103 @@
+use alloy_signer::k256::{PublicKey, SecretKey};
+use alloy_signer_local::{coins_bip39::{English, Entropy, Mnemonic}, MnemonicBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::to_string;
+use klave;
+use crate::error::Error;
+
+pub(crate) const HD_WALLET_TABLE: &str = "hdWalletTable";
+const ROOT_KEY: &str = "ROOT";
+/// BIP-44 path of Ethereum accounts, the address index is appended.
+pub(crate) const DERIVATION_PATH_PREFIX: &str = "m/44'/60'/0'/0/";
+/// 32 bytes of entropy give a 24 words mnemonic.
+const ENTROPY_SIZE: i32 = 32;
+
+/// Root seed of the enclave, from which wallets and escrows are derived at `m/44'/60'/0'/0/i`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HdRoot {
+    mnemonic: String,
+    next_index: u32,
+}
+
+impl HdRoot {
+    /// Creates a root from fresh enclave randomness.
+    pub fn generate() -> Result<HdRoot, Error> {
+        let bytes = klave::crypto::random::get_random_bytes(ENTROPY_SIZE)?;
+        let mnemonic = Mnemonic::<English>::new_from_entropy(Entropy::from_slice(&bytes)?);
+        Ok(HdRoot {
+            mnemonic: mnemonic.to_phrase(),
+            next_index: 0,
+        })
+    }
+
+    /// Creates a root from an existing BIP-39 mnemonic. `next_index` lets a restored root
+    /// skip indexes already in use.
+    pub fn import(phrase: &str, next_index: u32) -> Result<HdRoot, Error> {
+        let phrase = phrase.split_whitespace().collect::<Vec<&str>>().join(" ");
+        Mnemonic::<English>::new_from_phrase(&phrase)?;
+        Ok(HdRoot {
+            mnemonic: phrase,
+            next_index,
+        })
+    }
+
+    pub fn load() -> Result<HdRoot, Error> {
+        match klave::ledger::get_table(HD_WALLET_TABLE).get(ROOT_KEY) {
+            Ok(v) => {
+                let root: HdRoot = match serde_json::from_slice(&v) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        return Err(e.into());
+                    }
+                };
+                Ok(root)
+            },
+            Err(_) => Err(Error::NotFound(String::from("hd root not found, create or import one first")))
+        }
+    }
+
+    pub fn exists() -> bool {
+        HdRoot::load().is_ok()
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let serialized_root = match to_string(&self) {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(e.into());
+            }
+        };
+        match klave::ledger::get_table(HD_WALLET_TABLE).set(ROOT_KEY, serialized_root.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::Ledger(format!("failed to save hd root: {}", e)))
+        }
+    }
+
+    pub fn get_mnemonic(&self) -> &str {
+        &self.mnemonic
+    }
+
+    pub fn get_next_index(&self) -> u32 {
+        self.next_index
+    }
+
+    /// Derives the keypair at `m/44'/60'/0'/0/index`.
+    pub fn derive(&self, index: u32) -> Result<(SecretKey, PublicKey), Error> {
+        let signer = MnemonicBuilder::<English>::default()
+            .phrase(self.mnemonic.as_str())
+            .index(index)?
+            .build()?;
+        let secret_key = SecretKey::from_slice(signer.to_bytes().as_slice())?;
+        let public_key = secret_key.public_key();
+        Ok((secret_key, public_key))
+    }
+
+    /// Derives the keypair at the next unused index and persists the incremented index.
+    pub fn derive_next(&mut self) -> Result<(SecretKey, PublicKey, u32), Error> {
+        let index = self.next_index;
+        let (secret_key, public_key) = self.derive(index)?;
+        self.next_index += 1;
+        self.save()?;
+        Ok((secret_key, public_key, index))
+    }
+}
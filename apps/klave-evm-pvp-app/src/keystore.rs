@@ -0,0 +1,187 @@
+use aes::cipher::{KeyIvInit, StreamCipher};
+use alloy_primitives::{hex, keccak256};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use klave;
+use crate::error::Error;
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+const KEYSTORE_VERSION: u8 = 3;
+const CIPHER: &str = "aes-128-ctr";
+const DKLEN: u32 = 32;
+/// Largest scrypt cost the enclave memory allows (128 * r * n = 8 MiB). The geth "standard" 2^18 needs 256 MiB and does not fit.
+const MAX_SCRYPT_N: u32 = 1 << 13;
+/// Default scrypt cost, the largest that fits in the enclave memory.
+pub(crate) const DEFAULT_SCRYPT_N: u32 = MAX_SCRYPT_N;
+const DEFAULT_SCRYPT_R: u32 = 8;
+const DEFAULT_SCRYPT_P: u32 = 1;
+pub(crate) const DEFAULT_PBKDF2_C: u32 = 262_144;
+/// Further bounds on the KDF parameters of imported keystores, so that a file cannot exhaust the enclave memory or time.
+const MAX_SCRYPT_R: u32 = 8;
+const MAX_SCRYPT_P: u32 = 1;
+const MAX_PBKDF2_C: u32 = 1_000_000;
+
+/// Web3 Secret Storage v3 keystore.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Keystore {
+    pub version: u8,
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(alias = "Crypto")]
+    pub crypto: KeystoreCrypto,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeystoreCrypto {
+    pub cipher: String,
+    pub cipherparams: CipherParams,
+    pub ciphertext: String,
+    pub kdf: String,
+    pub kdfparams: KdfParams,
+    pub mac: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CipherParams {
+    pub iv: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum KdfParams {
+    Scrypt {
+        dklen: u32,
+        n: u32,
+        p: u32,
+        r: u32,
+        salt: String,
+    },
+    Pbkdf2 {
+        c: u32,
+        dklen: u32,
+        prf: String,
+        salt: String,
+    },
+}
+
+impl KdfParams {
+    pub fn scrypt(n: u32, salt: &[u8]) -> KdfParams {
+        KdfParams::Scrypt { dklen: DKLEN, n, p: DEFAULT_SCRYPT_P, r: DEFAULT_SCRYPT_R, salt: hex::encode(salt) }
+    }
+
+    pub fn pbkdf2(c: u32, salt: &[u8]) -> KdfParams {
+        KdfParams::Pbkdf2 { c, dklen: DKLEN, prf: String::from("hmac-sha256"), salt: hex::encode(salt) }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            KdfParams::Scrypt { .. } => "scrypt",
+            KdfParams::Pbkdf2 { .. } => "pbkdf2",
+        }
+    }
+
+    fn derive_key(&self, password: &str) -> Result<Vec<u8>, Error> {
+        match self {
+            KdfParams::Scrypt { dklen, n, p, r, salt } => {
+                if *dklen != DKLEN {
+                    return Err(Error::InvalidRequest(format!("unsupported scrypt dklen {}, expected {}", dklen, DKLEN)));
+                }
+                if !n.is_power_of_two() {
+                    return Err(Error::InvalidRequest(format!("scrypt n must be a power of two, got {}", n)));
+                }
+                if *n > MAX_SCRYPT_N || *r > MAX_SCRYPT_R || *p > MAX_SCRYPT_P {
+                    return Err(Error::InvalidRequest(format!("scrypt params n={}, r={}, p={} exceed n={}, r={}, p={}", n, r, p, MAX_SCRYPT_N, MAX_SCRYPT_R, MAX_SCRYPT_P)));
+                }
+                let params = scrypt::Params::new(n.trailing_zeros() as u8, *r, *p, *dklen as usize)
+                    .map_err(|e| Error::InvalidRequest(format!("invalid scrypt params: {}", e)))?;
+                let mut key = vec![0u8; *dklen as usize];
+                scrypt::scrypt(password.as_bytes(), &hex::decode(salt)?, &params, &mut key)
+                    .map_err(|e| Error::InvalidRequest(format!("scrypt failed: {}", e)))?;
+                Ok(key)
+            },
+            KdfParams::Pbkdf2 { c, dklen, prf, salt } => {
+                if prf != "hmac-sha256" {
+                    return Err(Error::InvalidRequest(format!("unsupported pbkdf2 prf '{}'", prf)));
+                }
+                if *dklen != DKLEN {
+                    return Err(Error::InvalidRequest(format!("unsupported pbkdf2 dklen {}, expected {}", dklen, DKLEN)));
+                }
+                if *c > MAX_PBKDF2_C {
+                    return Err(Error::InvalidRequest(format!("pbkdf2 iteration count {} exceeds {}", c, MAX_PBKDF2_C)));
+                }
+                let mut key = vec![0u8; *dklen as usize];
+                pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &hex::decode(salt)?, *c, &mut key);
+                Ok(key)
+            }
+        }
+    }
+}
+
+impl Keystore {
+    /// Encrypts `secret_key` under `password` with fresh salt, iv and id from the enclave randomness.
+    pub fn encrypt(secret_key: &[u8], address: &str, password: &str, use_pbkdf2: bool) -> Result<Keystore, Error> {
+        let random = klave::crypto::random::get_random_bytes(64)?;
+        let kdfparams = match use_pbkdf2 {
+            true => KdfParams::pbkdf2(DEFAULT_PBKDF2_C, &random[..32]),
+            false => KdfParams::scrypt(DEFAULT_SCRYPT_N, &random[..32]),
+        };
+        Keystore::encrypt_with(secret_key, address, password, kdfparams, &random[32..48], &random[48..64])
+    }
+
+    pub fn encrypt_with(secret_key: &[u8], address: &str, password: &str, kdfparams: KdfParams, iv: &[u8], id: &[u8]) -> Result<Keystore, Error> {
+        let derived_key = kdfparams.derive_key(password)?;
+
+        let mut ciphertext = secret_key.to_vec();
+        Aes128Ctr::new_from_slices(&derived_key[..16], iv)?.apply_keystream(&mut ciphertext);
+        let mac = keccak256([&derived_key[16..32], &ciphertext[..]].concat());
+
+        Ok(Keystore {
+            version: KEYSTORE_VERSION,
+            id: format_uuid_v4(id),
+            address: Some(address.trim_start_matches("0x").to_lowercase()),
+            crypto: KeystoreCrypto {
+                cipher: String::from(CIPHER),
+                cipherparams: CipherParams { iv: hex::encode(iv) },
+                ciphertext: hex::encode(&ciphertext),
+                kdf: String::from(kdfparams.name()),
+                kdfparams,
+                mac: hex::encode(mac),
+            },
+        })
+    }
+
+    /// Checks the MAC and returns the decrypted secret key.
+    pub fn decrypt(&self, password: &str) -> Result<Vec<u8>, Error> {
+        if self.version != KEYSTORE_VERSION {
+            return Err(Error::InvalidRequest(format!("unsupported keystore version {}", self.version)));
+        }
+        if self.crypto.cipher != CIPHER {
+            return Err(Error::InvalidRequest(format!("unsupported cipher '{}'", self.crypto.cipher)));
+        }
+        let derived_key = self.crypto.kdfparams.derive_key(password)?;
+
+        let mut ciphertext = hex::decode(&self.crypto.ciphertext)?;
+        let mac = keccak256([&derived_key[16..32], &ciphertext[..]].concat());
+        if hex::decode(&self.crypto.mac)? != mac.as_slice() {
+            return Err(Error::Crypto("invalid password or corrupted keystore".to_string()));
+        }
+
+        let iv = hex::decode(&self.crypto.cipherparams.iv)?;
+        Aes128Ctr::new_from_slices(&derived_key[..16], &iv)
+            .map_err(|_| Error::InvalidRequest(format!("invalid iv length {}, expected 16", iv.len())))?
+            .apply_keystream(&mut ciphertext);
+        Ok(ciphertext)
+    }
+}
+
+/// Formats 16 random bytes as a version 4 UUID.
+fn format_uuid_v4(bytes: &[u8]) -> String {
+    let mut b = [0u8; 16];
+    b.copy_from_slice(&bytes[..16]);
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let h = hex::encode(b);
+    format!("{}-{}-{}-{}-{}", &h[0..8], &h[8..12], &h[12..16], &h[16..20], &h[20..32])
+}
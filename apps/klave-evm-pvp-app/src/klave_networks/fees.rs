@@ -0,0 +1,144 @@
+use alloy_consensus::TxEip1559;
+use alloy_primitives::{hex, TxKind};
+use alloy_rpc_types_eth::FeeHistory;
+use serde::{Deserialize, Serialize};
+use super::http;
+use super::networks::Networks;
+use crate::error::Error;
+
+/// Number of past blocks sampled by `eth_feeHistory`.
+const FEE_HISTORY_BLOCKS: &str = "\"0x5\"";
+/// Reward percentile used when the node does not answer `eth_maxPriorityFeePerGas`.
+const FEE_HISTORY_PERCENTILE: &str = "[50]";
+
+/// Per-network tuning of the fee oracle. Multipliers are expressed in percent.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FeePolicy {
+    pub base_fee_multiplier: u64,
+    pub priority_fee_multiplier: u64,
+    pub max_fee_per_gas_cap: Option<u128>,
+    pub max_priority_fee_per_gas_cap: Option<u128>,
+}
+
+impl Default for FeePolicy {
+    fn default() -> Self {
+        FeePolicy {
+            base_fee_multiplier: 200,
+            priority_fee_multiplier: 100,
+            max_fee_per_gas_cap: None,
+            max_priority_fee_per_gas_cap: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FeeEstimate {
+    pub max_fee_per_gas: u128,
+    pub max_priority_fee_per_gas: u128,
+}
+
+impl FeeEstimate {
+    /// Keeps the fees supplied by the caller and completes the missing one from the estimate.
+    pub fn with_overrides(&self, max_fee_per_gas: Option<u128>, max_priority_fee_per_gas: Option<u128>) -> FeeEstimate {
+        match (max_fee_per_gas, max_priority_fee_per_gas) {
+            (Some(max_fee_per_gas), Some(max_priority_fee_per_gas)) => FeeEstimate { max_fee_per_gas, max_priority_fee_per_gas },
+            // The tip cannot exceed the fee cap
+            (Some(max_fee_per_gas), None) => FeeEstimate {
+                max_fee_per_gas,
+                max_priority_fee_per_gas: self.max_priority_fee_per_gas.min(max_fee_per_gas),
+            },
+            // The base fee part of the estimate is kept under the supplied tip
+            (None, Some(max_priority_fee_per_gas)) => FeeEstimate {
+                max_fee_per_gas: self.max_fee_per_gas.saturating_sub(self.max_priority_fee_per_gas) + max_priority_fee_per_gas,
+                max_priority_fee_per_gas,
+            },
+            (None, None) => self.clone()
+        }
+    }
+}
+
+impl FeePolicy {
+    /// Applies the multipliers and caps to the next base fee and the suggested priority fee.
+    pub fn apply(&self, base_fee: u128, priority_fee: u128) -> FeeEstimate {
+        let mut max_priority_fee_per_gas = priority_fee * self.priority_fee_multiplier as u128 / 100;
+        if let Some(cap) = self.max_priority_fee_per_gas_cap {
+            max_priority_fee_per_gas = max_priority_fee_per_gas.min(cap);
+        }
+        let mut max_fee_per_gas = base_fee * self.base_fee_multiplier as u128 / 100 + max_priority_fee_per_gas;
+        if let Some(cap) = self.max_fee_per_gas_cap {
+            max_fee_per_gas = max_fee_per_gas.min(cap);
+        }
+        FeeEstimate {
+            max_fee_per_gas,
+            max_priority_fee_per_gas: max_priority_fee_per_gas.min(max_fee_per_gas),
+        }
+    }
+}
+
+/// Suggests EIP-1559 fees for `network_name` from `eth_feeHistory` and `eth_maxPriorityFeePerGas`.
+///
+/// Networks that do not expose a fee history fall back to the configured `gas_price`, or to
+/// `eth_gasPrice` when none is configured.
+pub fn estimate_fees(nm: &Networks, network_name: &str) -> Result<FeeEstimate, Error> {
+    let network = nm.get_network(network_name)?;
+    let policy = network.get_fee_policy().cloned().unwrap_or_default();
+
+    let fee_history = nm.send::<FeeHistory>(network_name, "eth_feeHistory", &[FEE_HISTORY_BLOCKS, "\"latest\"", FEE_HISTORY_PERCENTILE]);
+    let base_fee = match fee_history.as_ref().ok().and_then(|h| h.next_block_base_fee()) {
+        Some(b) => b,
+        None => {
+            let gas_price = match network.get_gas_price() {
+                Some(g) => g as u128,
+                None => http::parse_quantity(&nm.send::<String>(network_name, "eth_gasPrice", &[])?)?
+            };
+            return Ok(FeeEstimate {
+                max_fee_per_gas: gas_price,
+                max_priority_fee_per_gas: gas_price,
+            });
+        }
+    };
+
+    let priority_fee = match nm.send::<String>(network_name, "eth_maxPriorityFeePerGas", &[]) {
+        Ok(p) => http::parse_quantity(&p)?,
+        Err(_) => {
+            let rewards: Vec<u128> = fee_history.ok()
+                .and_then(|h| h.reward)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|r| r.first().copied())
+                .collect();
+            match rewards.is_empty() {
+                true => 0,
+                false => rewards.iter().sum::<u128>() / rewards.len() as u128
+            }
+        }
+    };
+
+    Ok(policy.apply(base_fee, priority_fee))
+}
+
+/// Fees of a transaction: the ones supplied by the caller, the missing one estimated.
+pub fn resolve_fees(nm: &Networks, network_name: &str, max_fee_per_gas: Option<u128>, max_priority_fee_per_gas: Option<u128>) -> Result<FeeEstimate, Error> {
+    if let (Some(max_fee_per_gas), Some(max_priority_fee_per_gas)) = (max_fee_per_gas, max_priority_fee_per_gas) {
+        return Ok(FeeEstimate { max_fee_per_gas, max_priority_fee_per_gas });
+    }
+    Ok(estimate_fees(nm, network_name)?.with_overrides(max_fee_per_gas, max_priority_fee_per_gas))
+}
+
+/// Estimates the gas limit of `transaction` sent from `from` with `eth_estimateGas`.
+pub fn estimate_gas(nm: &Networks, network_name: &str, from: &str, transaction: &TxEip1559) -> Result<u64, Error> {
+    let mut call: Vec<String> = Vec::new();
+    call.push(format!("\"from\":\"{}\"", from));
+    if let TxKind::Call(to) = transaction.to {
+        call.push(format!("\"to\":\"{}\"", to));
+    }
+    call.push(format!("\"value\":\"0x{:x}\"", transaction.value));
+    if !transaction.input.is_empty() {
+        call.push(format!("\"input\":\"{}\"", hex::encode_prefixed(&transaction.input)));
+    }
+    if !transaction.access_list.is_empty() {
+        call.push(format!("\"accessList\":{}", serde_json::to_string(&transaction.access_list)?));
+    }
+    let gas: String = nm.send(network_name, "eth_estimateGas", &[&format!("{{{}}}", call.join(","))])?;
+    Ok(http::parse_quantity(&gas)? as u64)
+}
@@ -1,5 +1,11 @@
+use std::collections::BTreeMap;
+use alloy_primitives::hex;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use http::Request;
+use crate::error::Error;
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TransactionRequest {
@@ -23,48 +29,96 @@ pub struct JsonRpcError {
     message: String,
 }
 
-pub fn request_format_with_auth(uri: &str, body: &str, auth: &str) -> Result<Request<String>, Box<dyn std::error::Error>> {
-    let stripped_body = body.replace("\\", ""); // Remove extra backslashes
-
-    let http_request = Request::builder()
-        .method("POST")
-        .uri(uri)
-        .header("Content-Type", "application/json")
-        .header("Authorization", format!("Bearer {}", auth))
-        .body(stripped_body)?;
-
-    Ok(http_request)
+/// Authentication applied by `request_format`, resolved from the credentials of a network.
+#[derive(Debug, Clone, Copy)]
+pub enum Auth<'a> {
+    None,
+    /// `Authorization: Bearer` with a token obtained from the login endpoint.
+    Bearer(&'a str),
+    /// Static API key sent in the `name` header.
+    Header { name: &'a str, value: &'a str },
+    Basic { username: &'a str, password: &'a str },
+    /// API key appended to the path of the URI.
+    UrlPath(&'a str),
+    /// `X-Api-Key` carries `key_id`, `X-Signature` the hex HMAC-SHA256 of `X-Timestamp` followed by the body.
+    Hmac { key_id: &'a str, secret: &'a str, timestamp: u64 },
 }
 
-pub fn request_format(uri: &str, body: &str) -> Result<Request<String>, Box<dyn std::error::Error>> {
+pub fn request_format(uri: &str, body: &str, auth: &Auth, headers: &BTreeMap<String, String>) -> Result<Request<String>, Error> {
     let stripped_body = body.replace("\\", ""); // Remove extra backslashes
 
-    let http_request = Request::builder()
+    let uri = match auth {
+        Auth::UrlPath(key) => format!("{}/{}", uri.trim_end_matches('/'), key),
+        _ => uri.to_string()
+    };
+    let mut builder = Request::builder()
         .method("POST")
         .uri(uri)
-        .header("Content-Type", "application/json")
-        .body(stripped_body)?;
-        
+        .header("Content-Type", "application/json");
+    for (name, value) in headers {
+        builder = builder.header(name, value);
+    }
+    builder = match auth {
+        Auth::None | Auth::UrlPath(_) => builder,
+        Auth::Bearer(token) => builder.header("Authorization", format!("Bearer {}", token)),
+        Auth::Header { name, value } => builder.header(*name, *value),
+        Auth::Basic { username, password } => builder.header("Authorization", format!("Basic {}", STANDARD.encode(format!("{}:{}", username, password)))),
+        Auth::Hmac { key_id, secret, timestamp } => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())?;
+            mac.update(timestamp.to_string().as_bytes());
+            mac.update(stripped_body.as_bytes());
+            builder
+                .header("X-Api-Key", *key_id)
+                .header("X-Timestamp", timestamp.to_string())
+                .header("X-Signature", hex::encode(mac.finalize().into_bytes()))
+        }
+    };
+
+    let http_request = builder.body(stripped_body)?;
     Ok(http_request)
 }
 
-pub fn parse_json_rpc_response<T>(response_body: &str) -> Result<T, Box<dyn std::error::Error>>
+pub fn parse_json_rpc_response<T>(response_body: &str) -> Result<T, Error>
 where
     T: for<'de> Deserialize<'de>,
 {
     let response: JsonRpcResponse<T> = serde_json::from_str(response_body)?;
     match response.result {
         Some(block) => Ok(block),
-        None => Err(format!("Error in response: {:?}", response.error).into()),
+        None => Err(Error::Rpc(format!("Error in response: {:?}", response.error))),
     }
 }
 
+/// JSON-RPC error codes providers answer with when a client exceeds its rate limit.
+const RATE_LIMIT_CODES: &[i64] = &[-32005, -32029, -32090, 429];
+
+/// Whether the response is a JSON-RPC error telling the client to slow down.
+pub fn is_rate_limited(response_body: &str) -> bool {
+    match serde_json::from_str::<serde_json::Value>(response_body) {
+        Ok(response) => response["error"]["code"].as_i64().is_some_and(|code| RATE_LIMIT_CODES.contains(&code)),
+        Err(_) => false
+    }
+}
+
+/// Whether the body is a JSON-RPC response, carrying either a result or an error object.
+pub fn is_json_rpc_response(response_body: &str) -> bool {
+    match serde_json::from_str::<serde_json::Value>(response_body) {
+        Ok(response) => response.get("result").is_some() || response["error"].is_object(),
+        Err(_) => false
+    }
+}
+
+/// Parses a JSON-RPC hex quantity such as "0x1a".
+pub fn parse_quantity(quantity: &str) -> Result<u128, Error> {
+    Ok(u128::from_str_radix(quantity.trim_start_matches("0x"), 16)?)
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TokenResponse {
     token: String,
 }
 
-pub fn parse_token_response<T>(response_body: &str) -> Result<String, Box<dyn std::error::Error>>
+pub fn parse_token_response<T>(response_body: &str) -> Result<String, Error>
 where
     T: for<'de> Deserialize<'de>,
 {
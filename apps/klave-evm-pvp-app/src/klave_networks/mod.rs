@@ -1,3 +1,4 @@
 pub mod networks;
 pub mod network;
 pub mod http;
+pub mod fees;
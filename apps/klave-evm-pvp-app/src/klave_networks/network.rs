@@ -1,14 +1,412 @@
 use std::fmt::{self, Display, Formatter};
-use ::http::Request;
-use serde::{Deserialize, Serialize};
-use super::http;
+use ::http::{Response, StatusCode};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use std::collections::BTreeMap;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+use alloy_primitives::{hex, keccak256};
+use klave::crypto::subtle::{self, AesGcmParams, AesKeyGenParams, CryptoKey, EncryptAlgorithm, KeyGenAlgorithm};
+use crate::error::Error;
+use super::{fees::FeePolicy, http};
 
 pub(crate) const NETWORK_MANAGER_TABLE: &str = "networkManagerTable";
+pub(crate) const NETWORK_TOKEN_TABLE: &str = "networkTokenTable";
+pub(crate) const NETWORK_ENDPOINT_TABLE: &str = "networkEndpointTable";
+/// Weight of the endpoints that do not set one.
+pub(crate) const DEFAULT_ENDPOINT_WEIGHT: u32 = 1;
+/// Attempts on an endpoint before failing over to the next one.
+const ENDPOINT_ATTEMPTS: u32 = 2;
+/// A failing endpoint is tried last for this long, doubled on each consecutive failure, in seconds.
+const ENDPOINT_BACKOFF: u64 = 5;
+const MAX_ENDPOINT_BACKOFF: u64 = 5 * 60;
+/// Calls settling funds, answered by a quorum of endpoints when the network sets one. The head
+/// block number decides whether a funding is final, so it is one of them.
+pub const QUORUM_METHODS: &[&str] = &["eth_getTransactionReceipt", "eth_getBalance", "eth_getBlockByNumber", "eth_blockNumber"];
+/// Lifetime of a token without `exp` claim when the network has no `token_ttl`, in seconds.
+pub(crate) const DEFAULT_TOKEN_TTL: u64 = 5 * 60;
+/// Tokens are refreshed this long before they expire, in seconds.
+const TOKEN_EXPIRY_MARGIN: u64 = 30;
+/// `trusted_time` is expressed in nanoseconds.
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+/// Name of the enclave-sealed AES key that encrypts network credentials.
+const CREDENTIALS_KEY_NAME: &str = "networkCredentialsKey";
+const CREDENTIALS_IV_LENGTH: i32 = 12;
 
+/// How requests to the RPC endpoint of a network authenticate, a network without credentials sends none.
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Credentials {
-    pub username: String,
-    pub password: String
+#[serde(tag = "scheme", rename_all = "snake_case")]
+pub enum Credentials {
+    /// Username and password exchanged at `{rpc_url}/login` for a bearer token.
+    Login { username: String, password: String },
+    /// Static API key sent in `header`.
+    ApiKey { header: String, api_key: String },
+    /// HTTP Basic authentication.
+    Basic { username: String, password: String },
+    /// API key appended to the path of the RPC URL.
+    UrlPath { api_key: String },
+    /// Requests signed with HMAC-SHA256, see `http::Auth::Hmac`.
+    Hmac { key_id: String, secret: String },
+}
+
+impl Credentials {
+    /// Credentials saved before auth schemes were introduced carry no `scheme`, they are logins.
+    pub fn from_value(mut v: Value) -> Result<Credentials, serde_json::Error> {
+        if let Value::Object(m) = &mut v {
+            m.entry("scheme").or_insert_with(|| Value::from("login"));
+        }
+        serde_json::from_value(v)
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            Credentials::Login { .. } => "login",
+            Credentials::ApiKey { .. } => "api_key",
+            Credentials::Basic { .. } => "basic",
+            Credentials::UrlPath { .. } => "url_path",
+            Credentials::Hmac { .. } => "hmac",
+        }
+    }
+}
+
+/// Credentials encrypted with AES-GCM under the enclave-sealed key. Only the scheme is readable.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SealedCredentials {
+    pub scheme: String,
+    pub iv: String,
+    pub ciphertext: String,
+}
+
+impl SealedCredentials {
+    pub fn seal(credentials: &Credentials) -> Result<SealedCredentials, Error> {
+        let (iv, ciphertext) = seal(&serde_json::to_vec(credentials)?)?;
+        Ok(SealedCredentials {
+            scheme: credentials.scheme().to_string(),
+            iv,
+            ciphertext,
+        })
+    }
+
+    pub fn open(&self) -> Result<Credentials, Error> {
+        Ok(serde_json::from_slice(&open(&self.iv, &self.ciphertext)?)?)
+    }
+}
+
+/// Encrypts `clear_text` under the credentials key, returns the hex encoded iv and ciphertext.
+fn seal(clear_text: &[u8]) -> Result<(String, String), Error> {
+    let iv = klave::crypto::random::get_random_bytes(CREDENTIALS_IV_LENGTH)?;
+    match subtle::encrypt(&aes_gcm(&iv), &credentials_key()?, clear_text) {
+        Ok(ciphertext) => Ok((hex::encode(iv), hex::encode(ciphertext))),
+        Err(e) => Err(Error::Crypto(format!("failed to seal credentials: {}", e)))
+    }
+}
+
+fn open(iv: &str, ciphertext: &str) -> Result<Vec<u8>, Error> {
+    let key = match subtle::load_key(CREDENTIALS_KEY_NAME) {
+        Ok(k) => k,
+        Err(e) => return Err(Error::Crypto(format!("failed to load credentials key: {}", e)))
+    };
+    match subtle::decrypt(&aes_gcm(&hex::decode(iv)?), &key, &hex::decode(ciphertext)?) {
+        Ok(c) => Ok(c),
+        Err(e) => Err(Error::Crypto(format!("failed to open credentials: {}", e)))
+    }
+}
+
+fn aes_gcm(iv: &[u8]) -> EncryptAlgorithm {
+    EncryptAlgorithm::AesGcm(AesGcmParams {
+        iv: iv.to_vec(),
+        additional_data: Vec::new(),
+        tag_length: 128,
+    })
+}
+
+/// Loads the credentials key, generating and persisting it in the enclave on first use. It cannot be exported.
+fn credentials_key() -> Result<CryptoKey, Error> {
+    if let Ok(key) = subtle::load_key(CREDENTIALS_KEY_NAME) {
+        return Ok(key);
+    }
+    let key = match subtle::generate_key(&KeyGenAlgorithm::Aes(AesKeyGenParams::default()), false, &["encrypt", "decrypt"]) {
+        Ok(k) => k,
+        Err(e) => return Err(Error::Crypto(format!("failed to generate credentials key: {}", e)))
+    };
+    match subtle::save_key(&key, CREDENTIALS_KEY_NAME) {
+        Ok(_) => Ok(key),
+        Err(e) => Err(Error::Crypto(format!("failed to save credentials key: {}", e)))
+    }
+}
+
+/// Credentials of a network as stored in the ledger.
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum StoredCredentials {
+    Sealed(SealedCredentials),
+    /// Set in memory, or saved before credentials were encrypted. `Network::save` seals them.
+    Plain(Credentials),
+}
+
+impl StoredCredentials {
+    pub fn scheme(&self) -> &str {
+        match self {
+            StoredCredentials::Sealed(s) => &s.scheme,
+            StoredCredentials::Plain(c) => c.scheme(),
+        }
+    }
+
+    pub fn open(&self) -> Result<Credentials, Error> {
+        match self {
+            StoredCredentials::Sealed(s) => s.open(),
+            StoredCredentials::Plain(c) => Ok(c.clone()),
+        }
+    }
+}
+
+fn opt_stored_credentials<'de, D: Deserializer<'de>>(d: D) -> Result<Option<StoredCredentials>, D::Error> {
+    match Option::<Value>::deserialize(d)? {
+        Some(v) if v.get("ciphertext").is_some() => serde_json::from_value(v).map(|s| Some(StoredCredentials::Sealed(s))).map_err(de::Error::custom),
+        Some(v) => Credentials::from_value(v).map(|c| Some(StoredCredentials::Plain(c))).map_err(de::Error::custom),
+        None => Ok(None)
+    }
+}
+
+/// Static headers encrypted like the credentials. Only the header names are readable.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SealedHeaders {
+    pub names: Vec<String>,
+    pub iv: String,
+    pub ciphertext: String,
+}
+
+impl SealedHeaders {
+    pub fn seal(headers: &BTreeMap<String, String>) -> Result<SealedHeaders, Error> {
+        let (iv, ciphertext) = seal(&serde_json::to_vec(headers)?)?;
+        Ok(SealedHeaders {
+            names: headers.keys().cloned().collect(),
+            iv,
+            ciphertext,
+        })
+    }
+
+    pub fn open(&self) -> Result<BTreeMap<String, String>, Error> {
+        Ok(serde_json::from_slice(&open(&self.iv, &self.ciphertext)?)?)
+    }
+}
+
+/// Static headers of a network as stored in the ledger. They usually carry API keys, so they are sealed like the credentials.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum StoredHeaders {
+    Sealed(SealedHeaders),
+    /// Set in memory, or saved before headers were encrypted. `Network::save` seals them.
+    Plain(BTreeMap<String, String>),
+}
+
+impl Default for StoredHeaders {
+    fn default() -> Self {
+        StoredHeaders::Plain(BTreeMap::new())
+    }
+}
+
+impl StoredHeaders {
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            StoredHeaders::Sealed(s) => s.names.iter().map(String::as_str).collect(),
+            StoredHeaders::Plain(h) => h.keys().map(String::as_str).collect(),
+        }
+    }
+
+    pub fn open(&self) -> Result<BTreeMap<String, String>, Error> {
+        match self {
+            StoredHeaders::Sealed(s) => s.open(),
+            StoredHeaders::Plain(h) => Ok(h.clone()),
+        }
+    }
+}
+
+/// An RPC endpoint of a network.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Endpoint {
+    pub rpc_url: String,
+    #[serde(default, deserialize_with = "opt_stored_credentials")]
+    pub credentials: Option<StoredCredentials>,
+    /// Endpoints are tried by decreasing weight, in order for equal weights. Zero disables the endpoint.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    DEFAULT_ENDPOINT_WEIGHT
+}
+
+impl Endpoint {
+    /// The credentials are sealed when the network is saved.
+    pub fn new(rpc_url: &str, credentials: Option<&Credentials>, weight: Option<u32>) -> Endpoint {
+        Endpoint {
+            rpc_url: rpc_url.to_string(),
+            credentials: credentials.map(|c| StoredCredentials::Plain(c.clone())),
+            weight: weight.unwrap_or(DEFAULT_ENDPOINT_WEIGHT),
+        }
+    }
+}
+
+/// Health of an endpoint, kept to order failover and for diagnostics.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct EndpointStatus {
+    pub rpc_url: String,
+    /// Calls the endpoint has served.
+    pub served: u64,
+    pub last_served_at: Option<u64>,
+    /// Consecutive failures, reset once the endpoint serves a call again.
+    pub failures: u32,
+    /// `trusted_time` until which the endpoint is only tried after the healthy ones.
+    pub retry_after: u64,
+    pub last_error: Option<String>,
+    /// Quorum reads the endpoint answered differently from the accepted result.
+    #[serde(default)]
+    pub disagreements: u64,
+    pub last_disagreement: Option<String>,
+}
+
+impl EndpointStatus {
+    pub fn record_served(&mut self, now: u64) {
+        self.served += 1;
+        self.last_served_at = Some(now);
+        self.failures = 0;
+        self.retry_after = 0;
+    }
+
+    pub fn record_failure(&mut self, now: u64, error: &Error) {
+        self.failures = self.failures.saturating_add(1);
+        let backoff = ENDPOINT_BACKOFF.saturating_mul(1 << self.failures.min(16).saturating_sub(1)).min(MAX_ENDPOINT_BACKOFF);
+        self.retry_after = now.saturating_add(backoff * NANOSECONDS_PER_SECOND);
+        self.last_error = Some(error.to_string());
+    }
+
+    pub fn record_disagreement(&mut self, disagreement: String) {
+        self.disagreements += 1;
+        self.last_disagreement = Some(disagreement);
+    }
+
+    pub fn is_backing_off(&self, now: u64) -> bool {
+        now < self.retry_after
+    }
+}
+
+/// Quorum reads: the calls of `QUORUM_METHODS` are sent to `size` endpoints, in failover order,
+/// and their result is only accepted once `threshold` of them returned the same one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Quorum {
+    pub size: u32,
+    pub threshold: u32,
+}
+
+impl Quorum {
+    /// The threshold must be a majority of `size`, so that two results cannot both be accepted.
+    pub fn check(&self, enabled_endpoints: usize) -> Result<(), Error> {
+        if self.threshold == 0 || self.threshold > self.size || 2 * self.threshold <= self.size {
+            return Err(Error::InvalidRequest(format!("quorum threshold {} must be a majority of its size {}", self.threshold, self.size)));
+        }
+        if self.size as usize > enabled_endpoints {
+            return Err(Error::InvalidRequest(format!("quorum size {} exceeds the {} enabled endpoints", self.size, enabled_endpoints)));
+        }
+        Ok(())
+    }
+}
+
+/// The result at least `threshold` of `results` agree on. Endpoints lag each other by a few
+/// blocks, so for `eth_blockNumber` it is the highest head `threshold` endpoints have reached.
+pub fn agreed_result(method: &str, results: &[Value], threshold: u32) -> Option<Value> {
+    if method == "eth_blockNumber" {
+        let mut heads: Vec<(u128, &Value)> = results.iter()
+            .filter_map(|r| r.as_str().and_then(|h| http::parse_quantity(h).ok()).map(|h| (h, r)))
+            .collect();
+        heads.sort_by_key(|b| std::cmp::Reverse(b.0));
+        return (threshold as usize).checked_sub(1).and_then(|i| heads.get(i)).map(|(_, r)| (*r).clone());
+    }
+    results.iter()
+        .find(|result| results.iter().filter(|r| r == result).count() >= threshold as usize)
+        .cloned()
+}
+
+/// Short form of a result in disagreement reports: quantities as is, objects by digest.
+fn summarize(result: &Value) -> String {
+    match result {
+        Value::String(s) if s.len() <= 66 => s.clone(),
+        other => format!("digest 0x{}", hex::encode(&keccak256(other.to_string().as_bytes())[..8]))
+    }
+}
+
+/// How a call to an endpoint failed. `Retry` failures move on to the next attempt or endpoint,
+/// `Fatal` ones are answers of the endpoint and are returned as is.
+enum Failure {
+    Retry(Error),
+    Fatal(Error),
+}
+
+/// Token returned by the login endpoint of a network, cached until it expires.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuthToken {
+    pub token: String,
+    /// `trusted_time` at which the token expires.
+    pub expires_at: u64,
+}
+
+impl AuthToken {
+    /// The expiry is read from the `exp` claim when the token is a JWT, `ttl` seconds from `now` otherwise.
+    pub fn new(token: &str, now: u64, ttl: u64) -> AuthToken {
+        AuthToken {
+            token: token.to_string(),
+            expires_at: match jwt_expiry(token) {
+                Some(exp) => exp.saturating_mul(NANOSECONDS_PER_SECOND),
+                None => now.saturating_add(ttl.saturating_mul(NANOSECONDS_PER_SECOND))
+            },
+        }
+    }
+
+    pub fn is_valid(&self, now: u64) -> bool {
+        now.saturating_add(TOKEN_EXPIRY_MARGIN * NANOSECONDS_PER_SECOND) < self.expires_at
+    }
+}
+
+/// Login token as cached in the ledger, sealed like the credentials it was issued for.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SealedToken {
+    expires_at: u64,
+    iv: String,
+    ciphertext: String,
+}
+
+impl SealedToken {
+    fn seal(token: &AuthToken) -> Result<SealedToken, Error> {
+        let (iv, ciphertext) = seal(token.token.as_bytes())?;
+        Ok(SealedToken { expires_at: token.expires_at, iv, ciphertext })
+    }
+
+    fn open(&self) -> Result<AuthToken, Error> {
+        let token = match String::from_utf8(open(&self.iv, &self.ciphertext)?) {
+            Ok(t) => t,
+            Err(e) => return Err(Error::Crypto(format!("failed to open token: {}", e)))
+        };
+        Ok(AuthToken { token, expires_at: self.expires_at })
+    }
+}
+
+/// Reads the `exp` claim, in seconds since the epoch, from the payload of a JWT.
+fn jwt_expiry(token: &str) -> Option<u64> {
+    let payload = token.split('.').nth(1)?;
+    let claims = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    serde_json::from_slice::<Value>(&claims).ok()?.get("exp")?.as_u64()
+}
+
+/// Envelope used when signing transactions for a network.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionType {
+    /// Pre-EIP-2718 transaction with EIP-155 replay protection.
+    Legacy,
+    /// Type 1 transaction carrying an access list.
+    Eip2930,
+    /// Type 2 transaction with a base fee and a priority fee.
+    #[default]
+    Eip1559,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -17,13 +415,42 @@ pub struct Network {
     pub chain_id: Option<u64>,
     pub rpc_url: String,
     pub gas_price: Option<u64>,
-    pub credentials: Option<Credentials>,
+    pub confirmations: Option<u64>,
+    pub fee_policy: Option<FeePolicy>,
+    pub tx_type: Option<TransactionType>,
+    #[serde(default, deserialize_with = "opt_stored_credentials")]
+    pub credentials: Option<StoredCredentials>,
+    /// Static headers sent with every request to the network.
+    #[serde(default)]
+    pub headers: StoredHeaders,
+    /// Weight of the endpoint described by `rpc_url` and `credentials`, see `Endpoint::weight`.
+    pub weight: Option<u32>,
+    /// Endpoints to fail over to, on top of the one described by `rpc_url` and `credentials`.
+    #[serde(default)]
+    pub endpoints: Vec<Endpoint>,
+    /// Quorum of endpoints the settlement-critical reads are checked against, none for a single answer.
+    pub quorum: Option<Quorum>,
+    /// Lifetime of the login tokens of the network when they do not carry an `exp` claim, in seconds.
+    pub token_ttl: Option<u64>,
 }
 
+/// Credentials are redacted to their scheme and header values are hidden, so that the serialisation can be returned by queries.
 impl Display for Network {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", match serde_json::to_string(self) {
-            Ok(s) => s,
+        let redacted = serde_json::to_value(self).map(|mut v| {
+            if let Some(credentials) = &self.credentials {
+                v["credentials"] = json!({ "scheme": credentials.scheme() });
+            }
+            v["headers"] = self.headers.names().into_iter().map(|name| (name.to_string(), json!("[redacted]"))).collect();
+            for (index, endpoint) in self.endpoints.iter().enumerate() {
+                if let Some(credentials) = &endpoint.credentials {
+                    v["endpoints"][index]["credentials"] = json!({ "scheme": credentials.scheme() });
+                }
+            }
+            v
+        });
+        write!(f, "{}", match redacted {
+            Ok(s) => s.to_string(),
             Err(e) => {
                 format!("ERROR: failed to serialize Network: {}", e)
             }
@@ -35,15 +462,23 @@ impl Network {
     pub fn new(name: &str, chain_id: Option<u64>, rpc_url: &str, gas_price: Option<u64>, credentials_input: Option<&str>) -> Network {
         Network {
             name: name.to_string(),
-            chain_id: chain_id,
+            chain_id,
             rpc_url: rpc_url.to_string(),
-            gas_price: gas_price,
+            gas_price,
+            confirmations: None,
+            fee_policy: None,
+            tx_type: None,
+            token_ttl: None,
+            headers: StoredHeaders::default(),
+            weight: None,
+            endpoints: Vec::new(),
+            quorum: None,
             credentials: {
                 match credentials_input {
                     None => None,
                     Some(credentials_input) => {
-                        match serde_json::from_str::<Credentials>(credentials_input) {
-                            Ok(v) => Some(v),
+                        match serde_json::from_str::<Value>(credentials_input).and_then(Credentials::from_value) {
+                            Ok(v) => Some(StoredCredentials::Plain(v)),
                             Err(_e) => {
                                 None
                             }
@@ -54,26 +489,48 @@ impl Network {
         }
     }
 
-    pub fn load(name: &str) -> Result<Network, Box<dyn std::error::Error>> {
+    pub fn load(name: &str) -> Result<Network, Error> {
         match klave::ledger::get_table(NETWORK_MANAGER_TABLE).get(name) {
             Ok(v) => {
 
                 let network: Network = serde_json::from_slice(&v)?;
                 Ok(network)
             },
-            Err(e) => Err(e.into())
+            Err(_) => Err(Error::NotFound(format!("network '{}' not found", name)))
         }
     }
 
-    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let serialized_network = serde_json::to_string(&self)?;
-        klave::ledger::get_table(NETWORK_MANAGER_TABLE).set(self.name.as_str(), &serialized_network.as_bytes())?; 
-        Ok(())
+    pub fn save(&self) -> Result<(), Error> {
+        let mut serialized_network = serde_json::to_value(self)?;
+        if let Some(StoredCredentials::Plain(credentials)) = &self.credentials {
+            serialized_network["credentials"] = serde_json::to_value(SealedCredentials::seal(credentials)?)?;
+        }
+        for (index, endpoint) in self.endpoints.iter().enumerate() {
+            if let Some(StoredCredentials::Plain(credentials)) = &endpoint.credentials {
+                serialized_network["endpoints"][index]["credentials"] = serde_json::to_value(SealedCredentials::seal(credentials)?)?;
+            }
+        }
+        if let StoredHeaders::Plain(headers) = &self.headers {
+            if !headers.is_empty() {
+                serialized_network["headers"] = serde_json::to_value(SealedHeaders::seal(headers)?)?;
+            }
+        }
+        let serialized_network = serialized_network.to_string();
+        match klave::ledger::get_table(NETWORK_MANAGER_TABLE).set(self.name.as_str(), serialized_network.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::Ledger(format!("failed to save network: {}", e)))
+        }
     }
 
-    pub fn remove(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
-        klave::ledger::get_table(NETWORK_MANAGER_TABLE).remove(name)?;
-        Ok(())
+    pub fn remove(&self, name: &str) -> Result<(), Error> {
+        self.clear_token();
+        for endpoint in self.endpoints() {
+            let _ = klave::ledger::get_table(NETWORK_ENDPOINT_TABLE).remove(&self.endpoint_key(&endpoint));
+        }
+        match klave::ledger::get_table(NETWORK_MANAGER_TABLE).remove(name) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::Ledger(format!("failed to remove network: {}", e)))
+        }
     }
 
     pub fn get_rpc_url(&self) -> &str {
@@ -88,14 +545,46 @@ impl Network {
         self.chain_id
     }
 
+    pub fn get_confirmations(&self) -> Option<u64> {
+        self.confirmations
+    }
+
+    pub fn get_fee_policy(&self) -> Option<&FeePolicy> {
+        self.fee_policy.as_ref()
+    }
+
+    pub fn get_tx_type(&self) -> Option<TransactionType> {
+        self.tx_type
+    }
+
     pub fn get_name(&self) -> &str {
         &self.name
     }
 
-    pub fn get_credentials(&self) -> Option<&Credentials> {
+    pub fn get_credentials(&self) -> Option<&StoredCredentials> {
         self.credentials.as_ref()
     }
 
+    pub fn get_token_ttl(&self) -> Option<u64> {
+        self.token_ttl
+    }
+
+    pub fn get_headers(&self) -> &StoredHeaders {
+        &self.headers
+    }
+
+    pub fn get_weight(&self) -> Option<u32> {
+        self.weight
+    }
+
+    pub fn get_endpoints(&self) -> &Vec<Endpoint> {
+        &self.endpoints
+    }
+
+    pub fn get_quorum(&self) -> Option<Quorum> {
+        self.quorum
+    }
+
     pub fn set_gas_price(&mut self, gas_price: Option<u64>) {
         self.gas_price = gas_price;
     }
@@ -104,6 +593,18 @@ impl Network {
         self.chain_id = chain_id;
     }
 
+    pub fn set_confirmations(&mut self, confirmations: Option<u64>) {
+        self.confirmations = confirmations;
+    }
+
+    pub fn set_fee_policy(&mut self, fee_policy: Option<FeePolicy>) {
+        self.fee_policy = fee_policy;
+    }
+
+    pub fn set_tx_type(&mut self, tx_type: Option<TransactionType>) {
+        self.tx_type = tx_type;
+    }
+
     pub fn set_rpc_url(&mut self, rpc_url: &str) {
         self.rpc_url = rpc_url.to_string();
     }
@@ -112,46 +613,300 @@ impl Network {
         self.name = name.to_string();
     }
 
+    /// The credentials are sealed when the network is saved.
     pub fn set_credentials(&mut self, credentials: &Credentials) {
-        self.credentials = Some(credentials.clone());
+        self.credentials = Some(StoredCredentials::Plain(credentials.clone()));
+    }
+
+    pub fn set_stored_credentials(&mut self, credentials: Option<StoredCredentials>) {
+        self.credentials = credentials;
+    }
+
+    pub fn set_token_ttl(&mut self, token_ttl: Option<u64>) {
+        self.token_ttl = token_ttl;
+    }
+
+    /// The headers are sealed when the network is saved.
+    pub fn set_headers(&mut self, headers: BTreeMap<String, String>) {
+        self.headers = StoredHeaders::Plain(headers);
+    }
+
+    pub fn set_stored_headers(&mut self, headers: StoredHeaders) {
+        self.headers = headers;
+    }
+
+    pub fn set_weight(&mut self, weight: Option<u32>) {
+        self.weight = weight;
+    }
+
+    pub fn set_endpoints(&mut self, endpoints: Vec<Endpoint>) {
+        self.endpoints = endpoints;
+    }
+
+    pub fn set_quorum(&mut self, quorum: Option<Quorum>) {
+        self.quorum = quorum;
+    }
+
+    /// Enabled endpoints in the order they are tried, starting with the one described by `rpc_url`.
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        let mut endpoints = vec![Endpoint {
+            rpc_url: self.rpc_url.clone(),
+            credentials: self.credentials.clone(),
+            weight: self.weight.unwrap_or(DEFAULT_ENDPOINT_WEIGHT),
+        }];
+        endpoints.extend(self.endpoints.iter().cloned());
+        endpoints.retain(|e| e.weight > 0);
+        endpoints.sort_by_key(|b| std::cmp::Reverse(b.weight));
+        endpoints
+    }
+
+    /// Key of the cached token and of the status of an endpoint.
+    fn endpoint_key(&self, endpoint: &Endpoint) -> String {
+        format!("{}#{}", self.name, endpoint.rpc_url)
+    }
+
+    pub fn endpoint_status(&self, endpoint: &Endpoint) -> EndpointStatus {
+        match klave::ledger::get_table(NETWORK_ENDPOINT_TABLE).get(&self.endpoint_key(endpoint)).ok().and_then(|v| serde_json::from_slice::<EndpointStatus>(&v).ok()) {
+            Some(status) => status,
+            None => EndpointStatus { rpc_url: endpoint.rpc_url.clone(), ..Default::default() }
+        }
+    }
+
+    fn save_endpoint_status(&self, endpoint: &Endpoint, status: &EndpointStatus) {
+        // Queries cannot write to the ledger, only transactions keep the status up to date.
+        let _ = serde_json::to_string(status).map(|serialized| klave::ledger::get_table(NETWORK_ENDPOINT_TABLE).set(&self.endpoint_key(endpoint), serialized.as_bytes()));
+    }
+
+    pub fn generate_token(&self, endpoint: &Endpoint, credentials: &Credentials) -> Result<String, Error> {
+        let body = match credentials {
+            Credentials::Login { username, password } => json!({ "username": username, "password": password }).to_string(),
+            _ => return Err(Error::InvalidState(format!("endpoint '{}' has no login credentials", endpoint.rpc_url)))
+        };
+        let http_request = http::request_format(&format!("{}/login", endpoint.rpc_url), &body, &http::Auth::None, &self.headers.open()?)?;
+        let result = match klave::https::request(&http_request) {
+            Ok(r) => r,
+            Err(e) => return Err(Error::Rpc(format!("failed to send login request to {}: {}", http_request.uri(), e)))
+        };
+        match http::parse_token_response::<String>(result.body()) {
+            Ok(t) => Ok(t),
+            Err(e) => Err(Error::Rpc(format!("failed to parse login response: {}", e)))
+        }
+
     }
 
-    pub fn generate_token(&self) -> Result<String, Box<dyn std::error::Error>> {
-        let body = serde_json::to_string::<Credentials>(&self.credentials.clone().expect("credentials not found"))?;
-        let http_request = http::request_format(&format!("{}/login", self.get_rpc_url()), &body)?;
-        let result = klave::https::request(&http_request)?;
-        let token_response = http::parse_token_response::<String>(&result.body())?;
-        Ok(token_response)
+    /// Returns the cached login token of `endpoint` while it is valid, logs in again otherwise or when `refresh` is set.
+    pub fn auth_token(&self, endpoint: &Endpoint, credentials: &Credentials, refresh: bool) -> Result<String, Error> {
+        let now = klave::context::get("trusted_time")?.parse::<u64>()?;
+        let key = self.endpoint_key(endpoint);
+        if !refresh {
+            // Tokens cached in clear before they were sealed are not read, the network logs in again
+            let cached = klave::ledger::get_table(NETWORK_TOKEN_TABLE).get(&key).ok()
+                .and_then(|v| serde_json::from_slice::<SealedToken>(&v).ok())
+                .and_then(|sealed| sealed.open().ok());
+            if let Some(cached) = cached {
+                if cached.is_valid(now) {
+                    return Ok(cached.token);
+                }
+            }
+        }
+
+        let token = AuthToken::new(&self.generate_token(endpoint, credentials)?, now, self.token_ttl.unwrap_or(DEFAULT_TOKEN_TTL));
+        // Queries cannot write to the ledger, they keep logging in until a transaction caches a token.
+        if let Ok(serialized) = SealedToken::seal(&token).and_then(|sealed| Ok(serde_json::to_string(&sealed)?)) {
+            let _ = klave::ledger::get_table(NETWORK_TOKEN_TABLE).set(&key, serialized.as_bytes());
+        }
+        Ok(token.token)
+    }
 
+    /// Drops the cached login tokens, e.g. once the credentials they were issued for change.
+    pub fn clear_token(&self) {
+        for endpoint in self.endpoints() {
+            let _ = klave::ledger::get_table(NETWORK_TOKEN_TABLE).remove(&self.endpoint_key(&endpoint));
+        }
     }
 
-    pub fn request<T>(&self, body: &str) -> Result<T, Box<dyn std::error::Error>> 
+    fn send_request(&self, endpoint: &Endpoint, body: &str, auth: &http::Auth) -> Result<Response<String>, Failure> {
+        let http_request = match self.headers.open().and_then(|headers| http::request_format(&endpoint.rpc_url, body, auth, &headers)) {
+            Ok(r) => r,
+            Err(e) => return Err(Failure::Fatal(e))
+        };
+        match klave::https::request(&http_request) {
+            Ok(r) => Ok(r),
+            // The URI may carry an API key, errors only name the configured RPC URL
+            Err(e) => Err(Failure::Retry(Error::Rpc(format!("failed to send request to {}: {}, {}", endpoint.rpc_url, http_request.body(), e))))
+        }
+    }
+
+    /// Resolves `credentials` into the authentication of a request, `token` being the login token.
+    pub fn auth<'a>(credentials: Option<&'a Credentials>, token: Option<&'a str>, timestamp: u64) -> http::Auth<'a> {
+        match (credentials, token) {
+            (Some(Credentials::Login { .. }), Some(token)) => http::Auth::Bearer(token),
+            (Some(Credentials::ApiKey { header, api_key }), _) => http::Auth::Header { name: header, value: api_key },
+            (Some(Credentials::Basic { username, password }), _) => http::Auth::Basic { username, password },
+            (Some(Credentials::UrlPath { api_key }), _) => http::Auth::UrlPath(api_key),
+            (Some(Credentials::Hmac { key_id, secret }), _) => http::Auth::Hmac { key_id, secret, timestamp },
+            _ => http::Auth::None
+        }
+    }
+
+    /// Sends `body` to the endpoints of the network in turn until one of them answers.
+    ///
+    /// Transport errors, HTTP 5xx, rejected credentials and rate limits are retried, then the
+    /// next endpoint is tried. Endpoints that failed recently are tried last. The enclave has no
+    /// timer, so the backoff applies across calls rather than between attempts.
+    pub fn request<T>(&self, body: &str) -> Result<T, Error> 
         where
         T: for<'de> Deserialize<'de>,
     {    
-        let http_request: Request<String>;
-        if self.credentials.is_some() {
-            let token = self.generate_token()?;
-            http_request = http::request_format_with_auth(self.get_rpc_url(), &body, &token)?;
+        let now = trusted_time();
+        let mut errors: Vec<String> = Vec::new();
+        for (endpoint, mut status) in self.ordered_endpoints(now)? {
+            match self.attempt::<T>(&endpoint, &mut status, body, now) {
+                Ok(r) => return Ok(r),
+                Err(Failure::Fatal(e)) => return Err(e),
+                Err(Failure::Retry(e)) => errors.push(e.to_string())
+            }
         }
-        else {
-            http_request = http::request_format(self.get_rpc_url(), &body)?;
+        Err(Error::Rpc(format!("every endpoint of network '{}' failed: {}", self.name, errors.join("; "))))
+    }
+
+    /// Sends `body` to `quorum.size` endpoints and returns the result `quorum.threshold` of them agree on.
+    ///
+    /// Failing endpoints are replaced by the next ones in failover order. Endpoints answering
+    /// differently from the accepted result have the disagreement recorded in their status, and
+    /// when no result reaches the threshold the error lists what each endpoint answered.
+    /// See `agreed_result` for how the answers are compared.
+    pub fn quorum_request<T>(&self, method: &str, body: &str, quorum: Quorum) -> Result<T, Error>
+        where
+        T: for<'de> Deserialize<'de>,
+    {
+        let now = trusted_time();
+        let mut answers: Vec<(Endpoint, EndpointStatus, Value)> = Vec::new();
+        let mut errors: Vec<String> = Vec::new();
+        let mut fatal: Option<Error> = None;
+        for (endpoint, mut status) in self.ordered_endpoints(now)? {
+            if answers.len() == quorum.size as usize {
+                break;
+            }
+            match self.attempt::<Value>(&endpoint, &mut status, body, now) {
+                Ok(r) => answers.push((endpoint, status, r)),
+                Err(Failure::Fatal(e)) => {
+                    errors.push(e.to_string());
+                    fatal.get_or_insert(e);
+                },
+                Err(Failure::Retry(e)) => errors.push(e.to_string())
+            }
+        }
+        // Endpoints answering with a JSON-RPC error agree the call failed, return it as `request` would
+        if answers.is_empty() {
+            if let Some(e) = fatal {
+                return Err(e);
+            }
         }
 
-        let result = match klave::https::request(&http_request) {
-            Ok(r) => r,
-            Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: request - failed to send request: {}, {:?}, {}, {}", &http_request.uri(), &http_request.headers(), &http_request.body(), e));
-                return Err(e.into());
+        let results: Vec<Value> = answers.iter().map(|(_, _, r)| r.clone()).collect();
+        let accepted = agreed_result(method, &results, quorum.threshold);
+        match accepted {
+            Some(result) => {
+                for (endpoint, status, r) in answers.iter_mut().filter(|(_, _, r)| *r != result) {
+                    status.record_disagreement(format!("answered {} where the quorum answered {} to {}", summarize(r), summarize(&result), body));
+                    self.save_endpoint_status(endpoint, status);
+                }
+                Ok(serde_json::from_value(result)?)
+            },
+            None => {
+                let mut report: Vec<String> = answers.iter().map(|(endpoint, _, r)| format!("{} answered {}", endpoint.rpc_url, summarize(r))).collect();
+                report.extend(errors);
+                Err(Error::Rpc(format!("no {} of {} endpoints of network '{}' agree: {}", quorum.threshold, quorum.size, self.name, report.join("; "))))
             }
+        }
+    }
+
+    /// Enabled endpoints with their status, the ones backing off last.
+    fn ordered_endpoints(&self, now: u64) -> Result<Vec<(Endpoint, EndpointStatus)>, Error> {
+        let mut endpoints: Vec<(Endpoint, EndpointStatus)> = self.endpoints().into_iter().map(|e| {
+            let status = self.endpoint_status(&e);
+            (e, status)
+        }).collect();
+        endpoints.sort_by_key(|(_, status)| status.is_backing_off(now));
+        if endpoints.is_empty() {
+            return Err(Error::InvalidState(format!("network '{}' has no enabled endpoint", self.name)));
+        }
+        Ok(endpoints)
+    }
+
+    /// Calls an endpoint up to `ENDPOINT_ATTEMPTS` times and records the outcome in its status.
+    fn attempt<T>(&self, endpoint: &Endpoint, status: &mut EndpointStatus, body: &str, now: u64) -> Result<T, Failure>
+        where
+        T: for<'de> Deserialize<'de>,
+    {
+        let mut attempt = 1;
+        loop {
+            match self.call_endpoint::<T>(endpoint, body, now) {
+                Ok(r) => {
+                    status.record_served(now);
+                    self.save_endpoint_status(endpoint, status);
+                    return Ok(r);
+                },
+                Err(Failure::Fatal(e)) => {
+                    status.record_served(now);
+                    self.save_endpoint_status(endpoint, status);
+                    return Err(Failure::Fatal(e));
+                },
+                Err(Failure::Retry(e)) if attempt >= ENDPOINT_ATTEMPTS => {
+                    status.record_failure(now, &e);
+                    self.save_endpoint_status(endpoint, status);
+                    return Err(Failure::Retry(e));
+                },
+                Err(Failure::Retry(_)) => attempt += 1
+            }
+        }
+    }
+
+    fn call_endpoint<T>(&self, endpoint: &Endpoint, body: &str, now: u64) -> Result<T, Failure>
+        where
+        T: for<'de> Deserialize<'de>,
+    {
+        // Credentials are only opened here, for the time of the request
+        let credentials = match &endpoint.credentials {
+            Some(c) => Some(c.open().map_err(Failure::Fatal)?),
+            None => None
         };
-        let tx_response = match http::parse_json_rpc_response::<T>(&result.body()) {
-            Ok(r) => r,
-            Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: request - failed to parse response: {}, {}, {}, {:?}, {}", result.body(), e, &http_request.uri(), &http_request.headers(), &http_request.body()));
-                return Err(e.into());
+        let result = if let Some(login @ Credentials::Login { .. }) = &credentials {
+            let token = self.auth_token(endpoint, login, false).map_err(Failure::Retry)?;
+            let result = self.send_request(endpoint, body, &Network::auth(Some(login), Some(&token), 0))?;
+            // The token may have been revoked before its expiry, log in again once
+            if result.status() == StatusCode::UNAUTHORIZED {
+                let token = self.auth_token(endpoint, login, true).map_err(Failure::Retry)?;
+                self.send_request(endpoint, body, &Network::auth(Some(login), Some(&token), 0))?
+            } else {
+                result
             }
+        }
+        else {
+            // HMAC signatures are timestamped in seconds
+            let timestamp = now / NANOSECONDS_PER_SECOND;
+            self.send_request(endpoint, body, &Network::auth(credentials.as_ref(), None, timestamp))?
         };
-        Ok(tx_response)
+
+        let status = result.status();
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Err(Failure::Retry(Error::Rpc(format!("{} answered HTTP {}", endpoint.rpc_url, status))));
+        }
+        if http::is_rate_limited(result.body()) {
+            return Err(Failure::Retry(Error::Rpc(format!("{} is rate limiting requests: {}", endpoint.rpc_url, result.body()))));
+        }
+        // Proxies and gateways answer with error pages, only JSON-RPC errors are answers of the node
+        if !http::is_json_rpc_response(result.body()) {
+            return Err(Failure::Retry(Error::Rpc(format!("{} answered HTTP {} without a JSON-RPC response: {}", endpoint.rpc_url, status, result.body()))));
+        }
+        match http::parse_json_rpc_response::<T>(result.body()) {
+            Ok(r) => Ok(r),
+            Err(e) => Err(Failure::Fatal(Error::Rpc(format!("failed to parse response from {}: {}, {}", endpoint.rpc_url, result.body(), e))))
+        }
     }
 }
+
+fn trusted_time() -> u64 {
+    klave::context::get("trusted_time").ok().and_then(|t| t.parse::<u64>().ok()).unwrap_or(0)
+}
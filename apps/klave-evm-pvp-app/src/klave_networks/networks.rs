@@ -3,7 +3,9 @@ use super::network::NETWORK_MANAGER_TABLE;
 use serde::{Deserialize, Serialize};
 use serde_json::to_string;
 use super::network::Network;
-use super::network::Credentials;
+use super::network::{Credentials, Endpoint, Quorum, TransactionType, QUORUM_METHODS};
+use super::fees::FeePolicy;
+use crate::error::Error;
 use klave;
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -29,14 +31,14 @@ impl Networks {
         }
     }
 
-    pub fn load() -> Result<Networks, Box<dyn std::error::Error>> {
+    pub fn load() -> Result<Networks, Error> {
         match klave::ledger::get_table(NETWORK_MANAGER_TABLE).get("ALL") {
             Ok(v) => {
                 let wallet: Networks = serde_json::from_slice(&v)?;
                 Ok(wallet)
             },
-            Err(e) => {
-                Err(e.into())
+            Err(_) => {
+                Err(Error::NotFound(String::from("network manager not found, add a network first")))
             }
         }
     }
@@ -50,13 +52,13 @@ impl Networks {
         }
     }
 
-    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn save(&self) -> Result<(), Error> {
         let serialized_nm = to_string(&self)?;
-        klave::ledger::get_table(NETWORK_MANAGER_TABLE).set("ALL", &serialized_nm.as_bytes())?;
+        klave::ledger::get_table(NETWORK_MANAGER_TABLE).set("ALL", serialized_nm.as_bytes())?;
         Ok(())
     }
 
-    pub fn add_network(&mut self, network: &Network) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn add_network(&mut self, network: &Network) -> Result<(), Error> {
         //Check if network exists
         let mut found = false;
         for n in &self.networks {
@@ -66,7 +68,7 @@ impl Networks {
             }
         }
         if found {
-            return Err(format!("network {} already exists", network.name).into());
+            return Err(Error::AlreadyExists(format!("network {} already exists", network.name)));
         }        
 
         network.save()?;
@@ -76,7 +78,7 @@ impl Networks {
         Ok(())
     }
 
-    pub fn remove_network(&mut self, network_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn remove_network(&mut self, network_name: &str) -> Result<(), Error> {
         let mut found = false;
         for n in &self.networks {
             if n == network_name {
@@ -85,7 +87,7 @@ impl Networks {
             }
         }
         if !found {
-            return Err(format!("network {} not found", network_name).into());
+            return Err(Error::NotFound(format!("network {} not found", network_name)));
         }
 
         // Remove the network from the list
@@ -99,28 +101,71 @@ impl Networks {
         Ok(())
     }
 
-    pub fn update_gas_price(&self, network_name: &str, gas_price: u64) -> Result<(), Box<dyn std::error::Error>> {
-        let mut network = self.get_network(&network_name)?;
+    pub fn update_gas_price(&self, network_name: &str, gas_price: u64) -> Result<(), Error> {
+        let mut network = self.get_network(network_name)?;
         network.set_gas_price(Some(gas_price));
         network.save()?;
         Ok(())
     }
 
-    pub fn update_chain_id(&self, network_name: &str, chain_id: u64) -> Result<(), Box<dyn std::error::Error>> {
-        let mut network = self.get_network(&network_name)?;
+    pub fn update_chain_id(&self, network_name: &str, chain_id: u64) -> Result<(), Error> {
+        let mut network = self.get_network(network_name)?;
         network.set_chain_id(Some(chain_id));
         network.save()?;
         Ok(())
     }
 
-    pub fn get_network(&self, name: &str) -> Result<Network, Box<dyn std::error::Error>> {
+    pub fn update_confirmations(&self, network_name: &str, confirmations: u64) -> Result<(), Error> {
+        let mut network = self.get_network(network_name)?;
+        network.set_confirmations(Some(confirmations));
+        network.save()?;
+        Ok(())
+    }
+
+    pub fn update_tx_type(&self, network_name: &str, tx_type: TransactionType) -> Result<(), Error> {
+        let mut network = self.get_network(network_name)?;
+        network.set_tx_type(Some(tx_type));
+        network.save()?;
+        Ok(())
+    }
+
+    pub fn update_endpoints(&self, network_name: &str, endpoints: Vec<Endpoint>) -> Result<(), Error> {
+        let mut network = self.get_network(network_name)?;
+        network.clear_token();
+        network.set_endpoints(endpoints);
+        if let Some(quorum) = network.get_quorum() {
+            quorum.check(network.endpoints().len())?;
+        }
+        network.save()?;
+        Ok(())
+    }
+
+    /// `None` turns quorum reads off.
+    pub fn update_quorum(&self, network_name: &str, quorum: Option<Quorum>) -> Result<(), Error> {
+        let mut network = self.get_network(network_name)?;
+        if let Some(q) = &quorum {
+            q.check(network.endpoints().len())?;
+        }
+        network.set_quorum(quorum);
+        network.save()?;
+        Ok(())
+    }
+
+    pub fn update_fee_policy(&self, network_name: &str, fee_policy: FeePolicy) -> Result<(), Error> {
+        let mut network = self.get_network(network_name)?;
+        network.set_fee_policy(Some(fee_policy));
+        network.save()?;
+        Ok(())
+    }
+
+    pub fn get_network(&self, name: &str) -> Result<Network, Error> {
         for network in &self.networks {
             if network == name {
                 let network = Network::load(network)?;
                 return Ok(network)
             }
         }
-        Err("network not found".into())        
+        Err(Error::NotFound(format!("network {} not found", name)))        
     }
 
     pub fn get_networks(&self) -> &Vec<String> {
@@ -128,20 +173,26 @@ impl Networks {
     }
 
     #[allow(dead_code)]
-    pub fn update_network(&self, network: &Network) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn update_network(&self, network: &Network) -> Result<(), Error> {
         let network_name = network.get_name();
         for n in &self.networks {
             if n == network_name {
-                let mut local_network = self.get_network(&network_name)?;
+                let mut local_network = self.get_network(network_name)?;
                 local_network.set_name(network.get_name());
                 local_network.set_chain_id(network.get_chain_id());
                 local_network.set_rpc_url(network.get_rpc_url());
                 local_network.set_gas_price(network.get_gas_price());
-                match network.get_credentials() {
-                    Some(c) => {
-                        local_network.set_credentials(c);
-                    }
-                    None => {}
+                local_network.set_confirmations(network.get_confirmations());
+                local_network.set_fee_policy(network.get_fee_policy().cloned());
+                local_network.set_tx_type(network.get_tx_type());
+                local_network.set_token_ttl(network.get_token_ttl());
+                local_network.set_stored_headers(network.get_headers().clone());
+                local_network.set_weight(network.get_weight());
+                local_network.set_endpoints(network.get_endpoints().clone());
+                local_network.set_quorum(network.get_quorum());
+                if let Some(c) = network.get_credentials() {
+                    local_network.set_stored_credentials(Some(c.clone()));
+                    local_network.clear_token();
                 }
                 local_network.save()?;
             }
@@ -150,25 +201,36 @@ impl Networks {
     }
 
     #[allow(dead_code)]
-    pub fn set_credentials(&self, network_name: &str, credentials: &Credentials) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn set_credentials(&self, network_name: &str, credentials: &Credentials) -> Result<(), Error> {
         let mut network = self.get_network(network_name)?;
         network.set_credentials(credentials);
+        network.clear_token();
         network.save()?;
         Ok(())
     }
 
-    pub fn send<T>(&self, network_name: &str, method: &str, params: &[&str]) -> Result<T, Box<dyn std::error::Error>> 
+    pub fn send<T>(&self, network_name: &str, method: &str, params: &[&str]) -> Result<T, Error> 
     where
         T: for<'de> Deserialize<'de>,
     {
-        let network = self.get_network(&network_name)?;       
-        let chain_id = match network.get_chain_id() {
-            Some(c) => c,
-            None => 1            
+        let network = self.get_network(network_name)?;       
+        let chain_id = network.get_chain_id().unwrap_or(1);
+
+        let body = |method: &str, params: &[&str]| r#"{"jsonrpc":"2.0","method":""#.to_string() + method + r#"","params":["# + &params.join(",") + r#"],"id":"# + &chain_id.to_string() + r#"}"#;
+        let result = match network.get_quorum() {
+            Some(quorum) if QUORUM_METHODS.contains(&method) => {
+                // Endpoints a block apart answer different latest balances, read them at the agreed head
+                let mut params = params.to_vec();
+                let head;
+                if method == "eth_getBalance" && params.last() == Some(&"\"latest\"") {
+                    head = format!("\"{}\"", network.quorum_request::<String>("eth_blockNumber", &body("eth_blockNumber", &[]), quorum)?);
+                    params.pop();
+                    params.push(&head);
+                }
+                network.quorum_request::<T>(method, &body(method, &params), quorum)?
+            },
+            _ => network.request::<T>(&body(method, params))?
         };
-
-        let body = r#"{"jsonrpc":"2.0","method":""#.to_string() + method + r#"","params":["# + &params.join(",") + r#"],"id":"# + &chain_id.to_string() + r#"}"#;
-        let result = network.request::<T>(&body)?;
         Ok(result)
     }
 }
\ No newline at end of file
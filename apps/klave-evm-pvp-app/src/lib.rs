@@ -1,31 +1,46 @@
 #[allow(warnings)]
 mod bindings;
 
-use std::str::FromStr;
 
 use alloy_consensus::TxEip1559;
-use alloy_primitives::{hex, Address, Bytes, TxKind, U256};
-use alloy_rpc_types_eth::AccessList;
-use alloy_signer::k256::SecretKey;
+use alloy_primitives::{hex, Bytes, TxKind, U256};
 use bindings::Guest;
-use klave;
 use serde_json::Value;
-use crate::klave_networks::{networks::Networks, network::Network};
+use crate::klave_networks::{fees::{self, FeePolicy}, networks::Networks, network::{Endpoint, Network}};
 use solidity::{burnCall, mintCall};
 
 use transactions::Transactions;
+use contract::Contract;
+use contracts::Contracts;
 use transaction::{NetworkTransaction, Participant, PaymentVsPayment, PvPstate, Transaction};
+use settlement::{Leg, Settlement};
+use dvp::{Asset, Delivery, DeliveryVsPayment};
 use wallet::Wallet;
+use hd_wallet::HdRoot;
+use keystore::Keystore;
+use error::Error;
 use users::Users;
-use user::User;
+use user::{RoleType, User, UserRole};
 use alloy_sol_types::SolCall;
 
 
+pub mod abi;
 pub mod klave_networks;
+pub mod contract;
+pub mod contracts;
 pub mod wallet;
 pub mod wallets;
+pub mod hd_wallet;
+pub mod keystore;
+pub mod error;
+pub mod request;
+pub mod response;
+pub mod signature;
+pub mod lock_proof;
 pub mod transactions;
 pub mod transaction;
+pub mod settlement;
+pub mod dvp;
 pub mod users;
 pub mod user;
 pub mod solidity; 
@@ -55,6 +70,13 @@ impl Guest for Component {
         klave::router::add_user_transaction("network_remove");
         klave::router::add_user_transaction("network_set_chain_id");
         klave::router::add_user_transaction("network_set_gas_price");
+        klave::router::add_user_transaction("network_set_confirmations");
+        klave::router::add_user_transaction("network_set_tx_type");
+        klave::router::add_user_transaction("network_set_fee_policy");
+        klave::router::add_user_transaction("network_set_endpoints");
+        klave::router::add_user_transaction("network_set_quorum");
+        klave::router::add_user_query("network_estimate_fees");
+        klave::router::add_user_query("network_endpoints");
         klave::router::add_user_query("networks_all");
 
         klave::router::add_user_transaction("wallet_add");
@@ -62,31 +84,53 @@ impl Guest for Component {
         klave::router::add_user_transaction("wallet_lock");
         klave::router::add_user_transaction("wallet_unlock");
         klave::router::add_user_query("wallet_address");
-        klave::router::add_user_query("wallet_secret_key");
+        klave::router::add_user_transaction("wallet_import_keystore");
+        klave::router::add_user_query("wallet_export_keystore");
+        klave::router::add_user_query("wallet_sign_message");
+        klave::router::add_user_query("wallet_sign_typed_data");
+        klave::router::add_user_query("wallet_verify_signature");
+        klave::router::add_user_query("wallet_verify_lock_proof");
+        klave::router::add_user_query("wallet_position");
         klave::router::add_user_query("wallet_public_key");
         klave::router::add_user_query("wallet_balance");
         klave::router::add_user_query("wallet_networks");
-        klave::router::add_user_query("wallet_transfer");
-        klave::router::add_user_query("wallet_deploy_contract");
-        klave::router::add_user_query("wallet_call_contract");        
+        klave::router::add_user_transaction("wallet_transfer");
+        klave::router::add_user_transaction("wallet_deploy_contract");
+        klave::router::add_user_transaction("wallet_call_contract");        
+        klave::router::add_user_transaction("wallet_resync_nonce");
         klave::router::add_user_query("wallets_all_for_user");
         klave::router::add_user_query("wallets_all");
+        klave::router::add_user_transaction("hd_root_create");
+        klave::router::add_user_transaction("hd_root_import");
+        klave::router::add_user_query("hd_root_info");
 
         klave::router::add_user_transaction("user_add");
         klave::router::add_user_query("user_get");
         klave::router::add_user_query("users_all");
         klave::router::add_user_transaction("user_add_wallet");
+        klave::router::add_user_transaction("user_set_role");
         klave::router::add_user_transaction("transaction_add");
+        klave::router::add_user_transaction("settlement_add");
+        klave::router::add_user_transaction("delivery_vs_payment_add");
         klave::router::add_user_query("transaction_get");
         klave::router::add_user_transaction("transaction_commit");
         klave::router::add_user_transaction("transaction_apply");
+        klave::router::add_user_transaction("transaction_cancel");
+        klave::router::add_user_transaction("transaction_fund_escrow_gas");
         klave::router::add_user_query("transactions_all_for_user");    
 
+        klave::router::add_user_transaction("contract_add");
+        klave::router::add_user_transaction("contract_remove");
+        klave::router::add_user_transaction("contract_confirm_deployment");
+        klave::router::add_user_query("contract_get");
+        klave::router::add_user_query("contracts_all");
+
         klave::router::add_user_query(&String::from("eth_block_number"));
         klave::router::add_user_query(&String::from("eth_get_block_by_number"));
         klave::router::add_user_query(&String::from("eth_gas_price"));
         klave::router::add_user_query(&String::from("eth_estimate_gas"));
         klave::router::add_user_query(&String::from("eth_call_contract"));
+        klave::router::add_user_query(&String::from("eth_call_function"));
         klave::router::add_user_query(&String::from("eth_protocol_version"));
         klave::router::add_user_query(&String::from("eth_chain_id"));
         klave::router::add_user_query(&String::from("eth_get_transaction_by_hash"));
@@ -102,919 +146,1441 @@ impl Guest for Component {
     }
     
     fn network_add(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-            return
-        };
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
+            return;
+        }
 
-        let network_name = match v["network_name"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: network not found"));
+        let req = match request::parse::<request::NetworkAdd>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
-        let chain_id = v["chain_id"].as_u64();
-        let rpc_url = match v["rpc_url"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: rpc_url not found"));
+
+        let mut network = Network::new(&req.network_name, req.chain_id, &req.rpc_url, req.gas_price, None);
+        network.set_confirmations(req.confirmations);
+        network.set_tx_type(req.tx_type);
+        network.set_fee_policy(req.fee_policy);
+        network.set_token_ttl(req.token_ttl);
+        network.set_headers(req.headers.unwrap_or_default());
+        network.set_weight(req.weight);
+        network.set_endpoints(req.endpoints.iter().map(|e| Endpoint::new(&e.rpc_url, e.credentials.as_ref(), e.weight)).collect());
+        if let Some(credentials) = &req.credentials {
+            network.set_credentials(credentials);
+        }
+        if let Some(quorum) = &req.quorum {
+            if let Err(e) = quorum.check(network.endpoints().len()) {
+                response::error(e);
                 return;
             }
-        };
-        let gas_price = v["gas_price"].as_u64();
-        let credentials = v["credentials"].as_str();
-        let network = Network::new(network_name, chain_id, rpc_url, gas_price, credentials);        
+        }
+        network.set_quorum(req.quorum);
 
         let mut nm = Networks::get();
         match nm.add_network(&network) {
             Ok(_) => {
-                klave::notifier::send_string(&format!("network '{}' added", network_name));
+                response::success(format!("network '{}' added", req.network_name));
             },
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to add network '{}': {}", network_name, e));
+                response::error(e.context(format!("failed to add network '{}'", req.network_name)));
             }
         }
     }
 
     fn network_remove(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-            return
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::NetworkRemove>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
         };
 
         let mut nm = match Networks::load() {
             Ok(nm) => nm,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+                response::error(e.context("failed to load network manager"));                
                 return
             }
         };
 
-        let network_name = match v["network_name"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: network_name not found"));
-                return;
-            }
-        };
-        match nm.remove_network(network_name) {
+        match nm.remove_network(&req.network_name) {
             Ok(_) => {
-                klave::notifier::send_string(&format!("network '{}' added", network_name));
+                response::success(format!("network '{}' removed", req.network_name));
             },
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to add network '{}': {}", network_name, e));
+                response::error(e.context(format!("failed to remove network '{}'", req.network_name)));
             }
         }
     }
 
     fn network_set_chain_id(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-            return
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::NetworkSetChainId>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
         };
-    
+
         let nm = match Networks::load() {
             Ok(nm) => nm,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+                response::error(e.context("failed to load network manager"));                
                 return
             }
         };
 
-        let network_name = match v["network_name"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: network_name not found"));
-                return;
-            }
-        };
-        let chain_id = match v["chain_id"].as_u64() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: chain_id not found"));
-                return;
-            }
-        };
-        match nm.update_chain_id(network_name, chain_id) {
+        match nm.update_chain_id(&req.network_name, req.chain_id) {
             Ok(_) => {
-                klave::notifier::send_string(&format!("chain_id '{}' set as current", chain_id));
+                response::success(format!("chain_id '{}' set as current", req.chain_id));
             },
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to set chain_id '{}': {}", chain_id, e));
+                response::error(e.context(format!("failed to set chain_id '{}'", req.chain_id)));
             }
         }
     }
 
     fn network_set_gas_price(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-            return
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::NetworkSetGasPrice>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
         };
-    
+
         let nm = match Networks::load() {
             Ok(nm) => nm,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+                response::error(e.context("failed to load network manager"));                
                 return
             }
         };
 
-        let network_name = match v["network_name"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: network_name not found"));
+        match nm.update_gas_price(&req.network_name, req.gas_price) {
+            Ok(_) => {
+                response::success(format!("gas_price '{}' set as current", req.gas_price));
+            },
+            Err(e) => {
+                response::error(e.context(format!("failed to set gas_price '{}'", req.gas_price)));
+            }
+        }
+    }
+
+    fn network_set_confirmations(cmd: String){
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::NetworkSetConfirmations>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
-        let gas_price = match v["gas_price"].as_u64() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: gas_price not found"));
-                return;
+
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));                
+                return
             }
         };
-        match nm.update_gas_price(network_name, gas_price) {
+
+        match nm.update_confirmations(&req.network_name, req.confirmations) {
             Ok(_) => {
-                klave::notifier::send_string(&format!("gas_price '{}' set as current", gas_price));
+                response::success(format!("confirmations '{}' set as current", req.confirmations));
             },
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to set gas_price '{}': {}", gas_price, e));
+                response::error(e.context(format!("failed to set confirmations '{}'", req.confirmations)));
             }
         }
     }
 
-    fn networks_all(_cmd: String){
+    fn network_set_tx_type(cmd: String){
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::NetworkSetTxType>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+
         let nm = match Networks::load() {
             Ok(nm) => nm,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+                response::error(e.context("failed to load network manager"));                
                 return
             }
         };
 
-        let mut networks: Vec<String> = Vec::<String>::new();
-        for network_name in nm.get_networks() {
-            let network = match Network::load(network_name) {
-                Ok(n) => n.to_string(),
-                Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to load network '{}': {}", network_name, e));
-                    return;
-                }
-            };
-            networks.push(network);
+        match nm.update_tx_type(&req.network_name, req.tx_type) {
+            Ok(_) => {
+                response::success(format!("tx_type '{:?}' set as current", req.tx_type));
+            },
+            Err(e) => {
+                response::error(e.context(format!("failed to set tx_type '{:?}'", req.tx_type)));
+            }
         }
-
-        klave::notifier::send_string(&format!("{}", &serde_json::to_string(&networks).unwrap()));
     }
 
-    fn wallet_add(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
+    fn network_set_fee_policy(cmd: String){
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
             return;
-        };
-        
-        let (secret_key, public_key) = match wallet::generate_keypair(v["secret_key"].as_str()) {
-            Ok((s, p)) => (s, p),
+        }
+
+        let req = match request::parse::<request::NetworkSetFeePolicy>(&cmd) {
+            Ok(r) => r,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to generate keypair: {}", e));
+                response::error(e);
                 return;
             }
         };
-        let wallet = Wallet::new(&secret_key, &public_key);        
-        let eth_address = wallet.get_eth_address();
 
-        let mut wallets = wallets::Wallets::get();
-        let mut found = false;
-        for w in wallets.get_list_address() {
-            if w.address == eth_address {
-                found = true;
-                break;
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));                
+                return
+            }
+        };
+
+        let default_policy = FeePolicy::default();
+        let fee_policy = FeePolicy {
+            base_fee_multiplier: req.base_fee_multiplier.unwrap_or(default_policy.base_fee_multiplier),
+            priority_fee_multiplier: req.priority_fee_multiplier.unwrap_or(default_policy.priority_fee_multiplier),
+            max_fee_per_gas_cap: req.max_fee_per_gas_cap,
+            max_priority_fee_per_gas_cap: req.max_priority_fee_per_gas_cap,
+        };
+        match nm.update_fee_policy(&req.network_name, fee_policy) {
+            Ok(_) => {
+                response::success(format!("fee policy set for network '{}'", req.network_name));
+            },
+            Err(e) => {
+                response::error(e.context(format!("failed to set fee policy for network '{}'", req.network_name)));
             }
         }
+    }
 
-        if found {
-            klave::notifier::send_string(&format!("ERROR: wallet {} already exists", eth_address));
+    fn network_set_endpoints(cmd: String){
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
             return;
         }
 
-        match wallet.save() {
-            Ok(_) => (),
+        let req = match request::parse::<request::NetworkSetEndpoints>(&cmd) {
+            Ok(r) => r,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to save wallet: {}", e));
+                response::error(e);
                 return;
             }
         };
 
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));                
+                return
+            }
+        };
 
-        match wallets.add_address(&eth_address) {
+        let endpoints = req.endpoints.iter().map(|e| Endpoint::new(&e.rpc_url, e.credentials.as_ref(), e.weight)).collect();
+        match nm.update_endpoints(&req.network_name, endpoints) {
             Ok(_) => {
-                klave::notifier::send_string(&format!("wallet '{}' added", eth_address));
+                response::success(format!("{} fallback endpoints set for network '{}'", req.endpoints.len(), req.network_name));
             },
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to add wallet '{}': {}", eth_address, e));
+                response::error(e.context(format!("failed to set endpoints of network '{}'", req.network_name)));
             }
         }
     }
 
-    fn wallet_add_network(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
+    fn network_set_quorum(cmd: String){
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
             return;
-        };
+        }
 
-        let network_name = match v["network_name"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: network_name not found"));
+        let req = match request::parse::<request::NetworkSetQuorum>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
 
-        let eth_address = match v["eth_address"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: eth_address not found"));
-                return;
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));                
+                return
             }
         };
 
-        let mut wallet = match Wallet::load(eth_address) {
-            Ok(w) => w,
+        match nm.update_quorum(&req.network_name, req.quorum) {
+            Ok(_) => {
+                response::success(match req.quorum {
+                    Some(q) => format!("quorum of {} out of {} endpoints set for network '{}'", q.threshold, q.size, req.network_name),
+                    None => format!("quorum reads disabled for network '{}'", req.network_name)
+                });
+            },
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
-                return;
+                response::error(e.context(format!("failed to set quorum of network '{}'", req.network_name)));
             }
-        };
-
-        match wallet.add_network(network_name) {
-            Ok(_) => klave::notifier::send_string(&format!("new network {} added to wallet", network_name)),
-            Err(e) => klave::notifier::send_string(&format!("ERROR: failed to add network {}: {}", network_name, e))
-        };        
+        }
     }
 
-    fn wallet_lock(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
+    fn network_estimate_fees(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
             return;
-        };
+        }
 
-        let eth_address = match v["eth_address"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: eth_address not found"));
+        let req = match request::parse::<request::NetworkEstimateFees>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
 
-        let mut wallet = match Wallet::load(eth_address) {
-            Ok(w) => w,
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
-                return;
+                response::error(e.context("failed to load network manager"));                
+                return
             }
         };
 
-        let value = match v["value"].as_str() {
-            Some(c) => {
-                match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to parse value: {}", e));
-                        return;
-                    }
-                }
-            },
-            None => {
-                klave::notifier::send_string(&format!("ERROR: value not found"));
-                return;
-            }
-        };
+        match fees::estimate_fees(&nm, &req.network_name) {
+            Ok(f) => response::success(&f),
+            Err(e) => response::error(e.context("failed to estimate fees"))
+        }
+    }
 
-        let balance = match v["balance"].as_str() {
-            Some(c) => {
-                match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to parse balance: {}", e));
-                        return;
-                    }
-                }
-            },
-            None => {
-                klave::notifier::send_string(&format!("ERROR: balance not found"));
+    /// Reports the endpoints of a network in failover order, with the calls they served and their last error.
+    fn network_endpoints(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::NetworkEndpoints>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
 
-        let network_name = match v["network_name"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: network_name not found"));
+        let network = match Network::load(&req.network_name) {
+            Ok(n) => n,
+            Err(e) => {
+                response::error(e.context(format!("failed to load network '{}'", req.network_name)));
                 return;
             }
         };
 
-        match wallet.lock(network_name, value, balance) {
-            Ok(proof) => klave::notifier::send_string(&format!("locked {} for wallet. here's the proof: {}", value, proof)),
-            Err(e) => klave::notifier::send_string(&format!("ERROR: failed to lock {} for wallet: {}", value, e))
-        };        
+        let statuses: Vec<_> = network.endpoints().iter().map(|e| network.endpoint_status(e)).collect();
+        response::success(&statuses);
     }
 
-    fn wallet_unlock(cmd: String) {
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
+    fn networks_all(_cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
             return;
-        };
+        }
 
-        let eth_address = match v["eth_address"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: eth_address not found"));
-                return;
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));                
+                return
             }
         };
 
-        let mut wallet = match Wallet::load(eth_address) {
-            Ok(w) => w,
+        let mut networks: Vec<String> = Vec::<String>::new();
+        for network_name in nm.get_networks() {
+            let network = match Network::load(network_name) {
+                Ok(n) => n.to_string(),
+                Err(e) => {
+                    response::error(e.context(format!("failed to load network '{}'", network_name)));
+                    return;
+                }
+            };
+            networks.push(network);
+        }
+
+        response::success(&networks);
+    }
+
+    fn wallet_add(cmd: String){
+        let mut user = match user::authorize(user::ANY_ROLE) {
+            Ok(u) => u,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                response::error(e);
                 return;
             }
         };
 
-        let value = match v["value"].as_str() {
-            Some(c) => {
-                match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to parse value: {}", e));
-                        return;
-                    }
+        // Plaintext keys are refused, keys are imported with wallet_import_keystore.
+        if let Err(e) = request::parse::<request::WalletAdd>(&cmd) {
+            response::error(e);
+            return;
+        }
+
+        // New keys are derived from the HD root when there is one.
+        let wallet = match HdRoot::load() {
+            Ok(mut root) => match Wallet::derive(&mut root) {
+                Ok(w) => w,
+                Err(e) => {
+                    response::error(e.context("failed to derive wallet"));
+                    return;
                 }
             },
-            None => {
-                klave::notifier::send_string(&format!("ERROR: value not found"));
-                return;
+            Err(_) => match wallet::generate_keypair(None) {
+                Ok((s, p)) => Wallet::new(&s, &p),
+                Err(e) => {
+                    response::error(e.context("failed to generate keypair"));
+                    return;
+                }
             }
         };
+        let eth_address = wallet.get_eth_address();
 
-        let network_name = match v["network_name"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: network_name not found"));
-                return;
+        let mut wallets = wallets::Wallets::get();
+        let mut found = false;
+        for w in wallets.get_list_address() {
+            if w.address == eth_address {
+                found = true;
+                break;
             }
-        };
-
-        match wallet.unlock(network_name, value) {
-            Ok(proof) => klave::notifier::send_string(&format!("unlocked {} for wallet. here's the proof: {}", value, proof)),
-            Err(e) => klave::notifier::send_string(&format!("ERROR: failed to unlock {} for wallet: {}", value, e))
-        };        
-
-    }
+        }
 
-    fn wallet_address(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
+        if found {
+            response::error(Error::AlreadyExists(format!("wallet {} already exists", eth_address)));
             return;
-        };
+        }
 
-        let eth_address = match v["eth_address"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: eth_address not found"));
+        match wallet.save() {
+            Ok(_) => (),
+            Err(e) => {
+                response::error(e.context("failed to save wallet"));
                 return;
             }
         };
 
-        let wallet = match Wallet::load(eth_address) {
-            Ok(w) => w,
+
+        match wallets.add_address(eth_address) {
+            Ok(_) => (),
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                response::error(e.context(format!("failed to add wallet '{}'", eth_address)));
                 return;
             }
-        };
+        }
 
-        klave::notifier::send_string(&wallet.get_eth_address());
+        // The creator owns the new wallet.
+        match user.add_wallet(eth_address) {
+            Ok(_) => {
+                response::success(format!("wallet '{}' added", eth_address));
+            },
+            Err(e) => {
+                response::error(e.context(format!("failed to add wallet '{}' to user '{}'", eth_address, user.id)));
+            }
+        }
     }
 
-    fn wallet_secret_key(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-            return;
-        };
-
-        let eth_address = match v["eth_address"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: eth_address not found"));
+    fn wallet_add_network(cmd: String){
+        let req = match request::parse::<request::WalletAddNetwork>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
+        let eth_address = req.eth_address.to_string();
+
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
+        }
 
-        let wallet = match Wallet::load(eth_address) {
+        let mut wallet = match Wallet::load(&eth_address) {
             Ok(w) => w,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                response::error(e.context("failed to load wallet"));
                 return;
             }
         };
 
-        klave::notifier::send_string(&wallet.get_secret_key());
+        match wallet.add_network(&req.network_name) {
+            Ok(_) => response::success(format!("new network {} added to wallet", req.network_name)),
+            Err(e) => response::error(e.context(format!("failed to add network {}", req.network_name)))
+        };
     }
 
-    fn wallet_public_key(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-            return;
-        };
-
-        let eth_address = match v["eth_address"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: eth_address not found"));
+    fn wallet_lock(cmd: String){
+        let req = match request::parse::<request::WalletLock>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
+        let eth_address = req.eth_address.to_string();
+
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
+        }
 
-        let wallet = match Wallet::load(eth_address) {
+        let mut wallet = match Wallet::load(&eth_address) {
             Ok(w) => w,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                response::error(e.context("failed to load wallet"));
                 return;
             }
         };
 
-        klave::notifier::send_string(&wallet.get_public_key());
-    }
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));
+                return
+            }
+        };
 
-    fn wallet_networks(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-            return;
+        match wallet.lock(&nm, &req.network_name, req.value) {
+            Ok(proof) => response::success(serde_json::json!({"locked": req.value, "proof": proof})),
+            Err(e) => response::error(e.context(format!("failed to lock {} for wallet", req.value)))
         };
+    }
 
-        let eth_address = match v["eth_address"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: eth_address not found"));
+    fn wallet_unlock(cmd: String) {
+        let req = match request::parse::<request::WalletUnlock>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
+        let eth_address = req.eth_address.to_string();
+
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
+        }
 
-        let wallet = match Wallet::load(eth_address) {
+        let mut wallet = match Wallet::load(&eth_address) {
             Ok(w) => w,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                response::error(e.context("failed to load wallet"));
                 return;
             }
         };
 
-        let local_networks = wallet.get_networks();
-        let local_networks_str: Vec<String> = local_networks.iter().map(|network| format!("{}", serde_json::to_string(&network).unwrap())).collect();
-        klave::notifier::send_string(&format!("{}", serde_json::to_string(&local_networks_str).unwrap()));
+        match wallet.unlock(&req.network_name, req.value) {
+            Ok(proof) => response::success(serde_json::json!({"unlocked": req.value, "proof": proof})),
+            Err(e) => response::error(e.context(format!("failed to unlock {} for wallet", req.value)))
+        };
+
     }
 
-    fn wallet_transfer(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-            return;
-        };    
-    
-        let chain_id = match v["chainId"].as_u64() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: chainId not found"));
+    fn wallet_address(cmd: String){
+        let req = match request::parse::<request::WalletAddress>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
-        let nonce = match v["nonce"].as_u64() {
-            Some(n) => n,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: nonce not found"));
+        let eth_address = req.eth_address.to_string();
+
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
+        }
+
+        let wallet = match Wallet::load(&eth_address) {
+            Ok(w) => w,
+            Err(e) => {
+                response::error(e.context("failed to load wallet"));
                 return;
             }
         };
-        let gas_limit = match v["gasLimit"].as_u64(){
-            Some(g) => g,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: gasLimit not found"));
+
+        response::success(wallet.get_eth_address());
+    }
+
+    fn wallet_import_keystore(cmd: String){
+        let mut user = match user::authorize(user::ANY_ROLE) {
+            Ok(u) => u,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
-        let to_str = match v["to"].as_str(){
-            Some(t) => t,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: to not found"));
+
+        let req = match request::parse::<request::WalletImportKeystore>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
-        let to = match Address::from_str(&to_str) {
-            Ok(a) => a,
+        let keystore = req.keystore;
+
+        let secret_key = match keystore.decrypt(&req.password) {
+            Ok(k) => k,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to parse address: {}", e));
+                response::error(e.context("failed to decrypt keystore"));
                 return;
             }
         };
-        let value = match v["value"].as_str() {
-            Some(c) => {
-                match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to parse value: {}", e));
-                        return;
-                    }
-                }
-            },
-            None => {
-                klave::notifier::send_string(&format!("ERROR: value not found"));
+        let (secret_key, public_key) = match wallet::generate_keypair(Some(&hex::encode(secret_key))) {
+            Ok((s, p)) => (s, p),
+            Err(e) => {
+                response::error(e.context("failed to import key"));
                 return;
             }
         };
-        let max_fee_per_gas = match v["maxFeePerGas"].as_u64() {
-            Some(m) => m,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: maxFeePerGas not found"));
+        let wallet = Wallet::new(&secret_key, &public_key);
+        let eth_address = wallet.get_eth_address();
+        if let Some(address) = &keystore.address {
+            if !eth_address.trim_start_matches("0x").eq_ignore_ascii_case(address.trim_start_matches("0x")) {
+                response::error(Error::InvalidRequest(format!("keystore address {} does not match key address {}", address, eth_address)));
                 return;
             }
-        };
-        let max_priority_fee_per_gas = match v["maxPriorityFeePerGas"].as_u64() {
-            Some(m) => m,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: maxPriorityFeePerGas not found"));
+        }
+
+        let mut wallets = wallets::Wallets::get();
+        if wallets.get_list_address().iter().any(|w| w.address == eth_address) {
+            response::error(Error::AlreadyExists(format!("wallet {} already exists", eth_address)));
+            return;
+        }
+        if let Err(e) = wallet.save() {
+            response::error(e.context("failed to save wallet"));
+            return;
+        }
+        if let Err(e) = wallets.add_address(eth_address) {
+            response::error(e.context(format!("failed to add wallet '{}'", eth_address)));
+            return;
+        }
+        match user.add_wallet(eth_address) {
+            Ok(_) => response::success(format!("wallet '{}' imported", eth_address)),
+            Err(e) => response::error(e.context(format!("failed to add wallet '{}' to user '{}'", eth_address, user.id)))
+        }
+    }
+
+    fn wallet_export_keystore(cmd: String){
+        let req = match request::parse::<request::WalletExportKeystore>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
-    
-        let tx = TxEip1559 {
-            chain_id: chain_id,
-            nonce: nonce,
-            gas_limit: gas_limit,
-            to: to.into(),
-            value: value,
-            input: Bytes::new(),
-            max_fee_per_gas: max_fee_per_gas as u128,
-            max_priority_fee_per_gas: max_priority_fee_per_gas as u128,
-            access_list: AccessList::default(),
-        };
+        let eth_address = req.eth_address.to_string();
+        let use_pbkdf2 = req.kdf.unwrap_or_default() == request::Kdf::Pbkdf2;
 
-        let network_name = match v["network_name"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: network not found"));
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
+        }
+
+        let wallet = match Wallet::load(&eth_address) {
+            Ok(w) => w,
+            Err(e) => {
+                response::error(e.context("failed to load wallet"));
                 return;
             }
         };
-        let eth_address = match v["eth_address"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: eth_address not found"));
+
+        if let Err(e) = wallet.ensure_not_escrow() {
+            response::error(e);
+            return;
+        }
+        let secret_key = match hex::decode(wallet.get_secret_key()) {
+            Ok(k) => k,
+            Err(e) => {
+                response::error(Error::from(e).context("failed to decode secret key"));
                 return;
             }
         };
+        match Keystore::encrypt(&secret_key, wallet.get_eth_address(), &req.password, use_pbkdf2) {
+            Ok(keystore) => response::success(&keystore),
+            Err(e) => response::error(e.context("failed to encrypt keystore"))
+        }
+    }
 
-        let mut wallet = match Wallet::load(eth_address) {
-            Ok(w) => w,
+    fn wallet_sign_message(cmd: String){
+        let req = match request::parse::<request::WalletSignMessage>(&cmd) {
+            Ok(r) => r,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                response::error(e);
                 return;
             }
         };
-
-        let nm = match Networks::load() {
-            Ok(nm) => nm,
+        let eth_address = req.eth_address.to_string();
+        let message = match signature::message_bytes(&req.message, req.encoding.as_deref()) {
+            Ok(b) => b,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
-                return
+                response::error(Error::InvalidRequest(format!("failed to parse message: {}", e)));
+                return;
             }
         };
 
-        match wallet.sign_and_send(&nm, network_name, tx, false) {
-            Ok(result) => klave::notifier::send_string(&result),
-            Err(e) => klave::notifier::send_string(&format!("ERROR: failed to send transaction: {}", e))
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
         }
-    }
 
-    fn wallet_deploy_contract(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-            return;
-        };    
-    
-        let chain_id = match v["chainId"].as_u64() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: chainId not found"));
+        let wallet = match Wallet::load(&eth_address) {
+            Ok(w) => w,
+            Err(e) => {
+                response::error(e.context("failed to load wallet"));
                 return;
             }
         };
-        let nonce = match v["nonce"].as_u64() {
-            Some(n) => n,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: nonce not found"));
+
+        // A signature of the escrow, such as an EIP-2612 permit, could move the funds it holds
+        if let Err(e) = wallet.ensure_not_escrow() {
+            response::error(e);
+            return;
+        }
+        match wallet.sign_message(&message) {
+            Ok(sig) => response::success(signature::to_json(&sig, &signature::message_hash(&message))),
+            Err(e) => response::error(e.context("failed to sign message"))
+        }
+    }
+
+    fn wallet_sign_typed_data(cmd: String){
+        let req = match request::parse::<request::WalletSignTypedData>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
-        let gas_limit = match v["gasLimit"].as_u64(){
-            Some(g) => g,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: gasLimit not found"));
+        let eth_address = req.eth_address.to_string();
+        let hash = match signature::typed_data_hash(&req.typed_data) {
+            Ok(h) => h,
+            Err(e) => {
+                response::error(e.context("failed to hash typed_data"));
                 return;
             }
         };
-        let input = match v["input"].as_str() {
-            Some(v) => v,            
-            None => {
-                klave::notifier::send_string(&format!("ERROR: data not found"));
+        if lock_proof::is_attestation_domain(&req.typed_data) {
+            response::error(Error::Unauthorized(String::from("lock attestations are only signed by the enclave")));
+            return;
+        }
+
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
+        }
+
+        let wallet = match Wallet::load(&eth_address) {
+            Ok(w) => w,
+            Err(e) => {
+                response::error(e.context("failed to load wallet"));
                 return;
             }
         };
-        let max_fee_per_gas = match v["maxFeePerGas"].as_u64() {
-            Some(m) => m,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: maxFeePerGas not found"));
+
+        // A signature of the escrow, such as an EIP-2612 permit, could move the funds it holds
+        if let Err(e) = wallet.ensure_not_escrow() {
+            response::error(e);
+            return;
+        }
+        match wallet.sign_typed_data(&req.typed_data) {
+            Ok(sig) => response::success(signature::to_json(&sig, &hash)),
+            Err(e) => response::error(e.context("failed to sign typed_data"))
+        }
+    }
+
+    fn wallet_verify_signature(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::WalletVerifySignature>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
-        let max_priority_fee_per_gas = match v["maxPriorityFeePerGas"].as_u64() {
-            Some(m) => m,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: maxPriorityFeePerGas not found"));
+
+        // The signed hash comes from a personal_sign message, a typed data document or is given as is.
+        let hash = match (&req.message, &req.typed_data, req.hash) {
+            (Some(m), _, _) => match signature::message_bytes(m, req.encoding.as_deref()) {
+                Ok(b) => signature::message_hash(&b),
+                Err(e) => {
+                    response::error(Error::InvalidRequest(format!("failed to parse message: {}", e)));
+                    return;
+                }
+            },
+            (None, Some(t), _) => match signature::typed_data_hash(t) {
+                Ok(h) => h,
+                Err(e) => {
+                    response::error(e.context("failed to hash typed_data"));
+                    return;
+                }
+            },
+            (None, None, Some(h)) => h,
+            (None, None, None) => {
+                response::error(Error::InvalidRequest(String::from("one of message, typed_data or hash is required")));
                 return;
             }
         };
-    
-        let tx = TxEip1559 {
-            chain_id: chain_id,
-            nonce: nonce,
-            gas_limit: gas_limit,
-            to: TxKind::Create,
-            value: U256::default(),
-            input: hex::decode(input.trim_start_matches("0x")).unwrap().into(),
-            max_fee_per_gas: max_fee_per_gas as u128,
-            max_priority_fee_per_gas: max_priority_fee_per_gas as u128,
-            access_list: AccessList::default(),
-        };
+        match signature::recover(&req.signature, &hash) {
+            Ok(recovered) => response::success(serde_json::json!({
+                "valid": recovered == req.address,
+                "recovered": recovered.to_string()
+            })),
+            Err(e) => response::error(e.context("failed to recover signer"))
+        }
+    }
 
-        let network_name = match v["network_name"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: network not found"));
+    fn wallet_verify_lock_proof(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::WalletVerifyLockProof>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
-        let eth_address = match v["eth_address"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: eth_address not found"));
-                return;
+
+        // The proof is either given under `proof` or as the command itself
+        let proof = match req.proof {
+            Some(p) => p,
+            None => match serde_json::from_str::<Value>(&cmd) {
+                Ok(p) => p,
+                Err(e) => {
+                    response::error(Error::InvalidRequest(format!("failed to parse proof: {}", e)));
+                    return;
+                }
             }
         };
 
-        let mut wallet = match Wallet::load(eth_address) {
-            Ok(w) => w,
+        match lock_proof::verify(&proof) {
+            Ok((valid, recovered)) => response::success(serde_json::json!({
+                "valid": valid,
+                "recovered": recovered.to_string(),
+                "attestation": proof["typed_data"]["message"]
+            })),
+            Err(e) => response::error(e.context("failed to verify lock proof"))
+        }
+    }
+
+    fn wallet_public_key(cmd: String){
+        let req = match request::parse::<request::WalletPublicKey>(&cmd) {
+            Ok(r) => r,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                response::error(e);
                 return;
             }
         };
+        let eth_address = req.eth_address.to_string();
 
-        let nm = match Networks::load() {
-            Ok(nm) => nm,
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
+        }
+
+        let wallet = match Wallet::load(&eth_address) {
+            Ok(w) => w,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
-                return
+                response::error(e.context("failed to load wallet"));
+                return;
             }
         };
 
-        let trace = match v["trace"].as_bool() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: trace not found"));
+        response::success(wallet.get_public_key());
+    }
+
+    fn wallet_networks(cmd: String){
+        let req = match request::parse::<request::WalletNetworks>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
+        let eth_address = req.eth_address.to_string();
 
-        match wallet.sign_and_send(&nm, network_name, tx, trace) {
-            Ok(result) => klave::notifier::send_string(&result),
-            Err(e) => klave::notifier::send_string(&format!("ERROR: failed to send transaction: {}", e))
-        }
-    }
-
-    fn wallet_balance(cmd: String){
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
             return;
-        };
+        }
 
-        let network_name = match v["network_name"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: network not found"));
+        let wallet = match Wallet::load(&eth_address) {
+            Ok(w) => w,
+            Err(e) => {
+                response::error(e.context("failed to load wallet"));
                 return;
             }
         };
-        let eth_address = match v["eth_address"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: eth_address not found"));
+
+        let local_networks = wallet.get_networks();
+        let local_networks_str: Vec<String> = local_networks.iter().map(|network| serde_json::to_string(&network).unwrap().to_string()).collect();
+        response::success(&local_networks_str);
+    }
+
+    fn wallet_transfer(cmd: String){
+        let req = match request::parse::<request::WalletTransfer>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
+        let eth_address = req.eth_address.to_string();
+        let network_name = req.network_name.as_str();
+
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
+        }
 
-        let wallet = match Wallet::load(eth_address) {
+        let mut wallet = match Wallet::load(&eth_address) {
             Ok(w) => w,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                response::error(e.context("failed to load wallet"));
                 return;
             }
         };
 
+        if let Err(e) = wallet.ensure_not_escrow() {
+            response::error(e);
+            return;
+        }
+
         let nm = match Networks::load() {
             Ok(nm) => nm,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+                response::error(e.context("failed to load network manager"));
                 return
             }
         };
-        
-        match wallet.get_balance(&nm, network_name) {            
-            Ok(result) => klave::notifier::send_string(&result),
-            Err(e) => klave::notifier::send_string(&format!("ERROR: failed to send balance: {}", e))
-        }
-    }    
-
-    fn wallet_call_contract(cmd: String) {
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-            return;
-        };
 
-        let contract_owner_address = match v["contract_owner_address"].as_str() {
-            Some(c) => match c.parse::<Address>() {
-                Ok(a) => a,
+        let nonce = match req.nonce {
+            Some(n) => n,
+            None => match wallet.next_nonce(&nm, network_name) {
+                Ok(n) => n,
                 Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to parse contract owner address: {}", e));
+                    response::error(e.context("failed to resolve nonce"));
                     return;
                 }
-            },
-            None => {
-                klave::notifier::send_string(&format!("ERROR: contract owner address not found"));
-                return;
             }
         };
 
-        let contract_address = match v["contract_address"].as_str() {
-            Some(c) => match c.parse::<Address>() {
-                Ok(a) => a,
-                Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to parse contract address: {}", e));
-                    return;
-                }
-            },
-            None => {
-                klave::notifier::send_string(&format!("ERROR: contract address not found"));
+        let (max_fee_per_gas, max_priority_fee_per_gas) = match fees::resolve_fees(&nm, network_name, req.max_fee_per_gas.map(u128::from), req.max_priority_fee_per_gas.map(u128::from)) {
+            Ok(f) => (f.max_fee_per_gas, f.max_priority_fee_per_gas),
+            Err(e) => {
+                response::error(e.context("failed to estimate fees"));
                 return;
             }
         };
 
-        let recipient_address = match v["recipient_address"].as_str() {
-            Some(c) => match c.parse::<Address>() {
-                Ok(a) => a,
+        let mut tx = TxEip1559 {
+            chain_id: req.chain_id,
+            nonce,
+            gas_limit: req.gas_limit.unwrap_or_default(),
+            to: req.to.into(),
+            value: req.value,
+            input: Bytes::new(),
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            access_list: req.access_list.unwrap_or_default(),
+        };
+
+        if req.gas_limit.is_none() {
+            tx.gas_limit = match fees::estimate_gas(&nm, network_name, &eth_address, &tx) {
+                Ok(g) => g,
                 Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to parse recipient address: {}", e));
+                    response::error(e.context("failed to estimate gas"));
                     return;
                 }
-            },
-            None => {
-                klave::notifier::send_string(&format!("ERROR: recipient address not found"));
+            };
+        }
+
+        match wallet.sign_and_send(&nm, network_name, tx, false, None) {
+            Ok(result) => response::success(&result),
+            Err(e) => response::error(e.context("failed to send transaction"))
+        }
+    }
+
+    fn wallet_deploy_contract(cmd: String){
+        let req = match request::parse::<request::WalletDeployContract>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
-        let value = match v["value"].as_str() {
-            Some(c) => {
-                match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to parse value: {}", e));
-                        return;
-                    }
-                }
-            },
-            None => {
-                klave::notifier::send_string(&format!("ERROR: value not found"));
+        let eth_address = req.eth_address.to_string();
+        let network_name = req.network_name.as_str();
+
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
+        }
+
+        let mut wallet = match Wallet::load(&eth_address) {
+            Ok(w) => w,
+            Err(e) => {
+                response::error(e.context("failed to load wallet"));
                 return;
             }
         };
-        let mut hex_encoded_call = String::new();
-        match v["input"].as_str() {
-            Some(d) => {
-                match d {
-                    "mint" => {
-                            hex_encoded_call = hex::encode(mintCall::new((recipient_address, value)).abi_encode());                            
-                        },
-                    "burn" => {
-                            hex_encoded_call = hex::encode(burnCall::new((recipient_address, value)).abi_encode());                            
-                        },
-                    _ => {
-                        klave::notifier::send_string(&format!("ERROR: unsupported function call"));
-                        return;
-                    }
-                }
-            },
-            None => {}
-        };    
-        
-        let chain_id = match v["chainId"].as_u64() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: chainId not found"));
-                return;
+
+        if let Err(e) = wallet.ensure_not_escrow() {
+            response::error(e);
+            return;
+        }
+
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));
+                return
             }
         };
-        let nonce = match v["nonce"].as_u64() {
+
+        // Optionally register the contract so that later calls can refer to it by name.
+        let contract_name = req.contract_name.as_deref();
+        let contract_abi = match contract_name {
+            Some(name) => {
+                let contracts = match Contracts::get() {
+                    Ok(c) => c,
+                    Err(e) => {
+                        response::error(e.context("failed to load contracts"));
+                        return;
+                    }
+                };
+                if contracts.get_contracts().iter().any(|c| c == name) {
+                    response::error(Error::AlreadyExists(format!("contract {} already exists", name)));
+                    return;
+                }
+                match req.abi {
+                    Some(a) => Some(a),
+                    None => {
+                        response::error(Error::InvalidRequest(String::from("abi is required to register the contract")));
+                        return;
+                    }
+                }
+            },
+            None => None
+        };
+
+        let nonce = match req.nonce {
             Some(n) => n,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: nonce not found"));
+            None => match wallet.next_nonce(&nm, network_name) {
+                Ok(n) => n,
+                Err(e) => {
+                    response::error(e.context("failed to resolve nonce"));
+                    return;
+                }
+            }
+        };
+
+        let (max_fee_per_gas, max_priority_fee_per_gas) = match fees::resolve_fees(&nm, network_name, req.max_fee_per_gas.map(u128::from), req.max_priority_fee_per_gas.map(u128::from)) {
+            Ok(f) => (f.max_fee_per_gas, f.max_priority_fee_per_gas),
+            Err(e) => {
+                response::error(e.context("failed to estimate fees"));
                 return;
             }
         };
-        let gas_limit = match v["gasLimit"].as_u64(){
-            Some(g) => g,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: gasLimit not found"));
+
+        let mut tx = TxEip1559 {
+            chain_id: req.chain_id,
+            nonce,
+            gas_limit: req.gas_limit.unwrap_or_default(),
+            to: TxKind::Create,
+            value: U256::default(),
+            input: req.input,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            access_list: req.access_list.unwrap_or_default(),
+        };
+
+        if req.gas_limit.is_none() {
+            tx.gas_limit = match fees::estimate_gas(&nm, network_name, &eth_address, &tx) {
+                Ok(g) => g,
+                Err(e) => {
+                    response::error(e.context("failed to estimate gas"));
+                    return;
+                }
+            };
+        }
+
+        let result = match wallet.sign_and_send(&nm, network_name, tx, req.trace, None) {
+            Ok(result) => result,
+            Err(e) => {
+                response::error(e.context("failed to send transaction"));
                 return;
             }
         };
-        let max_fee_per_gas = match v["maxFeePerGas"].as_u64() {
-            Some(m) => m,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: maxFeePerGas not found"));
+
+        // The address is recorded by contract_confirm_deployment once the receipt shows `contractAddress`.
+        if let (Some(name), Some(contract_abi), false) = (contract_name, contract_abi, req.trace) {
+            let contract = Contract::new(name, network_name, None, contract_abi, Some(&result));
+            if let Err(e) = Contracts::get().and_then(|mut c| c.add_contract(&contract)) {
+                response::error(e.context(format!("failed to register contract '{}'", name)));
+                return;
+            }
+        }
+        response::success(&result);
+    }
+
+    fn wallet_balance(cmd: String){
+        let req = match request::parse::<request::WalletBalance>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
-        let max_priority_fee_per_gas = match v["maxPriorityFeePerGas"].as_u64() {
-            Some(m) => m,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: maxPriorityFeePerGas not found"));
+        let eth_address = req.eth_address.to_string();
+
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
+        }
+
+        let wallet = match Wallet::load(&eth_address) {
+            Ok(w) => w,
+            Err(e) => {
+                response::error(e.context("failed to load wallet"));
                 return;
             }
         };
-    
-        let tx = TxEip1559 {
-            chain_id: chain_id,
-            nonce: nonce,
-            gas_limit: gas_limit,
-            to: TxKind::Call(contract_address),
-            value: U256::default(),
-            input: hex::decode(hex_encoded_call).unwrap().into(),
-            max_fee_per_gas: max_fee_per_gas as u128,
-            max_priority_fee_per_gas: max_priority_fee_per_gas as u128,
-            ..Default::default()
+
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));
+                return
+            }
         };
 
-        let network_name = match v["network_name"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: network not found"));
+        match wallet.get_balance(&nm, &req.network_name) {
+            Ok(result) => response::success(&result),
+            Err(e) => response::error(e.context("failed to send balance"))
+        }
+    }
+
+    fn wallet_position(cmd: String){
+        let req = match request::parse::<request::WalletPosition>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+        let eth_address = req.eth_address.to_string();
+
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
+        }
+
+        let wallet = match Wallet::load(&eth_address) {
+            Ok(w) => w,
+            Err(e) => {
+                response::error(e.context("failed to load wallet"));
+                return;
+            }
+        };
+
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));
+                return
+            }
+        };
+
+        // Without network_name, every network the wallet is registered on is reconciled
+        let network_names: Vec<String> = match req.network_name {
+            Some(n) => vec![n],
+            None => wallet.get_networks().iter().map(|n| n.get_network_name().to_string()).collect()
+        };
+
+        let mut positions = Vec::new();
+        for network_name in network_names.iter() {
+            match wallet.position(&nm, network_name) {
+                Ok(p) => positions.push(p),
+                Err(e) => positions.push(serde_json::json!({
+                    "network_name": network_name,
+                    "error": e.to_string()
+                }))
+            }
+        }
+        response::success(serde_json::json!({
+            "eth_address": wallet.get_eth_address(),
+            "positions": positions
+        }));
+    }
+
+    fn wallet_call_contract(cmd: String) {
+        let req = match request::parse::<request::WalletCallContract>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
-        let mut wallet = match Wallet::load(&contract_owner_address.to_string()) {
+        let eth_address = req.eth_address.to_string();
+
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
+        }
+
+        let mut wallet = match Wallet::load(&eth_address) {
             Ok(w) => w,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                response::error(e.context("failed to load wallet"));
                 return;
             }
         };
 
+        if let Err(e) = wallet.ensure_not_escrow() {
+            response::error(e);
+            return;
+        }
+
         let nm = match Networks::load() {
             Ok(nm) => nm,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load network manager: {}. Create one first.", e));                
+                response::error(e.context("failed to load network manager"));
                 return
             }
         };
 
-        let trace = match v["trace"].as_bool() {
-            Some(c) => c,
-            None => false
+        // A registered contract supplies both the address and the abi.
+        let contract = match &req.contract_name {
+            Some(name) => match Contract::load(name) {
+                Ok(c) => Some(c),
+                Err(e) => {
+                    response::error(e.context(format!("failed to load contract '{}'", name)));
+                    return;
+                }
+            },
+            None => None
+        };
+
+        let contract_address = match &contract {
+            Some(c) => match c.get_address() {
+                Ok(a) => a,
+                Err(e) => {
+                    response::error(e);
+                    return;
+                }
+            },
+            None => match req.contract_address {
+                Some(a) => a,
+                None => {
+                    response::error(Error::InvalidRequest(String::from("one of contract_name or contract_address is required")));
+                    return;
+                }
+            }
+        };
+
+        // Generic calls are encoded from the supplied or registered abi, otherwise fall back to the built-in mint/burn.
+        let json_abi = match (req.abi, &contract) {
+            (Some(a), _) => Some(a),
+            (None, Some(c)) => Some(c.abi.clone()),
+            (None, None) => None
+        };
+        let input: Bytes = match json_abi {
+            Some(json_abi) => {
+                let function = match &req.function {
+                    Some(f) => match abi::find_function(&json_abi, f) {
+                        Ok(f) => f,
+                        Err(e) => {
+                            response::error(e);
+                            return;
+                        }
+                    },
+                    None => {
+                        response::error(Error::InvalidRequest(String::from("function is required with an abi")));
+                        return;
+                    }
+                };
+                match abi::encode_call(function, req.args.as_ref().unwrap_or(&Value::Null)) {
+                    Ok(input) => input.into(),
+                    Err(e) => {
+                        response::error(e.context("failed to encode call"));
+                        return;
+                    }
+                }
+            },
+            None => {
+                let (recipient_address, value) = match (req.recipient_address, req.value) {
+                    (Some(r), Some(v)) => (r, v),
+                    _ => {
+                        response::error(Error::InvalidRequest(String::from("recipient_address and value are required without an abi")));
+                        return;
+                    }
+                };
+                match req.input.as_deref() {
+                    Some("mint") => mintCall::new((recipient_address, value)).abi_encode().into(),
+                    Some("burn") => burnCall::new((recipient_address, value)).abi_encode().into(),
+                    Some(_) => {
+                        response::error(Error::InvalidRequest(String::from("unsupported function call")));
+                        return;
+                    },
+                    None => Bytes::new()
+                }
+            }
+        };
+
+        // A registered contract is only called on the network it was registered on.
+        let network_name = match (&req.network_name, &contract) {
+            (Some(n), Some(c)) if n != &c.network_name => {
+                response::error(Error::InvalidRequest(format!("contract '{}' is registered on '{}', not '{}'", c.name, c.network_name, n)));
+                return;
+            },
+            (_, Some(c)) => c.network_name.as_str(),
+            (Some(n), None) => n.as_str(),
+            (None, None) => {
+                response::error(Error::InvalidRequest(String::from("network_name is required without a contract_name")));
+                return;
+            }
+        };
+
+        let nonce = match req.nonce {
+            Some(n) => n,
+            None => match wallet.next_nonce(&nm, network_name) {
+                Ok(n) => n,
+                Err(e) => {
+                    response::error(e.context("failed to resolve nonce"));
+                    return;
+                }
+            }
+        };
+
+        let (max_fee_per_gas, max_priority_fee_per_gas) = match fees::resolve_fees(&nm, network_name, req.max_fee_per_gas.map(u128::from), req.max_priority_fee_per_gas.map(u128::from)) {
+            Ok(f) => (f.max_fee_per_gas, f.max_priority_fee_per_gas),
+            Err(e) => {
+                response::error(e.context("failed to estimate fees"));
+                return;
+            }
+        };
+
+        let mut tx = TxEip1559 {
+            chain_id: req.chain_id,
+            nonce,
+            gas_limit: req.gas_limit.unwrap_or_default(),
+            to: TxKind::Call(contract_address),
+            value: U256::default(),
+            input,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            access_list: req.access_list.clone().unwrap_or_default(),
         };
 
-        match wallet.sign_and_send(&nm, network_name, tx.clone(), trace) {
+        if req.gas_limit.is_none() {
+            tx.gas_limit = match fees::estimate_gas(&nm, network_name, &eth_address, &tx) {
+                Ok(g) => g,
+                Err(e) => {
+                    response::error(e.context("failed to estimate gas"));
+                    return;
+                }
+            };
+        }
+
+        match wallet.sign_and_send(&nm, network_name, tx.clone(), req.trace.unwrap_or(false), None) {
             Ok(result) => {
-                klave::notifier::send_string(&format!("{}", result))
+                response::success(result)
             },
-            Err(e) => klave::notifier::send_string(&format!("ERROR: failed to send transaction: {}", e))
-        }        
+            Err(e) => response::error(e.context("failed to send transaction"))
+        }
+    }
+
+    fn wallet_resync_nonce(cmd: String) {
+        let req = match request::parse::<request::WalletResyncNonce>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+        let eth_address = req.eth_address.to_string();
+
+        if let Err(e) = user::authorize_wallet(&eth_address) {
+            response::error(e);
+            return;
+        }
+
+        let mut wallet = match Wallet::load(&eth_address) {
+            Ok(w) => w,
+            Err(e) => {
+                response::error(e.context("failed to load wallet"));
+                return;
+            }
+        };
+
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));
+                return
+            }
+        };
+
+        match wallet.resync_nonce(&nm, &req.network_name) {
+            Ok(nonce) => response::success(format!("nonce of wallet {} on {} set to {}", eth_address, req.network_name, nonce)),
+            Err(e) => response::error(e.context("failed to resync nonce"))
+        }
     }
 
     fn wallets_all_for_user(_cmd: String) {
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
         let sender = match klave::context::get("sender") {
             Ok(s) => s,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: {}", e));
+                response::error(e);
                 return;
             }
         };
@@ -1022,7 +1588,7 @@ impl Guest for Component {
         let user = match User::load(&sender) {
             Ok(u) => u,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load user: {}", e));
+                response::error(e.context("failed to load user"));
                 return;
             }
         };
@@ -1034,15 +1600,21 @@ impl Guest for Component {
                     wallet_strings.push(wallet.to_string().clone());
                 },
                 Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to get wallet: {}", e));
+                    response::error(e.context("failed to get wallet"));
+                    return;
                 }
             }
         }
 
-        klave::notifier::send_string(&format!("{}", serde_json::to_string(&wallet_strings).unwrap()));
+        response::success(&wallet_strings);
     }
 
     fn wallets_all(_cmd: String) {
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
+            return;
+        }
+
         let mut wallet_strings: Vec<String> = vec![];
         for wallet_address in wallets::Wallets::get().get_list_address() {
             match Wallet::load(&wallet_address.address) {
@@ -1050,112 +1622,268 @@ impl Guest for Component {
                     wallet_strings.push(wallet.to_string().clone());
                 },
                 Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to get wallet: {}", e));
+                    response::error(e.context("failed to get wallet"));
+                    return;
                 }
             }
         }
 
-        klave::notifier::send_string(&format!("{}", serde_json::to_string(&wallet_strings).unwrap()));
+        response::success(&wallet_strings);
     }
 
-    fn user_add(_cmd: String){
-        let sender = match klave::context::get("sender") {
-            Ok(s) => s,
+    fn hd_root_create(_cmd: String) {
+        if let Err(e) = user::authorize(&[UserRole::Admin]) {
+            response::error(e);
+            return;
+        }
+
+        if HdRoot::exists() {
+            response::error(Error::AlreadyExists(String::from("hd root already exists")));
+            return;
+        }
+        let root = match HdRoot::generate() {
+            Ok(r) => r,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: {}", e));
+                response::error(e.context("failed to generate hd root"));
                 return;
             }
         };
-
-        let mut users = Users::get();
-        let mut found = false;
-        for u in users.list() {
-            if u.id == sender {
-                found = true;
-                break;
-            }
+        match root.save() {
+            // The mnemonic is only ever returned here, for the admin to back it up.
+            Ok(_) => response::success(serde_json::json!({"mnemonic": root.get_mnemonic()})),
+            Err(e) => response::error(e.context("failed to save hd root"))
         }
-        if found {
-            klave::notifier::send_string(&format!("ERROR: user '{}' already exists", sender));
+    }
+
+    fn hd_root_import(cmd: String) {
+        if let Err(e) = user::authorize(&[UserRole::Admin]) {
+            response::error(e);
             return;
         }
 
-        let user = User::get(&sender);
-        match user.save() {
-            Ok(_) => (),
+        let req = match request::parse::<request::HdRootImport>(&cmd) {
+            Ok(r) => r,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to save user: {}", e));
+                response::error(e);
                 return;
             }
-        }
+        };
 
-        match users.add_user(&user.id) {
-            Ok(_) => {
-                klave::notifier::send_string(&format!("user '{}' added", user.id));
-            },
-            Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to add user '{}': {}", user.id, e));
-            }
+        if HdRoot::exists() {
+            response::error(Error::AlreadyExists(String::from("hd root already exists")));
+            return;
         }
-    }
-            
-    fn user_get(_cmd: String) {
-        let sender = match klave::context::get("sender") {
-            Ok(s) => s,
+        let root = match HdRoot::import(&req.mnemonic, req.next_index.unwrap_or(0)) {
+            Ok(r) => r,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: {}", e));
+                response::error(e.context("invalid mnemonic"));
                 return;
             }
         };
+        match root.save() {
+            Ok(_) => response::success("hd root imported"),
+            Err(e) => response::error(e.context("failed to save hd root"))
+        }
+    }
 
-        let user = match User::load(&sender) {
-            Ok(u) => u,
+    fn hd_root_info(_cmd: String) {
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
+            return;
+        }
+
+        let root = match HdRoot::load() {
+            Ok(r) => r,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load user: {}", e));
+                response::error(e.context("failed to load hd root"));
                 return;
             }
         };
 
-        klave::notifier::send_string(&format!("{}", user));
+        // Lists every derived wallet and escrow with its path, for audit against the seed.
+        let mut derived: Vec<Value> = vec![];
+        for wallet_address in wallets::Wallets::get().get_list_address() {
+            if let Ok(wallet) = Wallet::load(&wallet_address.address) {
+                if let Some(index) = wallet.get_derivation_index() {
+                    derived.push(serde_json::json!({
+                        "index": index,
+                        "path": format!("{}{}", hd_wallet::DERIVATION_PATH_PREFIX, index),
+                        "address": wallet.get_eth_address()
+                    }));
+                }
+            }
+        }
+        response::success(serde_json::json!({
+            "next_index": root.get_next_index(),
+            "wallets": derived
+        }));
     }
-    
-    fn user_add_wallet(cmd: String) {
+
+    fn user_add(_cmd: String){
         let sender = match klave::context::get("sender") {
             Ok(s) => s,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: {}", e));
+                response::error(e);
                 return;
             }
         };
 
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
-            return;
+        let mut users = Users::get();
+        let registered = users.list();
+        // Bootstrap: the first caller administers the application. Ledgers
+        // written before roles existed have users but no admin, so the first
+        // caller is promoted there too, registered or not.
+        let promote = Users::lacks_admin(&registered);
+        if let Some(mut user) = registered.into_iter().find(|u| u.id == sender) {
+            if !promote {
+                response::error(Error::AlreadyExists(format!("user '{}' already exists", sender)));
+                return;
+            }
+            user.set_role(UserRole::Admin);
+            match user.save() {
+                Ok(_) => response::success(format!("user '{}' promoted to admin", user.id)),
+                Err(e) => response::error(e.context("failed to save user")),
+            }
+            return;
+        }
+
+        let mut user = User::get(&sender);
+        if promote {
+            user.set_role(UserRole::Admin);
+        }
+        match user.save() {
+            Ok(_) => (),
+            Err(e) => {
+                response::error(e.context("failed to save user"));
+                return;
+            }
+        }
+
+        match users.add_user(&user.id) {
+            Ok(_) => {
+                response::success(format!("user '{}' added", user.id));
+            },
+            Err(e) => {
+                response::error(e.context(format!("failed to add user '{}'", user.id)));
+            }
+        }
+    }
+            
+    fn user_get(_cmd: String) {
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
+        let sender = match klave::context::get("sender") {
+            Ok(s) => s,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
         };
 
-        let eth_address = match v["eth_address"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: eth_address not found"));
+        let user = match User::load(&sender) {
+            Ok(u) => u,
+            Err(e) => {
+                response::error(e.context("failed to load user"));
+                return;
+            }
+        };
+
+        response::success(user);
+    }
+    
+    fn user_add_wallet(cmd: String) {
+        let sender = match klave::context::get("sender") {
+            Ok(s) => s,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+
+        let req = match request::parse::<request::UserAddWallet>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
+        let eth_address = req.eth_address.to_string();
 
         let mut user = match User::load(&sender) {
             Ok(u) => u,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load user: {}", e));
+                response::error(e.context("failed to load user"));
                 return;
             }
         };
 
-        match user.add_wallet(eth_address) {
-            Ok(_) => klave::notifier::send_string(&format!("wallet {} added to user {}", eth_address, user.id)),
-            Err(e) => klave::notifier::send_string(&format!("ERROR: failed to add wallet {} to user {}: {}", eth_address, user.id, e))
+        // Only unowned wallets can be claimed, unless the sender is an admin.
+        match Wallet::load(&eth_address) {
+            Ok(w) => {
+                if !w.get_users().is_empty() && !user.has_role(&[UserRole::Admin]) {
+                    response::error(Error::Unauthorized(format!("wallet {} already belongs to another user", eth_address)));
+                    return;
+                }
+            },
+            Err(e) => {
+                response::error(e.context("failed to load wallet"));
+                return;
+            }
+        }
+
+        match user.add_wallet(&eth_address) {
+            Ok(_) => response::success(format!("wallet {} added to user {}", eth_address, user.id)),
+            Err(e) => response::error(e.context(format!("failed to add wallet {} to user {}", eth_address, user.id)))
         };
     }
         
+    fn user_set_role(cmd: String) {
+        let admin = match user::authorize(&[UserRole::Admin]) {
+            Ok(u) => u,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+
+        let req = match request::parse::<request::UserSetRole>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+        let user_id = req.user_id.as_str();
+        let role = req.role;
+        // Prevents an admin from locking everybody out.
+        if user_id == admin.id && role != UserRole::Admin {
+            response::error(Error::Unauthorized(String::from("admins cannot change their own role")));
+            return;
+        }
+
+        let mut user = match User::load(user_id) {
+            Ok(u) => u,
+            Err(e) => {
+                response::error(e.context("failed to load user"));
+                return;
+            }
+        };
+        user.set_role(role);
+        match user.save() {
+            Ok(_) => response::success(format!("role of user '{}' set to {:?}", user_id, role)),
+            Err(e) => response::error(e.context("failed to save user"))
+        }
+    }
+
     fn users_all(_cmd: String) {
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
+            return;
+        }
+
         let mut user_strings: Vec<String> = vec![];
         for user_id in Users::get().list {
             match User::load(&user_id) {
@@ -1163,82 +1891,41 @@ impl Guest for Component {
                     user_strings.push(user.to_string().clone());
                 },
                 Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to get user: {}", e));
+                    response::error(e.context("failed to get user"));
+                    return;
                 }
             }
         }
 
-        klave::notifier::send_string(&format!("{}", serde_json::to_string(&user_strings).unwrap()));
+        response::success(&user_strings);
     }
     
     fn transaction_add(cmd: String) {
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
+        if let Err(e) = user::authorize(&[UserRole::Orchestrator]) {
+            response::error(e);
             return;
+        }
+
+        let req = match request::parse::<request::TransactionAdd>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
         };
 
         let source_participant = Participant {
-            address: match v["source_address"].as_str() {
-                Some(c) => c.to_string(),
-                None => {
-                    klave::notifier::send_string(&format!("ERROR: source_address not found"));
-                    return;
-                }
-            },
-            network_name: match v["source_network_name"].as_str() {
-                Some(c) => c.to_string(),
-                None => {
-                    klave::notifier::send_string(&format!("ERROR: source_network_name not found"));
-                    return;
-                }
-            },
-            amount : match v["source_amount"].as_str() {
-                Some(c) => {
-                    match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            klave::notifier::send_string(&format!("ERROR: failed to parse source_amount: {}", e));
-                            return;
-                        }
-                    }
-                },
-                None => {
-                    klave::notifier::send_string(&format!("ERROR: source_amount not found"));
-                    return;
-                }
-            },
+            address: req.source_address.to_string(),
+            network_name: req.source_network_name,
+            amount: req.source_amount,
+            token_address: req.source_token_address.map(|a| a.to_string()),
         };
-        
+
         let destination_participant = Participant {
-            address: match v["destination_address"].as_str() {
-                Some(c) => c.to_string(),
-                None => {
-                    klave::notifier::send_string(&format!("ERROR: destination_address not found"));
-                    return;
-                }
-            },
-            network_name: match v["destination_network_name"].as_str() {
-                Some(c) => c.to_string(),
-                None => {
-                    klave::notifier::send_string(&format!("ERROR: destination_network_name not found"));
-                    return;
-                }
-            },
-            amount : match v["destination_amount"].as_str() {
-                Some(c) => {
-                    match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            klave::notifier::send_string(&format!("ERROR: failed to parse destination_amount: {}", e));
-                            return;
-                        }
-                    }
-                },
-                None => {
-                    klave::notifier::send_string(&format!("ERROR: destination_amount not found"));
-                    return;
-                }
-            },
+            address: req.destination_address.to_string(),
+            network_name: req.destination_network_name,
+            amount: req.destination_amount,
+            token_address: req.destination_token_address.map(|a| a.to_string()),
         };
 
         let payment_vs_payment = PaymentVsPayment {
@@ -1248,10 +1935,12 @@ impl Guest for Component {
             network_transactions: Vec::<NetworkTransaction>::new()
         };
 
-        let tx = match Transaction::new(&payment_vs_payment) {
+        let timeout = req.timeout.unwrap_or(transaction::DEFAULT_TIMEOUT);
+
+        let tx = match Transaction::new(&payment_vs_payment, timeout) {
             Ok(t) => t,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to create transaction: {}", e));
+                response::error(e.context("failed to create transaction"));
                 return;
             }
         };
@@ -1259,7 +1948,7 @@ impl Guest for Component {
         match tx.save() {
             Ok(_) => (),
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to save transaction: {}", e));
+                response::error(e.context("failed to save transaction"));
                 return;
             }
         }
@@ -1267,65 +1956,196 @@ impl Guest for Component {
         let mut transactions = Transactions::get();
         match transactions.add_transaction(&tx.id) {
             Ok(_) => {
-                klave::notifier::send_string(&format!("transaction '{}' added", tx.id));
+                response::success(format!("transaction '{}' added", tx.id));
             },
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to add transaction '{}': {}", tx.id, e));
+                response::error(e.context(format!("failed to add transaction '{}'", tx.id)));
             }
         }
     }
     
-    fn transaction_get(cmd: String) {
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
+    fn settlement_add(cmd: String) {
+        if let Err(e) = user::authorize(&[UserRole::Orchestrator]) {
+            response::error(e);
             return;
+        }
+
+        let req = match request::parse::<request::SettlementAdd>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
         };
 
-        let tx_id = match v["tx_id"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: tx_id not found"));
+        let legs = req.legs.iter().map(|l| Leg::new(Participant {
+            address: l.payer_address.to_string(),
+            network_name: l.network_name.clone(),
+            amount: l.amount,
+            token_address: l.token_address.map(|a| a.to_string()),
+        }, &l.payee_address.to_string())).collect();
+        let settlement = match Settlement::new(legs) {
+            Ok(s) => s,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+
+        let timeout = req.timeout.unwrap_or(transaction::DEFAULT_TIMEOUT);
+
+        let tx = match Transaction::new_settlement(&settlement, timeout) {
+            Ok(t) => t,
+            Err(e) => {
+                response::error(e.context("failed to create settlement"));
+                return;
+            }
+        };
+        match tx.save() {
+            Ok(_) => (),
+            Err(e) => {
+                response::error(e.context("failed to save transaction"));
+                return;
+            }
+        }
+
+        let mut transactions = Transactions::get();
+        match transactions.add_transaction(&tx.id) {
+            Ok(_) => {
+                response::success(format!("settlement '{}' added", tx.id));
+            },
+            Err(e) => {
+                response::error(e.context(format!("failed to add transaction '{}'", tx.id)));
+            }
+        }
+    }
+
+    fn delivery_vs_payment_add(cmd: String) {
+        if let Err(e) = user::authorize(&[UserRole::Orchestrator]) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::DeliveryVsPaymentAdd>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+
+        let asset = match Asset::new(req.asset_standard, &req.asset_contract_address.to_string(), req.asset_token_id, req.asset_amount) {
+            Ok(a) => a,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+        let delivery_vs_payment = DeliveryVsPayment {
+            delivery: Delivery {
+                network_name: req.asset_network_name,
+                address: req.seller_address.to_string(),
+                asset,
+            },
+            payment: Participant {
+                address: req.buyer_address.to_string(),
+                network_name: req.payment_network_name,
+                amount: req.payment_amount,
+                token_address: req.payment_token_address.map(|a| a.to_string()),
+            },
+            state_machine: PvPstate::Init,
+            network_transactions: Vec::<NetworkTransaction>::new()
+        };
+
+        let timeout = req.timeout.unwrap_or(transaction::DEFAULT_TIMEOUT);
+
+        let tx = match Transaction::new_delivery_vs_payment(&delivery_vs_payment, timeout) {
+            Ok(t) => t,
+            Err(e) => {
+                response::error(e.context("failed to create delivery versus payment"));
+                return;
+            }
+        };
+        match tx.save() {
+            Ok(_) => (),
+            Err(e) => {
+                response::error(e.context("failed to save transaction"));
+                return;
+            }
+        }
+
+        let mut transactions = Transactions::get();
+        match transactions.add_transaction(&tx.id) {
+            Ok(_) => {
+                response::success(format!("delivery versus payment '{}' added", tx.id));
+            },
+            Err(e) => {
+                response::error(e.context(format!("failed to add transaction '{}'", tx.id)));
+            }
+        }
+    }
+
+    fn transaction_get(cmd: String) {
+        let req = match request::parse::<request::TransactionGet>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+        let tx_id = req.tx_id.as_str();
+
+        let user = match user::authorize(user::ANY_ROLE) {
+            Ok(u) => u,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
+        if !user.is_in_transaction(tx_id) && !user.has_role(&[UserRole::Admin]) {
+            response::error(Error::Unauthorized(format!("user '{}' is not a participant in transaction '{}'", user.id, tx_id)));
+            return;
+        }
 
-        let tx = match Transaction::load(&tx_id) {
+        let tx = match Transaction::load(tx_id) {
             Ok(t) => t,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load transaction: {}", e));
+                response::error(e.context("failed to load transaction"));
                 return;
             }
         };
 
-        klave::notifier::send_string(&format!("{}", tx));
+        response::success(tx);
     }
     
     fn transaction_commit(cmd: String) {
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
             return;
-        };
+        }
 
-        let sender = match klave::context::get("sender") {
-            Ok(s) => s,
+        let req = match request::parse::<request::TransactionCommit>(&cmd) {
+            Ok(r) => r,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: {}", e));
+                response::error(e);
                 return;
             }
         };
 
-        let tx_id = match v["tx_id"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: tx_id not found"));
+        let sender = match klave::context::get("sender") {
+            Ok(s) => s,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
 
+        let tx_id = req.tx_id.as_str();
+
         let participant = match User::load(&sender) {
             Ok(u) => u,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load user: {}", e));
+                response::error(e.context("failed to load user"));
                 return;
             }
         };
@@ -1339,23 +2159,66 @@ impl Guest for Component {
             }
         }
         if !found {
-            klave::notifier::send_string(&format!("ERROR: user '{}' is not a participant in transaction '{}'", sender, tx_id));
+            response::error(Error::Unauthorized(format!("user '{}' is not a participant in transaction '{}'", sender, tx_id)));
             return;
         }
 
-        let mut tx = match Transaction::load(&tx_id) {
+        let mut tx = match Transaction::load(tx_id) {
             Ok(t) => t,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load transaction: {}", e));
+                response::error(e.context("failed to load transaction"));
                 return;
             }
         };
+        if let Ok(true) = tx.is_funding_expired() {
+            response::error(Error::InvalidState(format!("transaction '{}' has passed its deadline, cancel it instead", tx_id)));
+            return;
+        }
 
-        match tx.payment_vs_payment {
+        // Multi-leg settlements run their own state machine
+        if tx.settlement.is_some() {
+            match req.leg {
+                Some(leg) => match settlement::commit_funding(&mut tx, &participant, leg, req.tx_hash.as_deref()) {
+                    Ok(tx_hash) => response::success(&tx_hash),
+                    Err(e) => response::error(e)
+                },
+                None => {
+                    let nm = match Networks::load() {
+                        Ok(nm) => nm,
+                        Err(e) => {
+                            response::error(e.context("failed to load network manager"));
+                            return
+                        }
+                    };
+                    match settlement::send_payouts(&mut tx, &nm, &participant) {
+                        Ok(tx_hashes) => response::success(&tx_hashes),
+                        Err(e) => response::error(e.context("failed to send payout"))
+                    }
+                }
+            }
+            return;
+        }
+
+        if tx.delivery_vs_payment.is_some() {
+            let nm = match Networks::load() {
+                Ok(nm) => nm,
+                Err(e) => {
+                    response::error(e.context("failed to load network manager"));
+                    return
+                }
+            };
+            match dvp::commit(&mut tx, &nm, &participant, req.tx_hash.as_deref()) {
+                Ok(tx_hash) => response::success(&tx_hash),
+                Err(e) => response::error(e)
+            }
+            return;
+        }
+
+        match tx.payment_vs_payment.clone() {
             Some(mut pvp) => {
                 match pvp.state_machine {
                     PvPstate::Init => {
-                        klave::notifier::send_string(&format!("ERROR: transaction is not in the correct state to process payment"));
+                        response::error(Error::InvalidState(String::from("transaction is not in the correct state to process payment")));
                     },
                     PvPstate::AwaitingSourceReceive => {
                         //Find the source address in the participant wallets list
@@ -1367,68 +2230,33 @@ impl Guest for Component {
                             }
                         }
                         if !found {
-                            klave::notifier::send_string(&format!("ERROR: source address '{}' not found in participant wallets", pvp.source.address));
+                            response::error(Error::Unauthorized(format!("source address '{}' not found in participant wallets", pvp.source.address)));
                             return;
                         }
-                        let source_address = match v["source_address"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: source_address not found"));
+                        let deposit = match req.deposit() {
+                            Ok(d) => d,
+                            Err(e) => {
+                                response::error(e);
                                 return;
                             }
                         };
-                        if source_address != pvp.source.address {
-                            klave::notifier::send_string(&format!("ERROR: source_address '{}' does not match transaction source address '{}'", source_address, pvp.source.address));
+                        if deposit.address != pvp.source.address {
+                            response::error(Error::InvalidRequest(format!("source_address '{}' does not match transaction source address '{}'", deposit.address, pvp.source.address)));
                             return;
                         }
-                        let source_network_name = match v["source_network_name"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: source_network_name not found"));
-                                return;
-                            }
-                        };
-                        if source_network_name != pvp.source.network_name {
-                            klave::notifier::send_string(&format!("ERROR: source_network_name '{}' does not match transaction source network name '{}'", source_network_name, pvp.source.network_name));
+                        if deposit.network_name != pvp.source.network_name {
+                            response::error(Error::InvalidRequest(format!("source_network_name '{}' does not match transaction source network name '{}'", deposit.network_name, pvp.source.network_name)));
                             return;
                         };
-                        let source_amount = match v["source_amount"].as_str() {
-                            Some(c) => {
-                                match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        klave::notifier::send_string(&format!("ERROR: failed to parse source_amount: {}", e));
-                                        return;
-                                    }
-                                }
-                            },
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: source_amount not found"));
-                                return;
-                            }
-                        };
-                        if source_amount != pvp.source.amount {
-                            klave::notifier::send_string(&format!("ERROR: source_amount '{}' does not match transaction source amount '{}'", source_amount, pvp.source.amount));
+                        if deposit.amount != pvp.source.amount {
+                            response::error(Error::InvalidRequest(format!("source_amount '{}' does not match transaction source amount '{}'", deposit.amount, pvp.source.amount)));
                             return;
                         };
-                        let escrow_address = match v["escrow_address"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: escrow_address not found"));
-                                return;
-                            }
-                        };
-                        if escrow_address != tx.escrow_address {
-                            klave::notifier::send_string(&format!("ERROR: escrow_address '{}' does not match transaction escrow address '{}'", escrow_address, tx.escrow_address));
+                        if deposit.escrow_address != tx.escrow_address {
+                            response::error(Error::InvalidRequest(format!("escrow_address '{}' does not match transaction escrow address '{}'", deposit.escrow_address, tx.escrow_address)));
                             return;
                         }
-                        let tx_hash = match v["tx_hash"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: tx_hash not found"));
-                                return;
-                            }
-                        };
+                        let tx_hash = deposit.tx_hash;
 
                         pvp.state_machine = PvPstate::AwaitingSourceReceiveFinalized;
                         pvp.network_transactions.push(NetworkTransaction {
@@ -1440,14 +2268,14 @@ impl Guest for Component {
                         match tx.save() {
                             Ok(_) => (),
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to save transaction: {}", e));
+                                response::error(e.context("failed to save transaction"));
                                 return;
                             }
                         }
-                        klave::notifier::send_string(&tx_hash);
+                        response::success(&tx_hash);
                     },
                     PvPstate::AwaitingSourceReceiveFinalized => {
-                        klave::notifier::send_string(&format!("ERROR: transaction is not in the correct state to process payment"));
+                        response::error(Error::InvalidState(String::from("transaction is not in the correct state to process payment")));
                     },
                     PvPstate::AwaitingDestinationReceive => {
                         //Find the source address in the participant wallets list
@@ -1459,87 +2287,55 @@ impl Guest for Component {
                             }
                         }
                         if !found {
-                            klave::notifier::send_string(&format!("ERROR: source address '{}' not found in participant wallets", pvp.destination.address));
+                            response::error(Error::Unauthorized(format!("source address '{}' not found in participant wallets", pvp.destination.address)));
                             return;
                         }                        
-                        let source_address = match v["source_address"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: source_address not found"));
+                        let deposit = match req.deposit() {
+                            Ok(d) => d,
+                            Err(e) => {
+                                response::error(e);
                                 return;
                             }
                         };
-                        if source_address != pvp.destination.address {
-                            klave::notifier::send_string(&format!("ERROR: source_address '{}' does not match transaction source address '{}'", source_address, pvp.destination.address));
+                        if deposit.address != pvp.destination.address {
+                            response::error(Error::InvalidRequest(format!("source_address '{}' does not match transaction source address '{}'", deposit.address, pvp.destination.address)));
                             return;
                         }
-                        let source_network_name = match v["source_network_name"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: source_network_name not found"));
-                                return;
-                            }
-                        };
-                        if source_network_name != pvp.destination.network_name {
-                            klave::notifier::send_string(&format!("ERROR: source_network_name '{}' does not match transaction source network name '{}'", source_network_name, pvp.destination.network_name));
+                        if deposit.network_name != pvp.destination.network_name {
+                            response::error(Error::InvalidRequest(format!("source_network_name '{}' does not match transaction source network name '{}'", deposit.network_name, pvp.destination.network_name)));
                             return;
                         };
-                        let source_amount = match v["source_amount"].as_str() {
-                            Some(c) => {
-                                match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        klave::notifier::send_string(&format!("ERROR: failed to parse source_amount: {}", e));
-                                        return;
-                                    }
-                                }
-                            },
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: source_amount not found"));
-                                return;
-                            }
-                        };
-                        if source_amount != pvp.destination.amount {
-                            klave::notifier::send_string(&format!("ERROR: source_amount '{}' does not match transaction source amount '{}'", source_amount, pvp.destination.amount));
+                        if deposit.amount != pvp.destination.amount {
+                            response::error(Error::InvalidRequest(format!("source_amount '{}' does not match transaction source amount '{}'", deposit.amount, pvp.destination.amount)));
                             return;
-                        };            
-                        let escrow_address = match v["escrow_address"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: escrow_address not found"));
-                                return;
-                            }
                         };
-                        if escrow_address != tx.escrow_address {
-                            klave::notifier::send_string(&format!("ERROR: escrow_address '{}' does not match transaction escrow address '{}'", escrow_address, tx.escrow_address));
+                        if deposit.escrow_address != tx.escrow_address {
+                            response::error(Error::InvalidRequest(format!("escrow_address '{}' does not match transaction escrow address '{}'", deposit.escrow_address, tx.escrow_address)));
                             return;
                         }
-                        match klave::crypto::random::get_random_bytes(size_of::<SecretKey>() as i32) {                        
-                            Ok(result) => {
-                                pvp.state_machine = PvPstate::AwaitingDestinationReceiveFinalized;
-                                pvp.network_transactions.push(NetworkTransaction {
-                                    state: PvPstate::AwaitingDestinationReceive,
-                                    network_name: pvp.destination.network_name.clone(),
-                                    tx_hash: format!("0x{}", hex::encode(result.clone()))
-                                });
-                                tx.payment_vs_payment = Some(pvp);                                
-                                match tx.save() {
-                                    Ok(_) => (),
-                                    Err(e) => {
-                                        klave::notifier::send_string(&format!("ERROR: failed to save transaction: {}", e));
-                                        return;
-                                    }
-                                }
-                                klave::notifier::send_string(&hex::encode(result.clone()));
-                            },
-                            Err(e) => klave::notifier::send_string(&format!("ERROR: failed to send transaction: {}", e))
+                        let tx_hash = deposit.tx_hash;
+
+                        pvp.state_machine = PvPstate::AwaitingDestinationReceiveFinalized;
+                        pvp.network_transactions.push(NetworkTransaction {
+                            state: PvPstate::AwaitingDestinationReceive,
+                            network_name: pvp.destination.network_name.clone(),
+                            tx_hash: tx_hash.clone()
+                        });
+                        tx.payment_vs_payment = Some(pvp);                                
+                        match tx.save() {
+                            Ok(_) => (),
+                            Err(e) => {
+                                response::error(e.context("failed to save transaction"));
+                                return;
+                            }
                         }
+                        response::success(&tx_hash);
                     },
                     PvPstate::AwaitingDestinationReceiveFinalized => {
-                        klave::notifier::send_string(&format!("ERROR: transaction is not in the correct state to process payment"));
+                        response::error(Error::InvalidState(String::from("transaction is not in the correct state to process payment")));
                     },
                     PvPstate::AwaitingDestinationSend => {
-                        //Find the destination address in the participant wallets list
+                        //Find the escrow address in the orchestrator wallets list
                         let mut found = false;
                         for wallet_str in participant.get_wallets() {
                             if wallet_str == tx.escrow_address {
@@ -1548,73 +2344,22 @@ impl Guest for Component {
                             }
                         }
                         if !found {
-                            klave::notifier::send_string(&format!("ERROR: escrow address '{}' not found in orchestrator wallets", tx.escrow_address));
+                            response::error(Error::Unauthorized(format!("escrow address '{}' not found in orchestrator wallets", tx.escrow_address)));
                             return;
                         }
-                        let wallet = match Wallet::load(&tx.escrow_address) {
-                            Ok(w) => w,
+                        let nm = match Networks::load() {
+                            Ok(nm) => nm,
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
-                                return;
-                            }
-                        };
-                        let destination_address = match v["source_address"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: destination_address not found"));
-                                return;
-                            }
-                        };
-                        if destination_address != pvp.destination.address {
-                            klave::notifier::send_string(&format!("ERROR: destination_address '{}' does not match transaction destination address '{}'", destination_address, pvp.destination.address));
-                            return;
-                        }
-                        let destination_network_name = match v["source_network_name"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: destination_network_name not found"));
-                                return;
-                            }
-                        };
-                        if destination_network_name != pvp.source.network_name {
-                            klave::notifier::send_string(&format!("ERROR: destination_network_name '{}' does not match transaction source network name '{}'", destination_network_name, pvp.source.network_name));
-                            return;
-                        };
-                        let destination_amount = match v["source_amount"].as_str() {
-                            Some(c) => {
-                                match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        klave::notifier::send_string(&format!("ERROR: failed to parse destination_amount: {}", e));
-                                        return;
-                                    }
-                                }
-                            },
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: destination_amount not found"));
-                                return;
-                            }
-                        };
-                        if destination_amount != pvp.source.amount {
-                            klave::notifier::send_string(&format!("ERROR: destination_amount '{}' does not match transaction source amount '{}'", destination_amount, pvp.source.amount));
-                            return;
-                        };            
-                        let escrow_address = match v["escrow_address"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: escrow_address not found"));
-                                return;
+                                response::error(e.context("failed to load network manager"));                
+                                return
                             }
                         };
-                        if escrow_address != tx.escrow_address && escrow_address != wallet.get_eth_address() {
-                            klave::notifier::send_string(&format!("ERROR: escrow_address '{}' does not match transaction escrow address '{}'", destination_address, pvp.destination.address));
-                            return;
-                        }
 
-                        let tx_hash = match v["tx_hash"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: tx_hash not found"));
+                        //The escrow signs and broadcasts the payout itself
+                        let tx_hash = match tx.send_payout(&nm, &PvPstate::AwaitingDestinationSend) {
+                            Ok(h) => h,
+                            Err(e) => {
+                                response::error(e.context("failed to send payout"));
                                 return;
                             }
                         };
@@ -1629,17 +2374,17 @@ impl Guest for Component {
                         match tx.save() {
                             Ok(_) => (),
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to save transaction: {}", e));
+                                response::error(e.context("failed to save transaction"));
                                 return;
                             }
                         }
-                        klave::notifier::send_string(&tx_hash)
+                        response::success(&tx_hash)
                     },
                     PvPstate::AwaitingDestinationSendFinalized => {
-                        klave::notifier::send_string(&format!("ERROR: transaction is not in the correct state to process payment"));
+                        response::error(Error::InvalidState(String::from("transaction is not in the correct state to process payment")));
                     },
                     PvPstate::AwaitingSourceSend => {
-                        //Find the destination address in the participant wallets list
+                        //Find the escrow address in the orchestrator wallets list
                         let mut found = false;
                         for wallet_str in participant.get_wallets() {
                             if wallet_str == tx.escrow_address {
@@ -1648,121 +2393,85 @@ impl Guest for Component {
                             }
                         }
                         if !found {
-                            klave::notifier::send_string(&format!("ERROR: escrow address '{}' not found in orchestrator wallets", tx.escrow_address));
-                            return;
-                        }
-                        let destination_address = match v["source_address"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: destination_address not found"));
-                                return;
-                            }
-                        };
-                        if destination_address != pvp.source.address {
-                            klave::notifier::send_string(&format!("ERROR: destination_address '{}' does not match transaction source address '{}'", destination_address, pvp.source.address));
+                            response::error(Error::Unauthorized(format!("escrow address '{}' not found in orchestrator wallets", tx.escrow_address)));
                             return;
                         }
-                        let destination_network_name = match v["source_network_name"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: destination_network_name not found"));
-                                return;
+                        let nm = match Networks::load() {
+                            Ok(nm) => nm,
+                            Err(e) => {
+                                response::error(e.context("failed to load network manager"));                
+                                return
                             }
                         };
-                        if destination_network_name != pvp.destination.network_name {
-                            klave::notifier::send_string(&format!("ERROR: destination_network_name '{}' does not match transaction destination network name '{}'", destination_network_name, pvp.destination.network_name));
-                            return;
-                        };
-                        let destination_amount = match v["source_amount"].as_str() {
-                            Some(c) => {
-                                match U256::from_str_radix(c.trim_start_matches("0x"), 16) {
-                                    Ok(v) => v,
-                                    Err(e) => {
-                                        klave::notifier::send_string(&format!("ERROR: failed to parse destination_amount: {}", e));
-                                        return;
-                                    }
-                                }
-                            },
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: destination_amount not found"));
+
+                        //The escrow signs and broadcasts the payout itself
+                        let tx_hash = match tx.send_payout(&nm, &PvPstate::AwaitingSourceSend) {
+                            Ok(h) => h,
+                            Err(e) => {
+                                response::error(e.context("failed to send payout"));
                                 return;
                             }
                         };
-                        if destination_amount != pvp.destination.amount {
-                            klave::notifier::send_string(&format!("ERROR: destination_amount '{}' does not match transaction destination amount '{}'", destination_amount, pvp.destination.amount));
-                            return;
-                        };            
-                        let escrow_address = match v["escrow_address"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: escrow_address not found"));
+
+                        pvp.state_machine = PvPstate::AwaitingSourceSendFinalized;
+                        pvp.network_transactions.push(NetworkTransaction {
+                            state: PvPstate::AwaitingSourceSend,
+                            network_name: pvp.destination.network_name.clone(),
+                            tx_hash: tx_hash.clone()
+                        });
+                        tx.payment_vs_payment = Some(pvp);                                
+                        match tx.save() {
+                            Ok(_) => (),
+                            Err(e) => {
+                                response::error(e.context("failed to save transaction"));
                                 return;
                             }
-                        };
-                        if escrow_address != tx.escrow_address {
-                            klave::notifier::send_string(&format!("ERROR: escrow_address '{}' does not match transaction escrow address '{}'", escrow_address, tx.escrow_address));
-                            return;
-                        }
-                        match klave::crypto::random::get_random_bytes(size_of::<SecretKey>() as i32) {                        
-                            Ok(result) => {
-                                pvp.state_machine = PvPstate::AwaitingSourceSendFinalized;
-                                pvp.network_transactions.push(NetworkTransaction {
-                                    state: PvPstate::AwaitingSourceSend,
-                                    network_name: pvp.destination.network_name.clone(),
-                                    tx_hash: format!("0x{}", hex::encode(result.clone()))
-                                });
-                                tx.payment_vs_payment = Some(pvp);                                
-                                match tx.save() {
-                                    Ok(_) => (),
-                                    Err(e) => {
-                                        klave::notifier::send_string(&format!("ERROR: failed to save transaction: {}", e));
-                                        return;
-                                    }
-                                }
-                                klave::notifier::send_string(&hex::encode(result.clone()));
-                            },
-                            Err(e) => klave::notifier::send_string(&format!("ERROR: failed to send transaction: {}", e))
                         }
+                        response::success(&tx_hash)
                     },
                     PvPstate::AwaitingSourceSendFinalized => {
-                        klave::notifier::send_string(&format!("ERROR: transaction is not in the correct state to process payment"));
+                        response::error(Error::InvalidState(String::from("transaction is not in the correct state to process payment")));
                     },
                     PvPstate::Complete => {
-                        klave::notifier::send_string(&format!("SUCCESS: transaction is already complete"));
+                        response::success("transaction is already complete");
                     },
-                    PvPstate::Cancelled => {}
+                    PvPstate::Cancelled => {
+                        response::error(Error::InvalidState(String::from("transaction is cancelled")));
+                    }
                 }
             },
-            None => klave::notifier::send_string(&format!("ERROR: transaction does not have a payment"))
+            None => response::error(Error::InvalidState(String::from("transaction does not have a payment")))
         };
     }
     
     fn transaction_apply(cmd: String) {
-        let Ok(v) = serde_json::from_str::<Value>(&cmd) else {
-            klave::notifier::send_string(&format!("ERROR: failed to parse '{}' as json", cmd));
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
             return;
-        };
+        }
 
-        let sender = match klave::context::get("sender") {
-            Ok(s) => s,
+        let req = match request::parse::<request::TransactionApply>(&cmd) {
+            Ok(r) => r,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: {}", e));
+                response::error(e);
                 return;
             }
         };
 
-        let tx_id = match v["tx_id"].as_str() {
-            Some(c) => c,
-            None => {
-                klave::notifier::send_string(&format!("ERROR: tx_id not found"));
+        let sender = match klave::context::get("sender") {
+            Ok(s) => s,
+            Err(e) => {
+                response::error(e);
                 return;
             }
         };
 
+        let tx_id = req.tx_id.as_str();
+
         let participant = match User::load(&sender) {
             Ok(u) => u,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load user: {}", e));
+                response::error(e.context("failed to load user"));
                 return;
             }
         };
@@ -1776,42 +2485,60 @@ impl Guest for Component {
             }
         }
         if !found {
-            klave::notifier::send_string(&format!("ERROR: user '{}' is not a participant in transaction '{}'", sender, tx_id));
+            response::error(Error::Unauthorized(format!("user '{}' is not a participant in transaction '{}'", sender, tx_id)));
             return;
         }
 
-        let mut tx = match Transaction::load(&tx_id) {
+        let mut tx = match Transaction::load(tx_id) {
             Ok(t) => t,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load transaction: {}", e));
+                response::error(e.context("failed to load transaction"));
                 return;
             }
         };
 
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));                
+                return
+            }
+        };
+
+        if tx.settlement.is_some() {
+            match settlement::apply(&mut tx, &nm, &req.tx_hash) {
+                Ok(message) => response::success(message),
+                Err(e) => response::error(e)
+            }
+            return;
+        }
+
+        if tx.delivery_vs_payment.is_some() {
+            match dvp::apply(&mut tx, &nm, &req.tx_hash) {
+                Ok(message) => response::success(message),
+                Err(e) => response::error(e)
+            }
+            return;
+        }
+
         match tx.payment_vs_payment {
             Some(mut pvp) => {
                 match pvp.state_machine {
                     PvPstate::Init => {
-                        klave::notifier::send_string(&format!("ERROR: transaction is not in the correct state to process payment"));
+                        response::error(Error::InvalidState(String::from("transaction is not in the correct state to process payment")));
                     },
                     PvPstate::AwaitingSourceReceive => {
-                        klave::notifier::send_string(&format!("ERROR: transaction is not in the correct state to process payment"));
+                        response::error(Error::InvalidState(String::from("transaction is not in the correct state to process payment")));
                     },
                     PvPstate::AwaitingSourceReceiveFinalized => {
                         //check if the tx_hash is in the network_transactions
-                        let tx_hash = match v["tx_hash"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: tx_hash not found"));
-                                return;
-                            }
-                        };
+                        let tx_hash = req.tx_hash.clone();
                         let mut found = false;
                         for nt in &mut pvp.network_transactions {
                             if nt.tx_hash == tx_hash {
                                 found = true;
                                 if nt.state != PvPstate::AwaitingSourceReceive {
-                                    klave::notifier::send_string(&format!("ERROR: tx_hash '{}' is not in the correct state to process payment", tx_hash));
+                                    response::error(Error::InvalidState(format!("tx_hash '{}' is not in the correct state to process payment", tx_hash)));
                                     return;
                                 }
                                 nt.state = PvPstate::Complete;
@@ -1819,35 +2546,58 @@ impl Guest for Component {
                             }
                         }
                         if !found {
-                            klave::notifier::send_string(&format!("ERROR: tx_hash '{}' not found in network_transactions", tx_hash));
+                            response::error(Error::NotFound(format!("tx_hash '{}' not found in network_transactions", tx_hash)));
                             return;
                         }
 
+                        //check the transfer against the chain before moving the state forward
+                        let expected = match pvp.expected_transfer(&PvPstate::AwaitingSourceReceive, &tx.escrow_address) {
+                            Some(e) => e,
+                            None => {
+                                response::error(Error::InvalidState(format!("no transfer expected for tx_hash '{}'", tx_hash)));
+                                return;
+                            }
+                        };
+                        match transaction::verify_transfer(&nm, &expected, &tx_hash) {
+                            Ok(_) => (),
+                            Err(e) => {
+                                response::error(e.context(format!("failed to verify tx_hash '{}'", tx_hash)));
+                                return;
+                            }
+                        }
+
                         let mut escrow_wallet = match Wallet::load(&tx.escrow_address) {
                             Ok(w) => w,
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                                response::error(e.context("failed to load wallet"));
                                 return;
                             }
                         };
                         match escrow_wallet.mint(&pvp.source.network_name, &pvp.source.amount) {
                             Ok(_) => (),
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to mint: {}", e));
+                                response::error(e.context("failed to mint"));
+                                return;
+                            }
+                        }
+                        //native funds are held by the escrow until its payout releases them
+                        if pvp.source.token_address.is_none() {
+                            if let Err(e) = escrow_wallet.hold(&pvp.source.network_name, pvp.source.amount) {
+                                response::error(e.context("failed to hold funds"));
                                 return;
                             }
                         }                        
                         let mut source_wallet = match Wallet::load(&pvp.source.address) {
                             Ok(w) => w,
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                                response::error(e.context("failed to load wallet"));
                                 return;
                             }
                         };
                         match source_wallet.burn(&pvp.source.network_name, &pvp.source.amount) {
                             Ok(_) => (),
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to mint: {}", e));
+                                response::error(e.context("failed to mint"));
                                 return;
                             }
                         }      
@@ -1855,32 +2605,25 @@ impl Guest for Component {
                         tx.payment_vs_payment = Some(pvp.clone());
                         match tx.save() {
                             Ok(_) => {
-                                klave::notifier::send_string(&format!("SUCCESS: transaction '{}' finalized", tx.id));
+                                response::success(format!("transaction '{}' finalized", tx.id));
                             },
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to save transaction: {}", e));
-                                return;
+                                response::error(e.context("failed to save transaction"));
                             }
                         }                  
                     },
                     PvPstate::AwaitingDestinationReceive => {
-                        klave::notifier::send_string(&format!("ERROR: transaction is not in the correct state to process payment"));
+                        response::error(Error::InvalidState(String::from("transaction is not in the correct state to process payment")));
                     },
                     PvPstate::AwaitingDestinationReceiveFinalized => {
                         //check if the tx_hash is in the network_transactions
-                        let tx_hash = match v["tx_hash"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: tx_hash not found"));
-                                return;
-                            }
-                        };
+                        let tx_hash = req.tx_hash.clone();
                         let mut found = false;
                         for nt in &mut pvp.network_transactions {
                             if nt.tx_hash == tx_hash {
                                 found = true;
                                 if nt.state != PvPstate::AwaitingDestinationReceive {
-                                    klave::notifier::send_string(&format!("ERROR: tx_hash '{}' is not in the correct state to process payment", tx_hash));
+                                    response::error(Error::InvalidState(format!("tx_hash '{}' is not in the correct state to process payment", tx_hash)));
                                     return;
                                 }
                                 nt.state = PvPstate::Complete;
@@ -1888,35 +2631,58 @@ impl Guest for Component {
                             }
                         }
                         if !found {
-                            klave::notifier::send_string(&format!("ERROR: tx_hash '{}' not found in network_transactions", tx_hash));
+                            response::error(Error::NotFound(format!("tx_hash '{}' not found in network_transactions", tx_hash)));
                             return;
                         }
 
+                        //check the transfer against the chain before moving the state forward
+                        let expected = match pvp.expected_transfer(&PvPstate::AwaitingDestinationReceive, &tx.escrow_address) {
+                            Some(e) => e,
+                            None => {
+                                response::error(Error::InvalidState(format!("no transfer expected for tx_hash '{}'", tx_hash)));
+                                return;
+                            }
+                        };
+                        match transaction::verify_transfer(&nm, &expected, &tx_hash) {
+                            Ok(_) => (),
+                            Err(e) => {
+                                response::error(e.context(format!("failed to verify tx_hash '{}'", tx_hash)));
+                                return;
+                            }
+                        }
+
                         let mut escrow_wallet = match Wallet::load(&tx.escrow_address) {
                             Ok(w) => w,
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                                response::error(e.context("failed to load wallet"));
                                 return;
                             }
                         };
                         match escrow_wallet.mint(&pvp.destination.network_name, &pvp.destination.amount) {
                             Ok(_) => (),
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to mint: {}", e));
+                                response::error(e.context("failed to mint"));
+                                return;
+                            }
+                        }
+                        //native funds are held by the escrow until its payout releases them
+                        if pvp.destination.token_address.is_none() {
+                            if let Err(e) = escrow_wallet.hold(&pvp.destination.network_name, pvp.destination.amount) {
+                                response::error(e.context("failed to hold funds"));
                                 return;
                             }
                         }                        
                         let mut destination_wallet = match Wallet::load(&pvp.destination.address) {
                             Ok(w) => w,
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                                response::error(e.context("failed to load wallet"));
                                 return;
                             }
                         };
                         match destination_wallet.burn(&pvp.destination.network_name, &pvp.destination.amount) {
                             Ok(_) => (),
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to mint: {}", e));
+                                response::error(e.context("failed to mint"));
                                 return;
                             }
                         }  
@@ -1924,32 +2690,25 @@ impl Guest for Component {
                         tx.payment_vs_payment = Some(pvp);
                         match tx.save() {
                             Ok(_) => {
-                                klave::notifier::send_string(&format!("SUCCESS: transaction '{}' finalized", tx.id));
+                                response::success(format!("transaction '{}' finalized", tx.id));
                             },
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to save transaction: {}", e));
-                                return;
+                                response::error(e.context("failed to save transaction"));
                             }
                         }                  
                     },
                     PvPstate::AwaitingDestinationSend => {
-                        klave::notifier::send_string(&format!("ERROR: transaction is not in the correct state to process payment"));
+                        response::error(Error::InvalidState(String::from("transaction is not in the correct state to process payment")));
                     },
                     PvPstate::AwaitingDestinationSendFinalized => {
                         //check if the tx_hash is in the network_transactions
-                        let tx_hash = match v["tx_hash"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: tx_hash not found"));
-                                return;
-                            }
-                        };
+                        let tx_hash = req.tx_hash.clone();
                         let mut found = false;
                         for nt in &mut pvp.network_transactions {
                             if nt.tx_hash == tx_hash {
                                 found = true;
                                 if nt.state != PvPstate::AwaitingDestinationSend {
-                                    klave::notifier::send_string(&format!("ERROR: tx_hash '{}' is not in the correct state to process payment", tx_hash));
+                                    response::error(Error::InvalidState(format!("tx_hash '{}' is not in the correct state to process payment", tx_hash)));
                                     return;
                                 }
                                 nt.state = PvPstate::Complete;
@@ -1957,68 +2716,78 @@ impl Guest for Component {
                             }
                         }
                         if !found {
-                            klave::notifier::send_string(&format!("ERROR: tx_hash '{}' not found in network_transactions", tx_hash));
+                            response::error(Error::NotFound(format!("tx_hash '{}' not found in network_transactions", tx_hash)));
                             return;
                         }
 
-                        let mut escrow_wallet = match Wallet::load(&tx.escrow_address) {
+                        //check the transfer against the chain before moving the state forward
+                        let expected = match pvp.expected_transfer(&PvPstate::AwaitingDestinationSend, &tx.escrow_address) {
+                            Some(e) => e,
+                            None => {
+                                response::error(Error::InvalidState(format!("no transfer expected for tx_hash '{}'", tx_hash)));
+                                return;
+                            }
+                        };
+                        match transaction::verify_transfer(&nm, &expected, &tx_hash) {
+                            Ok(_) => (),
+                            Err(e) => {
+                                response::error(e.context(format!("failed to verify tx_hash '{}'", tx_hash)));
+                                return;
+                            }
+                        }
+
+                        //book the payout exactly as it was verified on chain
+                        let mut escrow_wallet = match Wallet::load(&expected.from) {
                             Ok(w) => w,
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                                response::error(e.context("failed to load wallet"));
                                 return;
                             }
                         };
-                        match escrow_wallet.burn(&pvp.destination.network_name, &pvp.destination.amount) {
+                        match escrow_wallet.burn(&expected.network_name, &expected.amount) {
                             Ok(_) => (),
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to mint: {}", e));
+                                response::error(e.context("failed to burn"));
                                 return;
                             }
-                        }                        
-                        let mut source_wallet = match Wallet::load(&pvp.source.address) {
+                        }
+                        let mut recipient_wallet = match Wallet::load(&expected.to) {
                             Ok(w) => w,
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                                response::error(e.context("failed to load wallet"));
                                 return;
                             }
                         };
-                        match source_wallet.mint(&pvp.destination.network_name, &pvp.destination.amount) {
+                        match recipient_wallet.mint(&expected.network_name, &expected.amount) {
                             Ok(_) => (),
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to mint: {}", e));
+                                response::error(e.context("failed to mint"));
                                 return;
                             }
-                        }           
+                        }
                         pvp.state_machine = PvPstate::AwaitingSourceSend;
                         tx.payment_vs_payment = Some(pvp);
                         match tx.save() {
                             Ok(_) => {
-                                klave::notifier::send_string(&format!("SUCCESS: transaction '{}' finalized", tx.id));
+                                response::success(format!("transaction '{}' finalized", tx.id));
                             },
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to save transaction: {}", e));
-                                return;
+                                response::error(e.context("failed to save transaction"));
                             }
                         }                  
                     },
                     PvPstate::AwaitingSourceSend => {
-                        klave::notifier::send_string(&format!("ERROR: transaction is not in the correct state to process payment"));
+                        response::error(Error::InvalidState(String::from("transaction is not in the correct state to process payment")));
                     },
                     PvPstate::AwaitingSourceSendFinalized => {
                         //check if the tx_hash is in the network_transactions
-                        let tx_hash = match v["tx_hash"].as_str() {
-                            Some(c) => c.to_string(),
-                            None => {
-                                klave::notifier::send_string(&format!("ERROR: tx_hash not found"));
-                                return;
-                            }
-                        };
+                        let tx_hash = req.tx_hash.clone();
                         let mut found = false;
                         for nt in &mut pvp.network_transactions {
                             if nt.tx_hash == tx_hash {
                                 found = true;
                                 if nt.state != PvPstate::AwaitingSourceSend {
-                                    klave::notifier::send_string(&format!("ERROR: tx_hash '{}' is not in the correct state to process payment", tx_hash));
+                                    response::error(Error::InvalidState(format!("tx_hash '{}' is not in the correct state to process payment", tx_hash)));
                                     return;
                                 }
                                 nt.state = PvPstate::Complete;
@@ -2026,158 +2795,666 @@ impl Guest for Component {
                             }
                         }
                         if !found {
-                            klave::notifier::send_string(&format!("ERROR: tx_hash '{}' not found in network_transactions", tx_hash));
+                            response::error(Error::NotFound(format!("tx_hash '{}' not found in network_transactions", tx_hash)));
                             return;
                         }
 
-                        let mut escrow_wallet = match Wallet::load(&tx.escrow_address) {
+                        //check the transfer against the chain before moving the state forward
+                        let expected = match pvp.expected_transfer(&PvPstate::AwaitingSourceSend, &tx.escrow_address) {
+                            Some(e) => e,
+                            None => {
+                                response::error(Error::InvalidState(format!("no transfer expected for tx_hash '{}'", tx_hash)));
+                                return;
+                            }
+                        };
+                        match transaction::verify_transfer(&nm, &expected, &tx_hash) {
+                            Ok(_) => (),
+                            Err(e) => {
+                                response::error(e.context(format!("failed to verify tx_hash '{}'", tx_hash)));
+                                return;
+                            }
+                        }
+
+                        //book the payout exactly as it was verified on chain
+                        let mut escrow_wallet = match Wallet::load(&expected.from) {
                             Ok(w) => w,
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                                response::error(e.context("failed to load wallet"));
                                 return;
                             }
                         };
-                        match escrow_wallet.burn(&pvp.source.network_name, &pvp.source.amount) {
+                        match escrow_wallet.burn(&expected.network_name, &expected.amount) {
                             Ok(_) => (),
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to mint: {}", e));
+                                response::error(e.context("failed to burn"));
                                 return;
                             }
-                        }                        
-                        let mut destination_wallet = match Wallet::load(&pvp.destination.address) {
+                        }
+                        let mut recipient_wallet = match Wallet::load(&expected.to) {
                             Ok(w) => w,
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to load wallet: {}", e));
+                                response::error(e.context("failed to load wallet"));
                                 return;
                             }
                         };
-                        match destination_wallet.mint(&pvp.source.network_name, &pvp.source.amount) {
+                        match recipient_wallet.mint(&expected.network_name, &expected.amount) {
                             Ok(_) => (),
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to mint: {}", e));
+                                response::error(e.context("failed to mint"));
                                 return;
-                            }                            
-                        }    
+                            }
+                        }
                         pvp.state_machine = PvPstate::Complete;
                         tx.payment_vs_payment = Some(pvp);
                         match tx.save() {
                             Ok(_) => {
-                                klave::notifier::send_string(&format!("SUCCESS: transaction '{}' finalized", tx.id));
+                                response::success(format!("transaction '{}' finalized", tx.id));
                             },
                             Err(e) => {
-                                klave::notifier::send_string(&format!("ERROR: failed to save transaction: {}", e));
-                                return;
+                                response::error(e.context("failed to save transaction"));
                             }
                         }                  
                     },
                     PvPstate::Complete => {
-                        klave::notifier::send_string(&format!("SUCCESS: transaction is already complete"));
+                        response::success("transaction is already complete");
                     },
                     PvPstate::Cancelled => {
-                        klave::notifier::send_string(&format!("ERROR: transaction is not in the correct state to process payment"));
+                        response::error(Error::InvalidState(String::from("transaction is not in the correct state to process payment")));
                     }
                 }
             },
-            None => klave::notifier::send_string(&format!("ERROR: transaction does not have a payment"))
-        };
+            None => response::error(Error::InvalidState(String::from("transaction does not have a payment")))
+        }
     }
 
-    fn transactions_all_for_user(_cmd: String) {
+    fn transaction_cancel(cmd: String) {
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::TransactionCancel>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+
         let sender = match klave::context::get("sender") {
             Ok(s) => s,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: {}", e));
+                response::error(e);
                 return;
             }
         };
 
-        let user = match User::load(&sender) {
+        let tx_id = req.tx_id.as_str();
+
+        let participant = match User::load(&sender) {
             Ok(u) => u,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to load user: {}", e));
+                response::error(e.context("failed to load user"));
                 return;
             }
         };
 
-        let mut transaction_strings: Vec<String> = vec![];
-        for tx_role in user.get_transactions() {
-            match Transaction::load(&tx_role.transaction_id) {
-                Ok(tx) => {
-                    transaction_strings.push(tx.to_string().clone());
-                },
-                Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to get transaction: {}", e));
-                }
+        //Check if the user is a participant or the orchestrator of the transaction
+        let mut found = false;
+        for tx_role in participant.get_transactions() {
+            if tx_role.transaction_id == tx_id {
+                found = true;
+                break;
             }
         }
+        if !found {
+            response::error(Error::Unauthorized(format!("user '{}' is not a participant in transaction '{}'", sender, tx_id)));
+            return;
+        }
 
-        klave::notifier::send_string(&format!("{}", serde_json::to_string(&transaction_strings).unwrap()));
-    }
-
-    fn eth_block_number(cmd: String){
-        eth::eth_block_number(cmd);
-    }
+        let mut tx = match Transaction::load(tx_id) {
+            Ok(t) => t,
+            Err(e) => {
+                response::error(e.context("failed to load transaction"));
+                return;
+            }
+        };
 
-    fn eth_get_block_by_number(cmd: String){
-        eth::eth_get_block_by_number(cmd);
-    }
+        match tx.is_expired() {
+            Ok(true) => (),
+            Ok(false) => {
+                response::error(Error::InvalidState(format!("transaction '{}' has not reached its deadline", tx_id)));
+                return;
+            },
+            Err(e) => {
+                response::error(e.context("failed to check transaction deadline"));
+                return;
+            }
+        }
 
-    fn eth_gas_price(cmd: String){
-        eth::eth_gas_price(cmd);
-    }
+        if tx.settlement.is_some() {
+            let nm = match Networks::load() {
+                Ok(nm) => nm,
+                Err(e) => {
+                    response::error(e.context("failed to load network manager"));
+                    return
+                }
+            };
+            match settlement::cancel(&mut tx, &nm) {
+                Ok(_) => response::success(format!("transaction '{}' cancelled", tx.id)),
+                Err(e) => response::error(e)
+            }
+            return;
+        }
+
+        if tx.delivery_vs_payment.is_some() {
+            let nm = match Networks::load() {
+                Ok(nm) => nm,
+                Err(e) => {
+                    response::error(e.context("failed to load network manager"));
+                    return
+                }
+            };
+            match dvp::cancel(&mut tx, &nm) {
+                Ok(_) => response::success(format!("transaction '{}' cancelled", tx.id)),
+                Err(e) => response::error(e)
+            }
+            return;
+        }
+
+        let mut pvp = match tx.payment_vs_payment.clone() {
+            Some(pvp) => pvp,
+            None => {
+                response::error(Error::InvalidState(String::from("transaction does not have a payment")));
+                return;
+            }
+        };
+        if pvp.state_machine == PvPstate::Cancelled {
+            response::error(Error::InvalidState(String::from("transaction is already cancelled")));
+            return;
+        }
+        if pvp.is_payout_started() {
+            response::error(Error::InvalidState(String::from("transaction payout has started and cannot be cancelled")));
+            return;
+        }
+
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));                
+                return
+            }
+        };
+
+        //Refunds already sent by a previous attempt are recorded in order, skip them
+        let already_refunded = pvp.network_transactions.iter().filter(|nt| nt.state == PvPstate::Cancelled).count();
+        let funded_legs = match pvp.funded_legs(&nm, &tx.escrow_address) {
+            Ok(l) => l,
+            Err(e) => {
+                response::error(e.context("cannot cancel before every committed leg is settled on chain"));
+                return;
+            }
+        };
+        let mut bookkeeping_errors: Vec<String> = Vec::new();
+        for leg in funded_legs.into_iter().skip(already_refunded) {
+            let refund_hash = match tx.send_refund(&nm, &leg.participant) {
+                Ok(h) => h,
+                Err(e) => {
+                    let e = e.context(format!("failed to refund {} on {}", leg.participant.address, leg.participant.network_name));
+                    tx.payment_vs_payment = Some(pvp);
+                    match tx.save() {
+                        Ok(_) => response::error(e),
+                        Err(save_error) => response::error(e.context(format!("failed to save transaction ({})", save_error)))
+                    }
+                    return;
+                }
+            };
+            pvp.network_transactions.push(NetworkTransaction {
+                state: PvPstate::Cancelled,
+                network_name: leg.participant.network_name.clone(),
+                tx_hash: refund_hash
+            });
+
+            //The refund is already broadcast, so bookkeeping failures must not abort the loop.
+            //The escrow is reloaded since sending the refund has advanced its nonce.
+            if leg.applied {
+                match Wallet::load(&tx.escrow_address) {
+                    Ok(mut escrow_wallet) => {
+                        if let Err(e) = escrow_wallet.burn(&leg.participant.network_name, &leg.participant.amount) {
+                            bookkeeping_errors.push(e.context("failed to burn").to_string());
+                        }
+                    },
+                    Err(e) => bookkeeping_errors.push(e.context("failed to load escrow wallet").to_string())
+                }
+                match Wallet::load(&leg.participant.address) {
+                    Ok(mut participant_wallet) => {
+                        if let Err(e) = participant_wallet.mint(&leg.participant.network_name, &leg.participant.amount) {
+                            bookkeeping_errors.push(e.context("failed to mint").to_string());
+                        }
+                    },
+                    Err(e) => bookkeeping_errors.push(e.context("failed to load wallet").to_string())
+                }
+            }
+        }
+
+        pvp.state_machine = PvPstate::Cancelled;
+        tx.payment_vs_payment = Some(pvp);
+        match tx.save() {
+            Ok(_) if bookkeeping_errors.is_empty() => {
+                response::success(format!("transaction '{}' cancelled", tx.id));
+            },
+            Ok(_) => {
+                response::error(Error::Internal(format!("transaction '{}' cancelled and refunded, but balances were not updated: {}", tx.id, bookkeeping_errors.join("; "))));
+            },
+            Err(e) => {
+                response::error(e.context("failed to save transaction"));
+            }
+        }
+    }
+
+    /// Sends the escrow, from a wallet of the orchestrator, the native funds it lacks to pay the
+    /// gas of its remaining payouts or refunds on a network, at the current fee estimate.
+    ///
+    /// Escrows only receive the legs they hold, so this must run before the escrow pays out or
+    /// refunds, and again if fees rise in between. Unspent gas stays with the escrow.
+    fn transaction_fund_escrow_gas(cmd: String) {
+        let req = match request::parse::<request::TransactionFundEscrowGas>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+        let eth_address = req.eth_address.to_string();
+        let network_name = req.network_name.as_str();
+
+        let orchestrator = match user::authorize_wallet(&eth_address) {
+            Ok(u) => u,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+        if !orchestrator.get_transactions().iter().any(|r| r.transaction_id == req.tx_id && r.role == RoleType::Orchestrator) {
+            response::error(Error::Unauthorized(format!("user '{}' is not the orchestrator of transaction '{}'", orchestrator.id, req.tx_id)));
+            return;
+        }
+
+        let mut wallet = match Wallet::load(&eth_address) {
+            Ok(w) => w,
+            Err(e) => {
+                response::error(e.context("failed to load wallet"));
+                return;
+            }
+        };
+        if let Err(e) = wallet.ensure_not_escrow() {
+            response::error(e);
+            return;
+        }
+
+        let tx = match Transaction::load(&req.tx_id) {
+            Ok(t) => t,
+            Err(e) => {
+                response::error(e.context("failed to load transaction"));
+                return;
+            }
+        };
+        let escrow_address = match tx.escrow_on(network_name).and_then(|a| Ok(a.parse::<alloy_primitives::Address>()?)) {
+            Ok(a) => a,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));
+                return
+            }
+        };
+
+        let shortfall = match fees::estimate_fees(&nm, network_name).and_then(|f| tx.escrow_gas_shortfall(&nm, network_name, f.max_fee_per_gas)) {
+            Ok(s) => s,
+            Err(e) => {
+                response::error(e.context("failed to compute the gas of the escrow"));
+                return;
+            }
+        };
+        if shortfall.is_zero() {
+            response::success(format!("escrow {} already holds enough gas on '{}'", escrow_address, network_name));
+            return;
+        }
+
+        let top_up = match wallet.build_transfer(&nm, network_name, escrow_address, shortfall) {
+            Ok(t) => t,
+            Err(e) => {
+                response::error(e.context("failed to build gas top-up"));
+                return;
+            }
+        };
+        match wallet.sign_and_send(&nm, network_name, top_up, false, None) {
+            Ok(tx_hash) => response::success(&tx_hash),
+            Err(e) => response::error(e.context("failed to send gas top-up"))
+        }
+    }
+
+    fn transactions_all_for_user(_cmd: String) {
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
+        let sender = match klave::context::get("sender") {
+            Ok(s) => s,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+
+        let user = match User::load(&sender) {
+            Ok(u) => u,
+            Err(e) => {
+                response::error(e.context("failed to load user"));
+                return;
+            }
+        };
+
+        let mut transaction_strings: Vec<String> = vec![];
+        for tx_role in user.get_transactions() {
+            match Transaction::load(&tx_role.transaction_id) {
+                Ok(tx) => {
+                    transaction_strings.push(tx.to_string().clone());
+                },
+                Err(e) => {
+                    response::error(e.context("failed to get transaction"));
+                    return;
+                }
+            }
+        }
+
+        response::success(&transaction_strings);
+    }
+
+    fn contract_add(cmd: String) {
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::ContractAdd>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+        let name = req.contract_name.as_str();
+        if req.contract_address.is_none() && req.tx_hash.is_none() {
+            response::error(Error::InvalidRequest(String::from("either contract_address or tx_hash is required")));
+            return;
+        }
+
+        let contract = Contract::new(name, &req.network_name, req.contract_address, req.abi, req.tx_hash.as_deref());
+        match Contracts::get().and_then(|mut c| c.add_contract(&contract)) {
+            Ok(_) => response::success(format!("contract '{}' added", name)),
+            Err(e) => response::error(e.context(format!("failed to add contract '{}'", name)))
+        }
+    }
+
+    fn contract_remove(cmd: String) {
+        if let Err(e) = user::authorize(&[UserRole::Admin, UserRole::Operator]) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::ContractRemove>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+
+        let name = req.contract_name.as_str();
+        match Contracts::get().and_then(|mut c| c.remove_contract(name)) {
+            Ok(_) => response::success(format!("contract '{}' removed", name)),
+            Err(e) => response::error(e.context(format!("failed to remove contract '{}'", name)))
+        }
+    }
+
+    /// Records the address of a contract registered by `wallet_deploy_contract` from its deployment receipt.
+    fn contract_confirm_deployment(cmd: String) {
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::ContractConfirmDeployment>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+
+        let nm = match Networks::load() {
+            Ok(nm) => nm,
+            Err(e) => {
+                response::error(e.context("failed to load network manager"));
+                return
+            }
+        };
+
+        let name = req.contract_name.as_str();
+        let mut contract = match Contract::load(name) {
+            Ok(c) => c,
+            Err(e) => {
+                response::error(e.context("failed to load contract"));
+                return;
+            }
+        };
+        if contract.address.is_some() {
+            response::success(&contract);
+            return;
+        }
+        if let Err(e) = contract.record_deployment(&nm) {
+            response::error(e.context(format!("failed to confirm deployment of contract '{}'", name)));
+            return;
+        }
+        match contract.save() {
+            Ok(_) => response::success(&contract),
+            Err(e) => response::error(e.context(format!("failed to save contract '{}'", name)))
+        }
+    }
+
+    fn contract_get(cmd: String) {
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
+        let req = match request::parse::<request::ContractGet>(&cmd) {
+            Ok(r) => r,
+            Err(e) => {
+                response::error(e);
+                return;
+            }
+        };
+
+        let name = req.contract_name.as_str();
+        match Contract::load(name) {
+            Ok(c) => response::success(c),
+            Err(e) => response::error(e.context("failed to load contract"))
+        }
+    }
+
+    fn contracts_all(_cmd: String) {
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
+        let contracts = match Contracts::get() {
+            Ok(c) => c,
+            Err(e) => {
+                response::error(e.context("failed to load contracts"));
+                return;
+            }
+        };
+        let mut contract_strings: Vec<String> = vec![];
+        for name in contracts.get_contracts() {
+            match Contract::load(name) {
+                Ok(c) => contract_strings.push(c.to_string()),
+                Err(e) => {
+                    response::error(e.context("failed to get contract"));
+                    return;
+                }
+            }
+        }
+        response::success(&contract_strings);
+    }
+
+    fn eth_block_number(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
+        eth::eth_block_number(cmd);
+    }
+
+    fn eth_get_block_by_number(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
+        eth::eth_get_block_by_number(cmd);
+    }
+
+    fn eth_gas_price(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
+        eth::eth_gas_price(cmd);
+    }
+
+    fn eth_estimate_gas(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
 
-    fn eth_estimate_gas(cmd: String){
         eth::eth_estimate_gas(cmd);
     }
 
     fn eth_call_contract(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
         eth::eth_call_contract(cmd);
     }
 
+    fn eth_call_function(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
+        eth::eth_call_function(cmd);
+    }
+
     fn eth_protocol_version(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
         eth::eth_protocol_version(cmd);
     }
 
     fn eth_chain_id(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
         eth::eth_chain_id(cmd);
     }
 
     fn eth_get_transaction_by_hash(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
         eth::eth_get_transaction_by_hash(cmd);
     }
 
     fn eth_get_transaction_receipt(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
         eth::eth_get_transaction_receipt(cmd);
     }
 
     fn eth_get_transaction_count(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
         eth::eth_get_transaction_count(cmd);
     }
 
     fn web_client_version(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
         web3::web3_client_version(cmd);
     }
 
     fn web_sha3(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
         web3::web3_sha3(cmd);
     }
 
     fn net_version(cmd: String){
+        if let Err(e) = user::authorize(user::ANY_ROLE) {
+            response::error(e);
+            return;
+        }
+
         web3::net_version(cmd);
     }
 
     fn get_sender(_cmd: String){
-        klave::notifier::send_string(&match klave::context::get("sender") {
-            Ok(s) => s,
-            Err(e) => e.to_string()
-        });
+        match klave::context::get("sender") {
+            Ok(s) => response::success(s),
+            Err(e) => response::error(e)
+        }
     }
 
     fn get_trusted_time(_cmd: String){
-        klave::notifier::send_string(&match klave::context::get("trusted_time") {
-            Ok(s) => s,
-            Err(e) => e.to_string()
-        });
+        match klave::context::get("trusted_time") {
+            Ok(s) => response::success(s),
+            Err(e) => response::error(e)
+        }
     }
 }
 
@@ -2186,6 +3463,11 @@ bindings::export!(Component with_types_in bindings);
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
+    use alloy_primitives::Address;
+    use alloy_signer::SignerSync;
+    use alloy_signer_local::PrivateKeySigner;
+    use alloy_sol_types::SolStruct;
 
     #[test]
     fn test_solidity_hex_encode() {
@@ -2195,4 +3477,500 @@ mod tests {
 
         assert_eq!(hex_encoded_call, "40c10f190000000000000000000000000e8f8ad443a1270a7d8af3b30d288daa0f988e40000000000000000000000000000000000000000000000000002386f26fc10000".to_string());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_solidity_transfer_hex_encode() {
+        let recipient_address = Address::from_str("0x0E8f8ad443a1270a7D8Af3B30D288DaA0F988e40").unwrap();   
+        let value = U256::from_str_radix("2386F26FC10000", 16).unwrap();
+        let hex_encoded_call = hex::encode(solidity::transferCall::new((recipient_address, value)).abi_encode());
+
+        assert_eq!(hex_encoded_call, "a9059cbb0000000000000000000000000e8f8ad443a1270a7d8af3b30d288daa0f988e40000000000000000000000000000000000000000000000000002386f26fc10000".to_string());
+    }
+
+    #[test]
+    fn test_user_roles() {
+        let mut user = User::new("user");
+        assert_eq!(user.get_role(), UserRole::Participant);
+        assert!(user.has_role(user::ANY_ROLE));
+        assert!(!user.has_role(&[UserRole::Admin, UserRole::Operator]));
+
+        user.set_role(UserRole::Admin);
+        assert!(user.has_role(&[UserRole::Orchestrator]));
+    }
+
+    #[test]
+    fn test_hd_root_derivation() {
+        let root = HdRoot::import("test test test test test test test test test test test junk", 0).unwrap();
+        let (_, public_key) = root.derive(0).unwrap();
+
+        assert_eq!(wallet::eth_address(&public_key).to_string(), "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266");
+    }
+
+    #[test]
+    fn test_keystore_decrypt() {
+        // Test vector from the Web3 Secret Storage definition.
+        let keystore: Keystore = serde_json::from_value(serde_json::json!({
+            "crypto": {
+                "cipher": "aes-128-ctr",
+                "cipherparams": {"iv": "6087dab2f9fdbbfaddc31a909735c1e6"},
+                "ciphertext": "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+                "kdf": "pbkdf2",
+                "kdfparams": {"c": 262144, "dklen": 32, "prf": "hmac-sha256", "salt": "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"},
+                "mac": "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+            },
+            "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+            "version": 3
+        })).unwrap();
+
+        assert_eq!(hex::encode(keystore.decrypt("testpassword").unwrap()), "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d");
+        assert!(keystore.decrypt("wrongpassword").is_err());
+
+        let secret_key = hex::decode("7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d").unwrap();
+        let kdfparams = keystore::KdfParams::scrypt(1 << 4, &[0u8; 32]);
+        let exported = Keystore::encrypt_with(&secret_key, "0x008aeeda4d805471df9b2a5b0f38a0c3bcba786b", "testpassword", kdfparams, &[1u8; 16], &[2u8; 16]).unwrap();
+        assert_eq!(exported.decrypt("testpassword").unwrap(), secret_key);
+
+        // Imported files cannot pick a short key or costly parameters
+        let mut short = keystore.clone();
+        short.crypto.kdfparams = keystore::KdfParams::Pbkdf2 { c: 1, dklen: 16, prf: String::from("hmac-sha256"), salt: String::from("00") };
+        assert!(short.decrypt("testpassword").is_err());
+        let mut costly = keystore.clone();
+        costly.crypto.kdfparams = keystore::KdfParams::Scrypt { dklen: 32, n: 1 << 18, p: 1, r: 8, salt: String::from("00") };
+        assert!(matches!(costly.decrypt("testpassword"), Err(Error::InvalidRequest(_))));
+        let mut malformed = keystore.clone();
+        malformed.crypto.kdfparams = keystore::KdfParams::Scrypt { dklen: 32, n: 1 << 4, p: 1, r: 0, salt: String::from("00") };
+        assert!(matches!(malformed.decrypt("testpassword"), Err(Error::InvalidRequest(_))));
+
+        // Keys are never imported in plaintext
+        assert!(request::parse::<request::WalletAdd>(r#"{"secret_key":"7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d"}"#).is_err());
+        assert!(request::parse::<request::WalletAdd>("{}").is_ok());
+    }
+
+    #[test]
+    fn test_sign_and_recover() {
+        let (secret_key, public_key) = wallet::generate_keypair(Some("89D7C6BB9F58F1EECDE6009243B6B3D968277B37A92B4D3C3D5C167E979BCF55")).unwrap();
+        let wallet = Wallet::new(&secret_key, &public_key);
+        let address = wallet::eth_address(&public_key);
+        assert!(!wallet.to_string().to_lowercase().contains("89d7c6bb9f58f1eecde6009243b6b3d968277b37a92b4d3c3d5c167e979bcf55"));
+        assert!(wallet.ensure_not_escrow().is_ok());
+
+        let sig = wallet.sign_message(b"hello").unwrap();
+        let sig_hex = signature::to_json(&sig, &signature::message_hash(b"hello"))["signature"].as_str().unwrap().to_string();
+        assert_eq!(signature::recover(&sig_hex, &signature::message_hash(b"hello")).unwrap(), address);
+
+        let typed_data = signature::parse_typed_data(&serde_json::json!({
+            "types": {
+                "EIP712Domain": [{"name": "name", "type": "string"}, {"name": "chainId", "type": "uint256"}],
+                "Settlement": [{"name": "id", "type": "string"}, {"name": "amount", "type": "uint256"}]
+            },
+            "primaryType": "Settlement",
+            "domain": {"name": "klave-evm-pvp", "chainId": 1},
+            "message": {"id": "pvp-1", "amount": "1000"}
+        })).unwrap();
+        let sig = wallet.sign_typed_data(&typed_data).unwrap();
+        let hash = signature::typed_data_hash(&typed_data).unwrap();
+        assert_eq!(sig.recover_address_from_prehash(&hash).unwrap(), address);
+    }
+
+    #[test]
+    fn test_lock_proof_vector() {
+        let attestation = lock_proof::LockAttestation::create(
+            Address::from_str("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266").unwrap(),
+            "sepolia",
+            "lock",
+            U256::from(1_000_000_000_000_000_000u128),
+            U256::from(1_500_000_000_000_000_000u128),
+            1_700_000_000_000_000_000);
+        let hash = attestation.eip712_signing_hash(&lock_proof::domain());
+        assert_eq!(hash.to_string(), "0x6d80c4e1fcf41010996603f535ea0bb671da5c8d8d8bd0923e927b2d9352d0d2");
+
+        // Same attestation written the way an external verifier would hand it to eth_signTypedData_v4
+        let typed_data = signature::parse_typed_data(&serde_json::json!({
+            "types": {
+                "EIP712Domain": [{"name": "name", "type": "string"}, {"name": "version", "type": "string"}],
+                "LockAttestation": [
+                    {"name": "wallet", "type": "address"},
+                    {"name": "network", "type": "string"},
+                    {"name": "action", "type": "string"},
+                    {"name": "amount", "type": "uint256"},
+                    {"name": "lockedAmount", "type": "uint256"},
+                    {"name": "timestamp", "type": "uint64"}
+                ]
+            },
+            "primaryType": "LockAttestation",
+            "domain": {"name": "Klave EVM PvP", "version": "1"},
+            "message": {
+                "wallet": "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+                "network": "sepolia",
+                "action": "lock",
+                "amount": "1000000000000000000",
+                "lockedAmount": "1500000000000000000",
+                "timestamp": 1700000000000000000u64
+            }
+        })).unwrap();
+        assert_eq!(signature::typed_data_hash(&typed_data).unwrap(), hash);
+
+        let signer = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80".parse::<PrivateKeySigner>().unwrap();
+        let proof = attestation.to_proof(&signer.sign_hash_sync(&hash).unwrap(), signer.address());
+        assert_eq!(proof["signature"], "0xaa05aebf72eff75e107a4276c708b190662687597d963e09e56ea4209497037104430819b62ea3ef374584d7d29a24e1c959e4a066b9ce4f7555b5ef9a796dbd1c");
+        assert_eq!(lock_proof::verify_with(&proof, signer.address()).unwrap(), (true, signer.address()));
+
+        let mut tampered = proof.clone();
+        tampered["typed_data"]["message"]["lockedAmount"] = serde_json::json!("0x0");
+        assert!(!lock_proof::verify_with(&tampered, signer.address()).unwrap().0);
+
+        // The attested wallet signing its own attestation does not make it valid
+        let wallet_signer = "59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d".parse::<PrivateKeySigner>().unwrap();
+        let forged = attestation.to_proof(&wallet_signer.sign_hash_sync(&hash).unwrap(), wallet_signer.address());
+        assert!(!lock_proof::verify_with(&forged, signer.address()).unwrap().0);
+        assert!(lock_proof::is_attestation_domain(&typed_data));
+    }
+
+    #[test]
+    fn test_abi_mint_hex_encode() {
+        let json_abi = abi::parse_abi(&serde_json::json!([{
+            "type": "function",
+            "name": "mint",
+            "inputs": [{"name": "to", "type": "address"}, {"name": "amount", "type": "uint256"}],
+            "outputs": [],
+            "stateMutability": "nonpayable"
+        }])).unwrap();
+        let function = abi::find_function(&json_abi, "mint(address, uint256)").unwrap();
+        let input = abi::encode_call(function, &serde_json::json!(["0x0E8f8ad443a1270a7D8Af3B30D288DaA0F988e40", "10000000000000000"])).unwrap();
+
+        assert_eq!(hex::encode(input), "40c10f190000000000000000000000000e8f8ad443a1270a7d8af3b30d288daa0f988e40000000000000000000000000000000000000000000000000002386f26fc10000".to_string());
+    }
+
+    #[test]
+    fn test_request_parse() {
+        let req = request::parse::<request::WalletTransfer>(r#"{
+            "eth_address": "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266",
+            "network_name": "sepolia",
+            "chain_id": 11155111,
+            "to": "0x0E8f8ad443a1270a7D8Af3B30D288DaA0F988e40",
+            "value": "0x2386F26FC10000"
+        }"#).unwrap();
+        assert_eq!(req.eth_address.to_string(), "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266");
+        assert_eq!(req.value, U256::from(10_000_000_000_000_000u64));
+        assert!(req.nonce.is_none());
+
+        // Every missing or invalid field is reported at once
+        let err = request::parse::<request::WalletTransfer>(r#"{"eth_address": "0x1234", "chain_id": 1, "to": "0x0E8f8ad443a1270a7D8Af3B30D288DaA0F988e40", "value": "0xzz"}"#).unwrap_err();
+        let message = err.message();
+        assert_eq!(err.code(), "invalid_request");
+        assert!(message.contains("eth_address:"));
+        assert!(message.contains("network_name: missing"));
+        assert!(message.contains("value:"));
+        assert!(!message.contains("chain_id"));
+        assert!(!message.contains("to:"));
+    }
+
+    #[test]
+    fn test_settlement_state_machine() {
+        let (a, b, c) = ("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266", "0x0E8f8ad443a1270a7D8Af3B30D288DaA0F988e40", "0x70997970C51812dc3A010C7d01b50e0d17dc79C8");
+        let leg = |from: &str, to: &str, network_name: &str| settlement::Leg::new(Participant {
+            network_name: network_name.to_string(),
+            address: from.to_string(),
+            amount: U256::from(100),
+            token_address: None,
+        }, to);
+        assert!(Settlement::new(vec![leg(a, b, "chain1")]).is_err());
+
+        let mut s = Settlement::new(vec![leg(a, b, "chain1"), leg(b, c, "chain2"), leg(c, a, "chain1")]).unwrap();
+        assert_eq!(s.network_names(), vec!["chain1", "chain2"]);
+        for network_name in s.network_names() {
+            s.escrows.push(settlement::Escrow { address: format!("escrow-{}", network_name), network_name });
+        }
+        let payout = s.expected_payout(1).unwrap();
+        assert_eq!((payout.from.as_str(), payout.to.as_str()), ("escrow-chain2", c));
+        assert_eq!(s.expected_funding(2).unwrap().to, "escrow-chain1");
+
+        // Payout only becomes possible once every leg is funded
+        let mut step = |index: usize, state: settlement::LegState, expected: settlement::SettlementState| {
+            s.legs[index].state = state;
+            s.update_state();
+            assert_eq!(s.state, expected);
+        };
+        step(0, settlement::LegState::Funded, settlement::SettlementState::Funding);
+        step(1, settlement::LegState::FundingCommitted, settlement::SettlementState::Funding);
+        step(2, settlement::LegState::FundingCommitted, settlement::SettlementState::Finalisation);
+        step(1, settlement::LegState::Funded, settlement::SettlementState::Finalisation);
+        step(2, settlement::LegState::Funded, settlement::SettlementState::Payout);
+        step(0, settlement::LegState::PayoutSent, settlement::SettlementState::Payout);
+        step(0, settlement::LegState::Paid, settlement::SettlementState::Payout);
+        step(1, settlement::LegState::Paid, settlement::SettlementState::Payout);
+        step(2, settlement::LegState::Paid, settlement::SettlementState::Complete);
+        assert!(s.is_payout_started());
+    }
+    #[test]
+    fn test_delivery_vs_payment() {
+        let (seller, buyer, escrow) = ("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266", "0x0E8f8ad443a1270a7D8Af3B30D288DaA0F988e40", "0x70997970C51812dc3A010C7d01b50e0d17dc79C8");
+        let contract = "0x5FbDB2315678afecb367f032d93F642f64180aa3";
+        assert!(Asset::new(dvp::AssetStandard::Erc721, contract, U256::from(7), Some(U256::from(2))).is_err());
+        assert!(Asset::new(dvp::AssetStandard::Erc1155, contract, U256::from(7), None).is_err());
+
+        let nft = Asset::new(dvp::AssetStandard::Erc721, contract, U256::from(7), None).unwrap();
+        assert_eq!(nft.amount, U256::from(1));
+        let input = nft.transfer_input(Address::from_str(seller).unwrap(), Address::from_str(buyer).unwrap());
+        assert_eq!(hex::encode(&input[..4]), "42842e0e");
+        let units = Asset::new(dvp::AssetStandard::Erc1155, contract, U256::from(7), Some(U256::from(5))).unwrap();
+        let input = units.transfer_input(Address::from_str(seller).unwrap(), Address::from_str(buyer).unwrap());
+        assert_eq!(hex::encode(&input[..4]), "f242432a");
+
+        // The payment goes into escrow from the buyer, then out of it to the seller
+        let mut dvp = DeliveryVsPayment {
+            delivery: Delivery { network_name: String::from("chain1"), address: seller.to_string(), asset: nft },
+            payment: Participant { network_name: String::from("chain2"), address: buyer.to_string(), amount: U256::from(100), token_address: None },
+            state_machine: PvPstate::AwaitingSourceReceive,
+            network_transactions: Vec::new(),
+        };
+        assert!(dvp.expected_payment(&PvPstate::AwaitingSourceReceive, escrow).is_none());
+        let deposit = dvp.expected_payment(&PvPstate::AwaitingDestinationReceive, escrow).unwrap();
+        assert_eq!((deposit.from.as_str(), deposit.to.as_str(), deposit.network_name.as_str()), (buyer, escrow, "chain2"));
+        let payout = dvp.expected_payment(&PvPstate::AwaitingSourceSend, escrow).unwrap();
+        assert_eq!((payout.from.as_str(), payout.to.as_str()), (escrow, seller));
+
+        assert!(!dvp.is_payout_started());
+        dvp.state_machine = PvPstate::AwaitingDestinationSendFinalized;
+        assert!(dvp.is_payout_started());
+    }
+
+    #[test]
+    fn test_auth_token_expiry() {
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+        use klave_networks::network::AuthToken;
+        let second = 1_000_000_000u64;
+        let now = 1_700_000_000 * second;
+
+        // The exp claim of a JWT wins over the configured TTL
+        let jwt = format!("{}.{}.signature", URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256"}"#), URL_SAFE_NO_PAD.encode(r#"{"sub":"pvp","exp":1700000600}"#));
+        let token = AuthToken::new(&jwt, now, 60);
+        assert_eq!(token.expires_at, 1_700_000_600 * second);
+        assert!(token.is_valid(now + 500 * second));
+        assert!(!token.is_valid(now + 580 * second));
+
+        let opaque = AuthToken::new("opaque-token", now, 60);
+        assert_eq!(opaque.expires_at, now + 60 * second);
+        assert!(opaque.is_valid(now));
+        assert!(!opaque.is_valid(now + 60 * second));
+    }
+
+    #[test]
+    fn test_request_format_auth() {
+        use klave_networks::{http::{self, Auth}, network::Credentials};
+        let mut headers = std::collections::BTreeMap::new();
+        headers.insert(String::from("X-Tenant"), String::from("pvp"));
+        let body = r#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":1}"#;
+        let format = |auth: &Auth| http::request_format("https://rpc.example.com/v1/", body, auth, &headers).unwrap();
+
+        let r = format(&Auth::Basic { username: "alice", password: "secret" });
+        assert_eq!(r.headers()["Authorization"], "Basic YWxpY2U6c2VjcmV0");
+        assert_eq!(r.headers()["X-Tenant"], "pvp");
+        let r = format(&Auth::Header { name: "x-api-key", value: "k1" });
+        assert_eq!(r.headers()["x-api-key"], "k1");
+        let r = format(&Auth::UrlPath("k2"));
+        assert_eq!(r.uri().to_string(), "https://rpc.example.com/v1/k2");
+        assert!(r.headers().get("Authorization").is_none());
+
+        let r = format(&Auth::Hmac { key_id: "id", secret: "key", timestamp: 1_700_000_000 });
+        assert_eq!(r.headers()["X-Timestamp"], "1700000000");
+        let signature = r.headers()["X-Signature"].to_str().unwrap().to_string();
+        assert_eq!(signature.len(), 64);
+        let r = format(&Auth::Hmac { key_id: "id", secret: "key", timestamp: 1_700_000_001 });
+        assert_ne!(r.headers()["X-Signature"].to_str().unwrap(), signature);
+
+        // Credentials saved before auth schemes existed are logins
+        let legacy = Credentials::from_value(serde_json::json!({ "username": "alice", "password": "secret" })).unwrap();
+        assert!(matches!(legacy, Credentials::Login { .. }));
+        let api_key = Credentials::from_value(serde_json::json!({ "scheme": "api_key", "header": "x-api-key", "api_key": "k1" })).unwrap();
+        assert!(matches!(api_key, Credentials::ApiKey { .. }));
+    }
+
+    #[test]
+    fn test_network_credentials_redacted() {
+        use klave_networks::network::{Credentials, StoredCredentials, StoredHeaders};
+        let mut network = Network::new("chain1", Some(1), "https://rpc.example.com", None, None);
+        network.set_credentials(&Credentials::Basic { username: String::from("alice"), password: String::from("secret") });
+        let displayed = network.to_string();
+        assert!(!displayed.contains("secret") && !displayed.contains("alice"));
+        assert!(displayed.contains(r#""credentials":{"scheme":"basic"}"#));
+
+        // Plaintext credentials of older ledgers still load, sealed ones are kept as is
+        let legacy: Network = serde_json::from_str(r#"{"name":"chain1","chain_id":1,"rpc_url":"https://rpc.example.com","gas_price":null,"confirmations":null,"fee_policy":null,"tx_type":null,"credentials":{"username":"alice","password":"secret"}}"#).unwrap();
+        assert!(matches!(legacy.get_credentials(), Some(StoredCredentials::Plain(Credentials::Login { .. }))));
+        let sealed: Network = serde_json::from_str(r#"{"name":"chain1","rpc_url":"https://rpc.example.com","credentials":{"scheme":"hmac","iv":"00","ciphertext":"01"}}"#).unwrap();
+        assert!(matches!(sealed.get_credentials(), Some(StoredCredentials::Sealed(_))));
+        assert!(!sealed.to_string().contains("ciphertext"));
+
+        // Headers usually carry API keys, their values are hidden
+        network.set_headers(std::collections::BTreeMap::from([(String::from("x-api-key"), String::from("k1"))]));
+        let displayed = network.to_string();
+        assert!(displayed.contains(r#""headers":{"x-api-key":"[redacted]"}"#) && !displayed.contains("k1"));
+        let legacy: Network = serde_json::from_str(r#"{"name":"chain1","rpc_url":"https://rpc.example.com","headers":{"x-api-key":"k1"}}"#).unwrap();
+        assert_eq!(legacy.get_headers().open().unwrap()["x-api-key"], "k1");
+        let sealed: Network = serde_json::from_str(r#"{"name":"chain1","rpc_url":"https://rpc.example.com","headers":{"names":["x-api-key"],"iv":"00","ciphertext":"01"}}"#).unwrap();
+        assert!(matches!(sealed.get_headers(), StoredHeaders::Sealed(_)));
+        assert!(sealed.to_string().contains(r#""headers":{"x-api-key":"[redacted]"}"#));
+    }
+
+    #[test]
+    fn test_network_endpoints_failover() {
+        use klave_networks::network::{Credentials, EndpointStatus};
+        let mut network = Network::new("chain1", Some(1), "https://primary.example.com", None, None);
+        network.set_endpoints(vec![
+            Endpoint::new("https://backup.example.com", None, None),
+            Endpoint::new("https://preferred.example.com", Some(&Credentials::ApiKey { header: String::from("X-Api-Key"), api_key: String::from("secret") }), Some(5)),
+            Endpoint::new("https://disabled.example.com", None, Some(0)),
+        ]);
+        let urls = network.endpoints().iter().map(|e| e.rpc_url.clone()).collect::<Vec<_>>();
+        assert_eq!(urls, vec!["https://preferred.example.com", "https://primary.example.com", "https://backup.example.com"]);
+        assert!(!network.to_string().contains("secret"));
+
+        let mut status = EndpointStatus::default();
+        status.record_failure(0, &Error::Rpc(String::from("HTTP 503")));
+        assert!(status.is_backing_off(1));
+        status.record_failure(0, &Error::Rpc(String::from("HTTP 503")));
+        assert_eq!(status.retry_after, 10 * 1_000_000_000);
+        status.record_served(1);
+        assert!(!status.is_backing_off(1) && status.failures == 0 && status.served == 1);
+
+        assert!(klave_networks::http::is_rate_limited(r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32005,"message":"limit exceeded"}}"#));
+        assert!(!klave_networks::http::is_rate_limited(r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"nonce too low"}}"#));
+        assert!(klave_networks::http::is_json_rpc_response(r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"nonce too low"}}"#));
+        assert!(klave_networks::http::is_json_rpc_response(r#"{"jsonrpc":"2.0","id":1,"result":null}"#));
+        assert!(!klave_networks::http::is_json_rpc_response("<html><body>502 Bad Gateway</body></html>"));
+    }
+
+    #[test]
+    fn test_network_quorum() {
+        use klave_networks::network::{EndpointStatus, Quorum};
+        let mut network = Network::new("chain1", Some(1), "https://rpc1.example.com", None, None);
+        network.set_endpoints(vec![
+            Endpoint::new("https://rpc2.example.com", None, None),
+            Endpoint::new("https://rpc3.example.com", None, None),
+        ]);
+        let enabled = network.endpoints().len();
+        assert!(Quorum { size: 3, threshold: 2 }.check(enabled).is_ok());
+        assert!(Quorum { size: 2, threshold: 2 }.check(enabled).is_ok());
+        // Two results could each gather half of the endpoints
+        assert!(Quorum { size: 2, threshold: 1 }.check(enabled).is_err());
+        assert!(Quorum { size: 3, threshold: 4 }.check(enabled).is_err());
+        assert!(Quorum { size: 5, threshold: 3 }.check(enabled).is_err());
+
+        let legacy: Network = serde_json::from_str(r#"{"name":"chain1","rpc_url":"https://rpc1.example.com"}"#).unwrap();
+        assert_eq!(legacy.get_quorum(), None);
+        network.set_quorum(Some(Quorum { size: 3, threshold: 2 }));
+        let reloaded: Network = serde_json::from_str(&network.to_string()).unwrap();
+        assert_eq!(reloaded.get_quorum(), Some(Quorum { size: 3, threshold: 2 }));
+
+        // A lagging or lying endpoint cannot move the agreed head on its own
+        use klave_networks::network::agreed_result;
+        let heads = [serde_json::json!("0x10"), serde_json::json!("0x64"), serde_json::json!("0xf")];
+        assert_eq!(agreed_result("eth_blockNumber", &heads, 2), Some(serde_json::json!("0x10")));
+        let balances = [serde_json::json!("0x1"), serde_json::json!("0x2"), serde_json::json!("0x1")];
+        assert_eq!(agreed_result("eth_getBalance", &balances, 2), Some(serde_json::json!("0x1")));
+        assert_eq!(agreed_result("eth_getBalance", &balances[..2], 2), None);
+
+        let mut status: EndpointStatus = serde_json::from_str(r#"{"rpc_url":"https://rpc3.example.com","served":4,"last_served_at":1,"failures":0,"retry_after":0,"last_error":null}"#).unwrap();
+        status.record_disagreement(String::from("answered 0x1 where the quorum answered 0x2"));
+        assert_eq!(status.disagreements, 1);
+        assert!(status.last_disagreement.unwrap().contains("0x2"));
+    }
+
+    #[test]
+    fn test_token_transfer_log() {
+        use alloy_primitives::{LogData, B256, U256};
+        use alloy_sol_types::SolEvent;
+        use solidity::Transfer;
+        let token = Address::from_str("0x00000000000000000000000000000000000000aa").unwrap();
+        let from = Address::from_str("0x0000000000000000000000000000000000000001").unwrap();
+        let to = Address::from_str("0x0000000000000000000000000000000000000002").unwrap();
+        let transfer_log = |address: Address| alloy_rpc_types_eth::Log {
+            inner: alloy_primitives::Log {
+                address,
+                data: LogData::new_unchecked(
+                    vec![Transfer::SIGNATURE_HASH, B256::left_padding_from(from.as_slice()), B256::left_padding_from(to.as_slice())],
+                    U256::from(100).to_be_bytes::<32>().to_vec().into(),
+                ),
+            },
+            ..Default::default()
+        };
+        assert!(transaction::is_token_transfer(&transfer_log(token), token, from, to, U256::from(100)));
+        assert!(!transaction::is_token_transfer(&transfer_log(token), token, from, to, U256::from(99)));
+        // The same event from another contract does not fund the leg
+        let other = Address::from_str("0x00000000000000000000000000000000000000bb").unwrap();
+        assert!(!transaction::is_token_transfer(&transfer_log(other), token, from, to, U256::from(100)));
+    }
+
+    #[test]
+    fn test_fee_overrides() {
+        use klave_networks::fees::FeeEstimate;
+        let estimate = FeeEstimate { max_fee_per_gas: 210, max_priority_fee_per_gas: 10 };
+        let f = estimate.with_overrides(Some(5), None);
+        assert_eq!((f.max_fee_per_gas, f.max_priority_fee_per_gas), (5, 5));
+        let f = estimate.with_overrides(None, Some(50));
+        assert_eq!((f.max_fee_per_gas, f.max_priority_fee_per_gas), (250, 50));
+        let f = estimate.with_overrides(Some(300), Some(2));
+        assert_eq!((f.max_fee_per_gas, f.max_priority_fee_per_gas), (300, 2));
+    }
+
+    #[test]
+    fn test_escrow_gas_limits() {
+        let (a, b, token) = ("0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266", "0x0E8f8ad443a1270a7D8Af3B30D288DaA0F988e40", "0x5FbDB2315678afecb367f032d93F642f64180aa3");
+        let participant = |address: &str, network_name: &str, token_address: Option<&str>| Participant {
+            network_name: network_name.to_string(),
+            address: address.to_string(),
+            amount: U256::from(100),
+            token_address: token_address.map(String::from),
+        };
+        let mut tx = Transaction {
+            id: String::from("tx"),
+            timestamp: String::from("0"),
+            deadline: Some(0),
+            payment_vs_payment: Some(PaymentVsPayment {
+                source: participant(a, "chain1", None),
+                destination: participant(b, "chain1", Some(token)),
+                state_machine: PvPstate::AwaitingSourceReceive,
+                network_transactions: Vec::new(),
+            }),
+            settlement: None,
+            delivery_vs_payment: None,
+            escrow_address: String::from("escrow"),
+        };
+
+        // Both legs leave the escrow on the same network, whether paid out or refunded
+        assert_eq!(tx.escrow_gas_limits("chain1"), vec![wallet::TRANSFER_GAS_LIMIT, wallet::TOKEN_TRANSFER_GAS_LIMIT]);
+        assert!(tx.escrow_gas_limits("chain2").is_empty());
+        assert_eq!(tx.escrow_on("chain2").unwrap(), "escrow");
+
+        // Once the source leg is paid out, only the destination payout is left
+        tx.payment_vs_payment.as_mut().unwrap().state_machine = PvPstate::AwaitingSourceSend;
+        assert_eq!(tx.escrow_gas_limits("chain1"), vec![wallet::TOKEN_TRANSFER_GAS_LIMIT]);
+        tx.payment_vs_payment.as_mut().unwrap().state_machine = PvPstate::Complete;
+        assert!(tx.escrow_gas_limits("chain1").is_empty());
+
+        // Settlement legs are paid by the escrow of their network
+        let mut s = Settlement::new(vec![Leg::new(participant(a, "chain1", None), b), Leg::new(participant(b, "chain2", Some(token)), a)]).unwrap();
+        s.escrows.push(settlement::Escrow { network_name: String::from("chain2"), address: String::from("escrow-chain2") });
+        s.legs[0].state = settlement::LegState::Paid;
+        tx.payment_vs_payment = None;
+        tx.settlement = Some(s);
+        assert!(tx.escrow_gas_limits("chain1").is_empty());
+        assert_eq!(tx.escrow_gas_limits("chain2"), vec![wallet::TOKEN_TRANSFER_GAS_LIMIT]);
+        assert_eq!(tx.escrow_on("chain2").unwrap(), "escrow-chain2");
+        assert!(tx.escrow_on("chain1").is_err());
+    }
+
+    #[test]
+    fn test_admin_migration() {
+        // users stored before roles existed carry no role field
+        let legacy: User = serde_json::from_str(r#"{"id":"alice","transactions":[],"wallets":[]}"#).unwrap();
+        assert_eq!(legacy.get_role(), UserRole::Participant);
+        assert!(Users::lacks_admin(&[]));
+        assert!(Users::lacks_admin(&[legacy]));
+
+        let mut admin = User::new("bob");
+        admin.set_role(UserRole::Admin);
+        assert!(!Users::lacks_admin(&[User::new("carol"), admin]));
+    }
+}
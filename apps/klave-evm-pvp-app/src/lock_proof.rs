@@ -0,0 +1,125 @@
+use alloy_dyn_abi::TypedData;
+use alloy_primitives::{hex, Address, PrimitiveSignature, U256};
+use alloy_signer::SignerSync;
+use alloy_signer_local::PrivateKeySigner;
+use alloy_sol_types::{sol, Eip712Domain, SolStruct};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::signature;
+use crate::error::Error;
+use crate::wallet;
+
+/// EIP-712 domain of the lock attestations. No chain id nor verifying contract is set:
+/// the attestation is checked off-chain, the network is part of the message.
+pub(crate) const DOMAIN_NAME: &str = "Klave EVM PvP";
+pub(crate) const DOMAIN_VERSION: &str = "1";
+pub(crate) const PRIMARY_TYPE: &str = "LockAttestation";
+
+pub(crate) const ATTESTATION_KEY_TABLE: &str = "attestationKeyTable";
+const ATTESTATION_KEY: &str = "LOCK_ATTESTATION";
+
+sol! {
+    /// State of the lock of `wallet` on `network` right after `action` ("lock" or "unlock") of `amount`.
+    /// `timestamp` is the enclave trusted time in nanoseconds.
+    #[derive(Debug, Serialize, Deserialize)]
+    struct LockAttestation {
+        address wallet;
+        string network;
+        string action;
+        uint256 amount;
+        uint256 lockedAmount;
+        uint64 timestamp;
+    }
+}
+
+pub fn domain() -> Eip712Domain {
+    Eip712Domain::new(Some(DOMAIN_NAME.into()), Some(DOMAIN_VERSION.into()), None, None, None)
+}
+
+/// Key the enclave signs attestations with. Unlike wallet keys, which their owners can sign
+/// any typed data with or export, it never leaves the ledger.
+fn attestation_signer() -> Result<Option<PrivateKeySigner>, Error> {
+    match klave::ledger::get_table(ATTESTATION_KEY_TABLE).get(ATTESTATION_KEY) {
+        Ok(v) if !v.is_empty() => Ok(Some(String::from_utf8_lossy(&v).parse::<PrivateKeySigner>()?)),
+        _ => Ok(None)
+    }
+}
+
+/// Loads the attestation key, generating it on first use.
+fn load_or_create_attestation_signer() -> Result<PrivateKeySigner, Error> {
+    if let Some(signer) = attestation_signer()? {
+        return Ok(signer);
+    }
+    let (secret_key, _) = wallet::generate_keypair(None)?;
+    let secret_key = hex::encode(secret_key.to_bytes());
+    if let Err(e) = klave::ledger::get_table(ATTESTATION_KEY_TABLE).set(ATTESTATION_KEY, secret_key.as_bytes()) {
+        return Err(Error::Ledger(format!("failed to save attestation key: {}", e)));
+    }
+    Ok(secret_key.parse::<PrivateKeySigner>()?)
+}
+
+/// Signs `attestation` with the attestation key of the enclave and returns its proof.
+pub fn attest(attestation: &LockAttestation) -> Result<Value, Error> {
+    let signer = load_or_create_attestation_signer()?;
+    let signature = signer.sign_hash_sync(&attestation.eip712_signing_hash(&domain()))?;
+    Ok(attestation.to_proof(&signature, signer.address()))
+}
+
+impl LockAttestation {
+    pub fn create(wallet: Address, network: &str, action: &str, amount: U256, locked_amount: U256, timestamp: u64) -> LockAttestation {
+        LockAttestation {
+            wallet,
+            network: network.to_string(),
+            action: action.to_string(),
+            amount,
+            lockedAmount: locked_amount,
+            timestamp,
+        }
+    }
+
+    /// Full EIP-712 document, as accepted by `eth_signTypedData_v4` and `wallet_verify_lock_proof`.
+    pub fn typed_data(&self) -> TypedData {
+        TypedData::from_struct(self, Some(domain()))
+    }
+
+    /// Builds the proof returned by `wallet_lock` and `wallet_unlock`, signed by `signer`.
+    pub fn to_proof(&self, signature: &PrimitiveSignature, signer: Address) -> Value {
+        let hash = self.eip712_signing_hash(&domain());
+        let mut proof = signature::to_json(signature, &hash);
+        proof["signer"] = Value::String(signer.to_string());
+        proof["typed_data"] = json!(self.typed_data());
+        proof
+    }
+}
+
+/// Checks a proof produced by `attest` against the attestation key of the enclave and returns
+/// the recovered signer. A proof cannot be valid before the enclave has attested any lock.
+pub fn verify(proof: &Value) -> Result<(bool, Address), Error> {
+    let attester = attestation_signer()?.map(|s| s.address()).unwrap_or(Address::ZERO);
+    verify_with(proof, attester)
+}
+
+/// Whether `typed_data` lies in the domain of the lock attestations, which only the enclave signs.
+pub fn is_attestation_domain(typed_data: &TypedData) -> bool {
+    typed_data.domain == domain()
+}
+
+/// Checks a proof produced by `LockAttestation::to_proof` against `attester`.
+/// Only `typed_data` and `signature` are used, the other fields are informative.
+pub fn verify_with(proof: &Value, attester: Address) -> Result<(bool, Address), Error> {
+    let typed_data = signature::parse_typed_data(&proof["typed_data"])?;
+    if typed_data.primary_type != PRIMARY_TYPE {
+        return Err(Error::InvalidRequest(format!("unexpected primary type '{}'", typed_data.primary_type)));
+    }
+    if typed_data.domain != domain() {
+        return Err(Error::InvalidRequest("unexpected EIP-712 domain".to_string()));
+    }
+    // The message must still be a well-formed attestation
+    serde_json::from_value::<LockAttestation>(typed_data.message.clone())?;
+    let signature = match proof["signature"].as_str() {
+        Some(s) => s,
+        None => return Err(Error::InvalidRequest("signature not found".to_string()))
+    };
+    let recovered = signature::recover(signature, &typed_data.eip712_signing_hash()?)?;
+    Ok((attester != Address::ZERO && recovered == attester, recovered))
+}
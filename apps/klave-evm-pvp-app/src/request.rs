@@ -0,0 +1,822 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use alloy_dyn_abi::TypedData;
+use alloy_json_abi::JsonAbi;
+use alloy_primitives::{hex, Address, Bytes, B256, U256};
+use alloy_rpc_types_eth::AccessList;
+use serde::de::{self, value::BorrowedStrDeserializer, DeserializeOwned, DeserializeSeed, MapAccess, Visitor};
+use serde::{forward_to_deserialize_any, Deserialize, Deserializer};
+use serde_json::Value;
+use crate::abi;
+use crate::dvp::AssetStandard;
+use crate::error::Error;
+use crate::keystore::Keystore;
+use crate::klave_networks::{fees::FeePolicy, network::{Credentials, Quorum, TransactionType}};
+use crate::signature;
+use crate::user::UserRole;
+
+/// Parses the command of a route into its request type.
+///
+/// When the command does not match, a single error lists every missing or invalid field.
+pub fn parse<T: DeserializeOwned>(cmd: &str) -> Result<T, Error> {
+    let Ok(v) = serde_json::from_str::<Value>(cmd) else {
+        return Err(Error::InvalidRequest(format!("failed to parse '{}' as json", cmd)));
+    };
+    match T::deserialize(&v) {
+        Ok(r) => Ok(r),
+        Err(e) => {
+            let errors = field_errors::<T>(&v);
+            match errors.is_empty() {
+                true => Err(Error::InvalidRequest(e.to_string())),
+                false => Err(invalid(errors))
+            }
+        }
+    }
+}
+
+fn invalid(errors: Vec<String>) -> Error {
+    Error::InvalidRequest(format!("invalid request: {}", errors.join("; ")))
+}
+
+/// Deserializes `T` once per field, each time from that field alone, so that a failure
+/// can be attributed to the field being probed.
+fn field_errors<T: DeserializeOwned>(v: &Value) -> Vec<String> {
+    let Some(object) = v.as_object() else {
+        return Vec::new();
+    };
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(Fields(&mut fields));
+
+    let mut errors = Vec::new();
+    for field in fields {
+        let mut error = None;
+        let _ = T::deserialize(Probe { field, value: object.get(*field), error: &mut error });
+        if let Some(e) = error {
+            errors.push(format!("{}: {}", field, e));
+        }
+    }
+    errors
+}
+
+/// Captures the field names a derived struct passes to `deserialize_struct`.
+struct Fields<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for Fields<'_> {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("expected a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], _visitor: V) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("fields captured"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit
+        unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// A struct holding `field` only, set to `value` or absent.
+struct Probe<'a, 'e> {
+    field: &'static str,
+    value: Option<&'a Value>,
+    error: &'e mut Option<String>,
+}
+
+impl<'de> Deserializer<'de> for Probe<'de, '_> {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(ProbeAccess { probe: self, key_sent: false })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit
+        unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct ProbeAccess<'a, 'e> {
+    probe: Probe<'a, 'e>,
+    key_sent: bool,
+}
+
+impl<'de> MapAccess<'de> for ProbeAccess<'de, '_> {
+    type Error = serde_json::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+        if self.key_sent {
+            return Ok(None);
+        }
+        self.key_sent = true;
+        seed.deserialize(BorrowedStrDeserializer::new(self.probe.field)).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        let result = match self.probe.value {
+            Some(v) => seed.deserialize(v),
+            None => seed.deserialize(Absent)
+        };
+        if let Err(e) = &result {
+            *self.probe.error = Some(e.to_string());
+        }
+        result
+    }
+}
+
+/// Value of a field missing from the command: only optional fields accept it.
+struct Absent;
+
+impl<'de> Deserializer<'de> for Absent {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("missing"))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_none()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit
+        unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// Deserializes a `U256` from a hex string, with or without the `0x` prefix.
+pub fn hex_u256<'de, D: Deserializer<'de>>(d: D) -> Result<U256, D::Error> {
+    let s = String::deserialize(d)?;
+    U256::from_str_radix(s.trim_start_matches("0x"), 16).map_err(de::Error::custom)
+}
+
+pub fn opt_hex_u256<'de, D: Deserializer<'de>>(d: D) -> Result<Option<U256>, D::Error> {
+    match Option::<String>::deserialize(d)? {
+        Some(s) => U256::from_str_radix(s.trim_start_matches("0x"), 16).map(Some).map_err(de::Error::custom),
+        None => Ok(None)
+    }
+}
+
+/// Deserializes an `Address` from a hex string, whatever its case.
+pub fn address<'de, D: Deserializer<'de>>(d: D) -> Result<Address, D::Error> {
+    let s = String::deserialize(d)?;
+    Address::from_str(&s).map_err(de::Error::custom)
+}
+
+pub fn opt_address<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Address>, D::Error> {
+    match Option::<String>::deserialize(d)? {
+        Some(s) => Address::from_str(&s).map(Some).map_err(de::Error::custom),
+        None => Ok(None)
+    }
+}
+
+pub fn opt_b256<'de, D: Deserializer<'de>>(d: D) -> Result<Option<B256>, D::Error> {
+    match Option::<String>::deserialize(d)? {
+        Some(s) => B256::from_str(&s).map(Some).map_err(de::Error::custom),
+        None => Ok(None)
+    }
+}
+
+/// Deserializes bytes from a hex string, with or without the `0x` prefix.
+pub fn hex_bytes<'de, D: Deserializer<'de>>(d: D) -> Result<Bytes, D::Error> {
+    let s = String::deserialize(d)?;
+    hex::decode(s.trim_start_matches("0x")).map(Bytes::from).map_err(de::Error::custom)
+}
+
+pub fn opt_json_abi<'de, D: Deserializer<'de>>(d: D) -> Result<Option<JsonAbi>, D::Error> {
+    match Option::<Value>::deserialize(d)? {
+        Some(v) => abi::parse_abi(&v).map(Some).map_err(de::Error::custom),
+        None => Ok(None)
+    }
+}
+
+pub fn json_abi<'de, D: Deserializer<'de>>(d: D) -> Result<JsonAbi, D::Error> {
+    let v = Value::deserialize(d)?;
+    abi::parse_abi(&v).map_err(de::Error::custom)
+}
+
+pub fn typed_data<'de, D: Deserializer<'de>>(d: D) -> Result<TypedData, D::Error> {
+    let v = Value::deserialize(d)?;
+    signature::parse_typed_data(&v).map_err(de::Error::custom)
+}
+
+pub fn opt_typed_data<'de, D: Deserializer<'de>>(d: D) -> Result<Option<TypedData>, D::Error> {
+    match Option::<Value>::deserialize(d)? {
+        Some(v) => signature::parse_typed_data(&v).map(Some).map_err(de::Error::custom),
+        None => Ok(None)
+    }
+}
+
+/// Deserializes `T` from a JSON object or from a string holding one.
+pub fn json_or_string<'de, D: Deserializer<'de>, T: DeserializeOwned>(d: D) -> Result<T, D::Error> {
+    match Value::deserialize(d)? {
+        Value::String(s) => serde_json::from_str::<T>(&s).map_err(de::Error::custom),
+        v => serde_json::from_value::<T>(v).map_err(de::Error::custom)
+    }
+}
+
+pub fn opt_json_or_string<'de, D: Deserializer<'de>, T: DeserializeOwned>(d: D) -> Result<Option<T>, D::Error> {
+    match Option::<Value>::deserialize(d)? {
+        Some(Value::String(s)) => serde_json::from_str::<T>(&s).map(Some).map_err(de::Error::custom),
+        Some(v) => serde_json::from_value::<T>(v).map(Some).map_err(de::Error::custom),
+        None => Ok(None)
+    }
+}
+
+/// Accepts credentials as json or as a json string, without `scheme` they are a login.
+pub fn opt_credentials<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Credentials>, D::Error> {
+    match opt_json_or_string::<D, Value>(d)? {
+        Some(v) => Credentials::from_value(v).map(Some).map_err(de::Error::custom),
+        None => Ok(None)
+    }
+}
+
+/// Endpoint to fail over to, see `klave_networks::network::Endpoint`.
+#[derive(Deserialize, Debug)]
+pub struct NetworkEndpoint {
+    pub rpc_url: String,
+    #[serde(default, deserialize_with = "opt_credentials")]
+    pub credentials: Option<Credentials>,
+    pub weight: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NetworkAdd {
+    pub network_name: String,
+    pub rpc_url: String,
+    pub chain_id: Option<u64>,
+    pub gas_price: Option<u64>,
+    pub confirmations: Option<u64>,
+    #[serde(default, deserialize_with = "opt_credentials")]
+    pub credentials: Option<Credentials>,
+    pub token_ttl: Option<u64>,
+    #[serde(default, deserialize_with = "opt_json_or_string")]
+    pub headers: Option<BTreeMap<String, String>>,
+    pub weight: Option<u32>,
+    #[serde(default)]
+    pub endpoints: Vec<NetworkEndpoint>,
+    pub quorum: Option<Quorum>,
+    pub tx_type: Option<TransactionType>,
+    pub fee_policy: Option<FeePolicy>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NetworkRemove {
+    pub network_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NetworkSetChainId {
+    pub network_name: String,
+    pub chain_id: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NetworkSetGasPrice {
+    pub network_name: String,
+    pub gas_price: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NetworkSetConfirmations {
+    pub network_name: String,
+    pub confirmations: u64,
+}
+
+/// Replaces the fallback endpoints, the one of `rpc_url` is kept.
+#[derive(Deserialize, Debug)]
+pub struct NetworkSetEndpoints {
+    pub network_name: String,
+    pub endpoints: Vec<NetworkEndpoint>,
+}
+
+/// Without `quorum`, settlement-critical reads go back to a single endpoint.
+#[derive(Deserialize, Debug)]
+pub struct NetworkSetQuorum {
+    pub network_name: String,
+    pub quorum: Option<Quorum>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NetworkEndpoints {
+    pub network_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NetworkSetTxType {
+    pub network_name: String,
+    pub tx_type: TransactionType,
+}
+
+/// Fields left out keep the default policy.
+#[derive(Deserialize, Debug)]
+pub struct NetworkSetFeePolicy {
+    pub network_name: String,
+    pub base_fee_multiplier: Option<u64>,
+    pub priority_fee_multiplier: Option<u64>,
+    pub max_fee_per_gas_cap: Option<u128>,
+    pub max_priority_fee_per_gas_cap: Option<u128>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NetworkEstimateFees {
+    pub network_name: String,
+}
+
+/// Takes no fields: the wallet is derived from the HD root or generated.
+/// Existing keys are imported encrypted, with `WalletImportKeystore`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WalletAdd {}
+
+#[derive(Deserialize, Debug)]
+pub struct WalletAddNetwork {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+    pub network_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WalletLock {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+    pub network_name: String,
+    #[serde(deserialize_with = "hex_u256")]
+    pub value: U256,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WalletUnlock {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+    pub network_name: String,
+    #[serde(deserialize_with = "hex_u256")]
+    pub value: U256,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WalletAddress {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WalletPublicKey {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WalletNetworks {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WalletImportKeystore {
+    #[serde(deserialize_with = "json_or_string")]
+    pub keystore: Keystore,
+    pub password: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Kdf {
+    #[default]
+    Scrypt,
+    Pbkdf2,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WalletExportKeystore {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+    pub password: String,
+    pub kdf: Option<Kdf>,
+}
+
+/// `encoding` is either "utf8", the default, or "hex".
+#[derive(Deserialize, Debug)]
+pub struct WalletSignMessage {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+    pub message: String,
+    pub encoding: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WalletSignTypedData {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+    #[serde(deserialize_with = "typed_data")]
+    pub typed_data: TypedData,
+}
+
+/// The signed hash comes from `message`, `typed_data` or `hash`, in that order of precedence.
+#[derive(Deserialize, Debug)]
+pub struct WalletVerifySignature {
+    #[serde(deserialize_with = "address")]
+    pub address: Address,
+    pub signature: String,
+    pub message: Option<String>,
+    pub encoding: Option<String>,
+    #[serde(default, deserialize_with = "opt_typed_data")]
+    pub typed_data: Option<TypedData>,
+    #[serde(default, deserialize_with = "opt_b256")]
+    pub hash: Option<B256>,
+}
+
+/// Without `proof`, the command itself is the proof.
+#[derive(Deserialize, Debug)]
+pub struct WalletVerifyLockProof {
+    #[serde(default, deserialize_with = "opt_json_or_string")]
+    pub proof: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WalletTransfer {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+    pub network_name: String,
+    pub chain_id: u64,
+    #[serde(deserialize_with = "address")]
+    pub to: Address,
+    #[serde(deserialize_with = "hex_u256")]
+    pub value: U256,
+    pub nonce: Option<u64>,
+    pub gas_limit: Option<u64>,
+    pub max_fee_per_gas: Option<u64>,
+    pub max_priority_fee_per_gas: Option<u64>,
+    pub access_list: Option<AccessList>,
+}
+
+/// With `contract_name` and `abi`, the deployed contract is registered under that name.
+#[derive(Deserialize, Debug)]
+pub struct WalletDeployContract {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+    pub network_name: String,
+    pub chain_id: u64,
+    #[serde(deserialize_with = "hex_bytes")]
+    pub input: Bytes,
+    pub trace: bool,
+    pub nonce: Option<u64>,
+    pub gas_limit: Option<u64>,
+    pub max_fee_per_gas: Option<u64>,
+    pub max_priority_fee_per_gas: Option<u64>,
+    pub access_list: Option<AccessList>,
+    pub contract_name: Option<String>,
+    #[serde(default, deserialize_with = "opt_json_abi")]
+    pub abi: Option<JsonAbi>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WalletBalance {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+    pub network_name: String,
+}
+
+/// Without `network_name`, every network of the wallet is reconciled.
+#[derive(Deserialize, Debug)]
+pub struct WalletPosition {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+    pub network_name: Option<String>,
+}
+
+/// The contract is given by `contract_name` or by `contract_address`. Calls are encoded from
+/// `function` and `args` with the supplied or registered abi, otherwise `input` selects the
+/// built-in "mint" or "burn" of `value` to `recipient_address`.
+#[derive(Deserialize, Debug)]
+pub struct WalletCallContract {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+    pub chain_id: u64,
+    pub network_name: Option<String>,
+    pub contract_name: Option<String>,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub contract_address: Option<Address>,
+    #[serde(default, deserialize_with = "opt_json_abi")]
+    pub abi: Option<JsonAbi>,
+    pub function: Option<String>,
+    pub args: Option<Value>,
+    pub input: Option<String>,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub recipient_address: Option<Address>,
+    #[serde(default, deserialize_with = "opt_hex_u256")]
+    pub value: Option<U256>,
+    pub trace: Option<bool>,
+    pub nonce: Option<u64>,
+    pub gas_limit: Option<u64>,
+    pub max_fee_per_gas: Option<u64>,
+    pub max_priority_fee_per_gas: Option<u64>,
+    pub access_list: Option<AccessList>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct WalletResyncNonce {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+    pub network_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct HdRootImport {
+    pub mnemonic: String,
+    pub next_index: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserAddWallet {
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserSetRole {
+    pub user_id: String,
+    pub role: UserRole,
+}
+
+/// Token addresses are left out for native legs.
+#[derive(Deserialize, Debug)]
+pub struct TransactionAdd {
+    #[serde(deserialize_with = "address")]
+    pub source_address: Address,
+    pub source_network_name: String,
+    #[serde(deserialize_with = "hex_u256")]
+    pub source_amount: U256,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub source_token_address: Option<Address>,
+    #[serde(deserialize_with = "address")]
+    pub destination_address: Address,
+    pub destination_network_name: String,
+    #[serde(deserialize_with = "hex_u256")]
+    pub destination_amount: U256,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub destination_token_address: Option<Address>,
+    pub timeout: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SettlementLeg {
+    #[serde(deserialize_with = "address")]
+    pub payer_address: Address,
+    #[serde(deserialize_with = "address")]
+    pub payee_address: Address,
+    pub network_name: String,
+    #[serde(deserialize_with = "hex_u256")]
+    pub amount: U256,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub token_address: Option<Address>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SettlementAdd {
+    pub legs: Vec<SettlementLeg>,
+    pub timeout: Option<u64>,
+}
+
+/// `asset_amount` defaults to one, which is the only amount an ERC-721 asset accepts.
+#[derive(Deserialize, Debug)]
+pub struct DeliveryVsPaymentAdd {
+    #[serde(deserialize_with = "address")]
+    pub seller_address: Address,
+    pub asset_network_name: String,
+    pub asset_standard: AssetStandard,
+    #[serde(deserialize_with = "address")]
+    pub asset_contract_address: Address,
+    #[serde(deserialize_with = "hex_u256")]
+    pub asset_token_id: U256,
+    #[serde(default, deserialize_with = "opt_hex_u256")]
+    pub asset_amount: Option<U256>,
+    #[serde(deserialize_with = "address")]
+    pub buyer_address: Address,
+    pub payment_network_name: String,
+    #[serde(deserialize_with = "hex_u256")]
+    pub payment_amount: U256,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub payment_token_address: Option<Address>,
+    pub timeout: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TransactionGet {
+    pub tx_id: String,
+}
+
+/// The deposit fields are only required while a leg is awaiting funding, whichever
+/// participant funds it. Settlements identify the funded leg by its index in `leg`.
+#[derive(Deserialize, Debug)]
+pub struct TransactionCommit {
+    pub tx_id: String,
+    pub leg: Option<usize>,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub source_address: Option<Address>,
+    pub source_network_name: Option<String>,
+    #[serde(default, deserialize_with = "opt_hex_u256")]
+    pub source_amount: Option<U256>,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub escrow_address: Option<Address>,
+    pub tx_hash: Option<String>,
+}
+
+/// Funding transfer a participant reports to `transaction_commit`.
+#[derive(Debug)]
+pub struct Deposit {
+    pub address: String,
+    pub network_name: String,
+    pub amount: U256,
+    pub escrow_address: String,
+    pub tx_hash: String,
+}
+
+impl TransactionCommit {
+    pub fn deposit(&self) -> Result<Deposit, Error> {
+        match (&self.source_address, &self.source_network_name, &self.source_amount, &self.escrow_address, &self.tx_hash) {
+            (Some(address), Some(network_name), Some(amount), Some(escrow_address), Some(tx_hash)) => Ok(Deposit {
+                address: address.to_string(),
+                network_name: network_name.clone(),
+                amount: *amount,
+                escrow_address: escrow_address.to_string(),
+                tx_hash: tx_hash.clone(),
+            }),
+            _ => {
+                let missing = [
+                    ("source_address", self.source_address.is_none()),
+                    ("source_network_name", self.source_network_name.is_none()),
+                    ("source_amount", self.source_amount.is_none()),
+                    ("escrow_address", self.escrow_address.is_none()),
+                    ("tx_hash", self.tx_hash.is_none()),
+                ];
+                Err(invalid(missing.iter().filter(|(_, m)| *m).map(|(f, _)| format!("{}: missing", f)).collect()))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TransactionApply {
+    pub tx_id: String,
+    pub tx_hash: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TransactionCancel {
+    pub tx_id: String,
+}
+
+/// `eth_address` is the orchestrator wallet paying for the gas of the escrow on `network_name`.
+#[derive(Deserialize, Debug)]
+pub struct TransactionFundEscrowGas {
+    pub tx_id: String,
+    #[serde(deserialize_with = "address")]
+    pub eth_address: Address,
+    pub network_name: String,
+}
+
+/// Without `contract_address`, the address is read from the receipt of `tx_hash`.
+#[derive(Deserialize, Debug)]
+pub struct ContractAdd {
+    pub contract_name: String,
+    pub network_name: String,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub contract_address: Option<Address>,
+    pub tx_hash: Option<String>,
+    #[serde(deserialize_with = "json_abi")]
+    pub abi: JsonAbi,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ContractRemove {
+    pub contract_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ContractConfirmDeployment {
+    pub contract_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ContractGet {
+    pub contract_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EthBlockNumber {
+    pub network_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EthGetBlockByNumber {
+    pub network_name: String,
+    pub block_number: String,
+    pub trace: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EthGasPrice {
+    pub network_name: String,
+}
+
+/// `input` is raw calldata or the name of a built-in ERC-20 call.
+#[derive(Deserialize, Debug)]
+pub struct EthEstimateGas {
+    pub network_name: String,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub to: Option<Address>,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub from: Option<Address>,
+    pub input: Option<String>,
+    #[serde(default, deserialize_with = "opt_hex_u256")]
+    pub value: Option<U256>,
+    pub gas_price: Option<String>,
+    pub gas: Option<String>,
+    pub nonce: Option<String>,
+}
+
+/// `input` is the name of a built-in ERC-20 call.
+#[derive(Deserialize, Debug)]
+pub struct EthCallContract {
+    pub network_name: String,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub to: Option<Address>,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub from: Option<Address>,
+    pub input: Option<String>,
+    #[serde(default, deserialize_with = "opt_hex_u256")]
+    pub value: Option<U256>,
+    pub gas_price: Option<String>,
+    pub gas: Option<String>,
+    pub nonce: Option<String>,
+    pub max_fee_per_gas: Option<u64>,
+    pub max_priority_fee_per_gas: Option<u64>,
+    pub trace: Option<bool>,
+}
+
+/// A registered `contract_name` supplies the network, the address and the abi.
+#[derive(Deserialize, Debug)]
+pub struct EthCallFunction {
+    pub function: String,
+    pub args: Option<Value>,
+    pub network_name: Option<String>,
+    pub contract_name: Option<String>,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub to: Option<Address>,
+    #[serde(default, deserialize_with = "opt_json_abi")]
+    pub abi: Option<JsonAbi>,
+    #[serde(default, deserialize_with = "opt_address")]
+    pub from: Option<Address>,
+    pub block: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EthProtocolVersion {
+    pub network_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EthChainId {
+    pub network_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EthGetTransactionByHash {
+    pub network_name: String,
+    pub tx_hash: String,
+    pub trace: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EthGetTransactionReceipt {
+    pub network_name: String,
+    pub tx_hash: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EthGetTransactionCount {
+    pub network_name: String,
+    #[serde(deserialize_with = "address")]
+    pub address: Address,
+    pub block: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Web3ClientVersion {
+    pub network_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Web3Sha3 {
+    pub network_name: String,
+    pub input: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NetVersion {
+    pub network_name: String,
+}
@@ -0,0 +1,30 @@
+use serde::Serialize;
+use serde_json::json;
+use klave;
+use crate::error::Error;
+
+/// Answers a route with `{"success": true, "result": result}`.
+pub fn success<T: Serialize>(result: T) {
+    send(&json!({
+        "success": true,
+        "result": result
+    }));
+}
+
+/// Answers a route with `{"success": false, "error": {"code", "message"}}`.
+pub fn error(e: impl Into<Error>) {
+    let e: Error = e.into();
+    send(&json!({
+        "success": false,
+        "error": {
+            "code": e.code(),
+            "message": e.message()
+        }
+    }));
+}
+
+fn send(response: &serde_json::Value) {
+    if let Err(e) = klave::notifier::send_json(response) {
+        klave::notifier::send_string(&format!("ERROR: failed to serialize response: {}", e));
+    }
+}
@@ -0,0 +1,333 @@
+use serde::{Deserialize, Serialize};
+use crate::{error::Error, klave_networks::networks::Networks, transaction::{self, ExpectedTransfer, Participant, Transaction}, user::User, wallet::Wallet};
+
+/// Progress of a multi-leg settlement. Payout only starts once every leg is funded.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum SettlementState {
+    /// Waiting for every payer to commit its transfer into escrow.
+    Funding,
+    /// Every leg is committed, waiting for the transfers to be confirmed on chain.
+    Finalisation,
+    /// Every leg is funded, the escrows pay the payees out.
+    Payout,
+    Complete,
+    Cancelled,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum LegState {
+    AwaitingFunding,
+    FundingCommitted,
+    Funded,
+    PayoutSent,
+    Paid,
+    Refunded,
+}
+
+/// `payer` pays its amount to `payee` on its network, through the escrow of that network.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Leg {
+    pub payer: Participant,
+    pub payee: String,
+    pub state: LegState,
+    pub funding_tx_hash: Option<String>,
+    pub payout_tx_hash: Option<String>,
+    pub refund_tx_hash: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Escrow {
+    pub network_name: String,
+    pub address: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Settlement {
+    pub legs: Vec<Leg>,
+    pub escrows: Vec<Escrow>,
+    pub state: SettlementState,
+}
+
+impl Leg {
+    pub fn new(payer: Participant, payee: &str) -> Leg {
+        Leg {
+            payer,
+            payee: payee.to_string(),
+            state: LegState::AwaitingFunding,
+            funding_tx_hash: None,
+            payout_tx_hash: None,
+            refund_tx_hash: None,
+        }
+    }
+}
+
+impl Settlement {
+    /// Escrows are created along with the transaction, see `Transaction::new_settlement`.
+    pub fn new(legs: Vec<Leg>) -> Result<Settlement, Error> {
+        if legs.len() < 2 {
+            return Err(Error::InvalidRequest(String::from("a settlement needs at least two legs")));
+        }
+        Ok(Settlement {
+            legs,
+            escrows: Vec::new(),
+            state: SettlementState::Funding,
+        })
+    }
+
+    /// Networks the legs settle on, in the order they first appear.
+    pub fn network_names(&self) -> Vec<String> {
+        let mut network_names: Vec<String> = Vec::new();
+        for leg in &self.legs {
+            if !network_names.contains(&leg.payer.network_name) {
+                network_names.push(leg.payer.network_name.clone());
+            }
+        }
+        network_names
+    }
+
+    pub fn escrow_address(&self, network_name: &str) -> Result<&str, Error> {
+        match self.escrows.iter().find(|e| e.network_name == network_name) {
+            Some(e) => Ok(&e.address),
+            None => Err(Error::NotFound(format!("no escrow on network {}", network_name)))
+        }
+    }
+
+    pub fn leg(&self, index: usize) -> Result<&Leg, Error> {
+        match self.legs.get(index) {
+            Some(l) => Ok(l),
+            None => Err(Error::NotFound(format!("leg {} not found, the settlement has {} legs", index, self.legs.len())))
+        }
+    }
+
+    /// Returns the transfer the payer of leg `index` must send to the escrow.
+    pub fn expected_funding(&self, index: usize) -> Result<ExpectedTransfer, Error> {
+        let leg = self.leg(index)?;
+        Ok(ExpectedTransfer {
+            network_name: leg.payer.network_name.clone(),
+            from: leg.payer.address.clone(),
+            to: self.escrow_address(&leg.payer.network_name)?.to_string(),
+            amount: leg.payer.amount,
+            token_address: leg.payer.token_address.clone(),
+        })
+    }
+
+    /// Returns the transfer the escrow must send to the payee of leg `index`.
+    pub fn expected_payout(&self, index: usize) -> Result<ExpectedTransfer, Error> {
+        let leg = self.leg(index)?;
+        Ok(ExpectedTransfer {
+            network_name: leg.payer.network_name.clone(),
+            from: self.escrow_address(&leg.payer.network_name)?.to_string(),
+            to: leg.payee.clone(),
+            amount: leg.payer.amount,
+            token_address: leg.payer.token_address.clone(),
+        })
+    }
+
+    /// Moves the settlement forward from the state of its legs.
+    pub fn update_state(&mut self) {
+        let all = |states: &[LegState]| self.legs.iter().all(|l| states.contains(&l.state));
+        self.state = if all(&[LegState::Paid]) {
+            SettlementState::Complete
+        } else if all(&[LegState::Funded, LegState::PayoutSent, LegState::Paid]) {
+            SettlementState::Payout
+        } else if all(&[LegState::FundingCommitted, LegState::Funded]) {
+            SettlementState::Finalisation
+        } else {
+            SettlementState::Funding
+        };
+    }
+
+    /// Whether an escrow has started paying out, after which the settlement can no longer be cancelled.
+    pub fn is_payout_started(&self) -> bool {
+        self.legs.iter().any(|l| l.state == LegState::PayoutSent || l.state == LegState::Paid)
+    }
+
+    /// Returns the legs whose funds are currently held by an escrow.
+    ///
+    /// A leg that has only been committed is checked against the chain, since its funds may already be in escrow.
+    /// Fails while the chain cannot tell whether a committed leg landed.
+    pub fn funded_legs(&self, nm: &Networks) -> Result<Vec<usize>, Error> {
+        let mut funded = Vec::new();
+        for (index, leg) in self.legs.iter().enumerate() {
+            let is_funded = match (&leg.state, &leg.funding_tx_hash) {
+                (LegState::Funded, _) => true,
+                (LegState::FundingCommitted, Some(tx_hash)) => transaction::is_transfer_received(nm, &self.expected_funding(index)?, tx_hash)?,
+                _ => false
+            };
+            if is_funded {
+                funded.push(index);
+            }
+        }
+        Ok(funded)
+    }
+}
+
+fn settlement_of(tx: &Transaction) -> Result<Settlement, Error> {
+    match &tx.settlement {
+        Some(s) => Ok(s.clone()),
+        None => Err(Error::InvalidState(String::from("transaction is not a settlement")))
+    }
+}
+
+/// Records the funding transfer `tx_hash` that the payer of leg `index` sent to the escrow.
+pub fn commit_funding(tx: &mut Transaction, participant: &User, index: usize, tx_hash: Option<&str>) -> Result<String, Error> {
+    let mut settlement = settlement_of(tx)?;
+    if settlement.state != SettlementState::Funding {
+        return Err(Error::InvalidState(format!("settlement is in state {:?}, legs can no longer be funded", settlement.state)));
+    }
+    let tx_hash = match tx_hash {
+        Some(h) => h.to_string(),
+        None => return Err(Error::InvalidRequest(String::from("tx_hash is required to commit a leg")))
+    };
+    let leg = settlement.leg(index)?;
+    if !participant.owns_wallet(&leg.payer.address) {
+        return Err(Error::Unauthorized(format!("payer address '{}' not found in participant wallets", leg.payer.address)));
+    }
+    if leg.state != LegState::AwaitingFunding {
+        return Err(Error::InvalidState(format!("leg {} is already committed", index)));
+    }
+
+    let leg = &mut settlement.legs[index];
+    leg.state = LegState::FundingCommitted;
+    leg.funding_tx_hash = Some(tx_hash.clone());
+    settlement.update_state();
+    tx.settlement = Some(settlement);
+    tx.save()?;
+    Ok(tx_hash)
+}
+
+/// Has the escrows pay every funded leg out and returns the payout transaction hashes.
+///
+/// Payouts that fail are left funded, calling again sends the remaining ones.
+pub fn send_payouts(tx: &mut Transaction, nm: &Networks, participant: &User) -> Result<Vec<String>, Error> {
+    let mut settlement = settlement_of(tx)?;
+    match settlement.state {
+        SettlementState::Payout => (),
+        SettlementState::Complete => return Err(Error::InvalidState(String::from("transaction is already complete"))),
+        _ => return Err(Error::InvalidState(format!("settlement is in state {:?}, every leg must be funded before payout", settlement.state)))
+    }
+    for escrow in &settlement.escrows {
+        if !participant.owns_wallet(&escrow.address) {
+            return Err(Error::Unauthorized(format!("escrow address '{}' not found in orchestrator wallets", escrow.address)));
+        }
+    }
+
+    let mut tx_hashes = Vec::new();
+    for index in 0..settlement.legs.len() {
+        if settlement.legs[index].state != LegState::Funded {
+            continue;
+        }
+        let payout = settlement.expected_payout(index)?;
+        let tx_hash = match tx.send_from_escrow(nm, &payout) {
+            Ok(h) => h,
+            Err(e) => {
+                // Payouts already broadcast must be recorded before giving up
+                tx.settlement = Some(settlement);
+                tx.save()?;
+                return Err(e.context(format!("failed to pay leg {} out", index)));
+            }
+        };
+        settlement.legs[index].state = LegState::PayoutSent;
+        settlement.legs[index].payout_tx_hash = Some(tx_hash.clone());
+        tx_hashes.push(tx_hash);
+    }
+    settlement.update_state();
+    tx.settlement = Some(settlement);
+    tx.save()?;
+    Ok(tx_hashes)
+}
+
+/// Checks the funding or payout transfer `tx_hash` against the chain and books it.
+pub fn apply(tx: &mut Transaction, nm: &Networks, tx_hash: &str) -> Result<String, Error> {
+    let mut settlement = settlement_of(tx)?;
+    match settlement.state {
+        SettlementState::Complete => return Ok(String::from("transaction is already complete")),
+        SettlementState::Cancelled => return Err(Error::InvalidState(String::from("transaction is not in the correct state to process payment"))),
+        _ => ()
+    }
+
+    let index = match settlement.legs.iter().position(|l| l.funding_tx_hash.as_deref() == Some(tx_hash) || l.payout_tx_hash.as_deref() == Some(tx_hash)) {
+        Some(i) => i,
+        None => return Err(Error::NotFound(format!("tx_hash '{}' not found in settlement legs", tx_hash)))
+    };
+    let leg = settlement.legs[index].clone();
+    let network_name = leg.payer.network_name.as_str();
+    let mut escrow_wallet = Wallet::load(settlement.escrow_address(network_name)?)?;
+
+    match leg.state {
+        LegState::FundingCommitted if leg.funding_tx_hash.as_deref() == Some(tx_hash) => {
+            let expected = settlement.expected_funding(index)?;
+            transaction::verify_transfer(nm, &expected, tx_hash).map_err(|e| e.context(format!("failed to verify tx_hash '{}'", tx_hash)))?;
+
+            escrow_wallet.mint(network_name, &leg.payer.amount)?;
+            // native funds are held by the escrow until its payout releases them
+            if leg.payer.token_address.is_none() {
+                escrow_wallet.hold(network_name, leg.payer.amount)?;
+            }
+            Wallet::load(&leg.payer.address)?.burn(network_name, &leg.payer.amount)?;
+            settlement.legs[index].state = LegState::Funded;
+        },
+        LegState::PayoutSent if leg.payout_tx_hash.as_deref() == Some(tx_hash) => {
+            let expected = settlement.expected_payout(index)?;
+            transaction::verify_transfer(nm, &expected, tx_hash).map_err(|e| e.context(format!("failed to verify tx_hash '{}'", tx_hash)))?;
+
+            escrow_wallet.burn(network_name, &leg.payer.amount)?;
+            Wallet::load(&leg.payee)?.mint(network_name, &leg.payer.amount)?;
+            settlement.legs[index].state = LegState::Paid;
+        },
+        _ => return Err(Error::InvalidState(format!("tx_hash '{}' is not in the correct state to process payment", tx_hash)))
+    }
+
+    settlement.update_state();
+    tx.settlement = Some(settlement);
+    tx.save()?;
+    Ok(format!("transaction '{}' finalized", tx.id))
+}
+
+/// Refunds every funded leg to its payer and cancels the settlement.
+///
+/// Legs already refunded by a previous attempt are skipped.
+pub fn cancel(tx: &mut Transaction, nm: &Networks) -> Result<(), Error> {
+    let mut settlement = settlement_of(tx)?;
+    if settlement.state == SettlementState::Cancelled {
+        return Err(Error::InvalidState(String::from("transaction is already cancelled")));
+    }
+    if settlement.is_payout_started() {
+        return Err(Error::InvalidState(String::from("transaction payout has started and cannot be cancelled")));
+    }
+
+    for index in settlement.funded_legs(nm).map_err(|e| e.context("cannot cancel before every committed leg is settled on chain"))? {
+        let leg = settlement.legs[index].clone();
+        let escrow_address = settlement.escrow_address(&leg.payer.network_name)?.to_string();
+        let refund_hash = match tx.send_from_escrow(nm, &ExpectedTransfer {
+            network_name: leg.payer.network_name.clone(),
+            from: escrow_address.clone(),
+            to: leg.payer.address.clone(),
+            amount: leg.payer.amount,
+            token_address: leg.payer.token_address.clone(),
+        }) {
+            Ok(h) => h,
+            Err(e) => {
+                tx.settlement = Some(settlement);
+                tx.save()?;
+                return Err(e.context(format!("failed to refund {} on {}", leg.payer.address, leg.payer.network_name)));
+            }
+        };
+        settlement.legs[index].state = LegState::Refunded;
+        settlement.legs[index].refund_tx_hash = Some(refund_hash);
+
+        // The refund is already broadcast, bookkeeping failures must not abort the loop.
+        if leg.state == LegState::Funded {
+            if let Ok(mut escrow_wallet) = Wallet::load(&escrow_address) {
+                let _ = escrow_wallet.burn(&leg.payer.network_name, &leg.payer.amount);
+            }
+            if let Ok(mut payer_wallet) = Wallet::load(&leg.payer.address) {
+                let _ = payer_wallet.mint(&leg.payer.network_name, &leg.payer.amount);
+            }
+        }
+    }
+
+    settlement.state = SettlementState::Cancelled;
+    tx.settlement = Some(settlement);
+    tx.save()
+}
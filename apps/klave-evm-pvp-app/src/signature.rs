@@ -0,0 +1,48 @@
+use alloy_dyn_abi::TypedData;
+use alloy_primitives::{eip191_hash_message, hex, Address, PrimitiveSignature, B256};
+use serde_json::{json, Value};
+use crate::error::Error;
+
+/// Returns the bytes of a message given as UTF-8 text or, with `encoding` "hex", as hex.
+pub fn message_bytes(message: &str, encoding: Option<&str>) -> Result<Vec<u8>, Error> {
+    match encoding {
+        None | Some("utf8") => Ok(message.as_bytes().to_vec()),
+        Some("hex") => Ok(hex::decode(message.trim_start_matches("0x"))?),
+        Some(e) => Err(Error::InvalidRequest(format!("unsupported encoding '{}'", e)))
+    }
+}
+
+/// Parses an EIP-712 typed data document given as a JSON object or as a string holding one.
+pub fn parse_typed_data(typed_data: &Value) -> Result<TypedData, Error> {
+    match typed_data {
+        Value::String(s) => Ok(serde_json::from_str::<TypedData>(s)?),
+        _ => Ok(serde_json::from_value::<TypedData>(typed_data.clone())?)
+    }
+}
+
+/// EIP-191 `personal_sign` hash of `message`.
+pub fn message_hash(message: &[u8]) -> B256 {
+    eip191_hash_message(message)
+}
+
+/// EIP-712 signing hash of `typed_data`.
+pub fn typed_data_hash(typed_data: &TypedData) -> Result<B256, Error> {
+    Ok(typed_data.eip712_signing_hash()?)
+}
+
+/// Recovers the signer of `hash` from a 65 bytes RSV signature in hex.
+pub fn recover(signature: &str, hash: &B256) -> Result<Address, Error> {
+    let signature = PrimitiveSignature::from_raw(&hex::decode(signature.trim_start_matches("0x"))?)?;
+    Ok(signature.recover_address_from_prehash(hash)?)
+}
+
+/// Renders a signature as its 65 bytes RSV hex encoding along with its parts and the signed hash.
+pub fn to_json(signature: &PrimitiveSignature, hash: &B256) -> Value {
+    json!({
+        "signature": hex::encode_prefixed(signature.as_bytes()),
+        "r": format!("0x{:064x}", signature.r()),
+        "s": format!("0x{:064x}", signature.s()),
+        "v": 27 + signature.v() as u8,
+        "hash": hash.to_string()
+    })
+}
@@ -8,8 +8,30 @@ sol! {
     function totalSupply() view returns (uint256);
     function owner() view returns (address);
 
+    function transfer(address to, uint256 amount) external returns (bool);
     function mint(address to, uint256 amount) external;
     function burn(address to, uint256 amount) external;
     function pause() external;
     function unpause() external;
+
+    event Transfer(address indexed from, address indexed to, uint256 value);
+}
+
+/// ERC-721 and ERC-1155 share names with the ERC-20 calls above, they live in their own modules.
+pub mod erc721 {
+    use alloy_sol_types::sol;
+
+    sol! {
+        function ownerOf(uint256 tokenId) view returns (address);
+        function safeTransferFrom(address from, address to, uint256 tokenId) external;
+    }
+}
+
+pub mod erc1155 {
+    use alloy_sol_types::sol;
+
+    sol! {
+        function balanceOf(address account, uint256 id) view returns (uint256);
+        function safeTransferFrom(address from, address to, uint256 id, uint256 value, bytes data) external;
+    }
 }
@@ -1,12 +1,18 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 use serde_json::to_string;
-use crate::{user::{RoleType, User}, wallet::{self, Wallet}};
-use alloy_primitives::{hex, U256};
+use crate::{dvp::DeliveryVsPayment, error::Error, hd_wallet::HdRoot, klave_networks::networks::Networks, settlement::{Escrow, LegState, Settlement, SettlementState}, solidity::Transfer, user::{RoleType, User}, wallet::{self, SpendOverride, Wallet}};
+use alloy_consensus::Transaction as _;
+use alloy_primitives::{hex, Address, U256};
 use klave;
 
 pub(crate) const TRANSACTION_TABLE: &str = "transactionTable";
+pub(crate) const DEFAULT_CONFIRMATIONS: u64 = 1;
+/// Default time a PvP may stay open before it can be cancelled, in seconds.
+pub(crate) const DEFAULT_TIMEOUT: u64 = 24 * 60 * 60;
+/// `trusted_time` is expressed in nanoseconds.
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub enum PvPstate {
@@ -28,6 +34,8 @@ pub struct Participant {
     pub network_name: String,
     pub address: String,
     pub amount: U256,    
+    /// ERC-20 contract of the leg, native currency when unset.
+    pub token_address: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -45,11 +53,191 @@ pub struct PaymentVsPayment {
     pub network_transactions: Vec<NetworkTransaction>,
 }
 
+/// A leg paid into escrow. `applied` is set once `transaction_apply` has booked it.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct FundedLeg {
+    pub participant: Participant,
+    pub applied: bool,
+}
+
+/// A transfer the escrow expects to observe on chain for a given PvP leg.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ExpectedTransfer {
+    pub network_name: String,
+    pub from: String,
+    pub to: String,
+    pub amount: U256,
+    pub token_address: Option<String>,
+}
+
+impl PaymentVsPayment {
+    /// Returns the transfer that the network transaction recorded in `state` must carry.
+    pub fn expected_transfer(&self, state: &PvPstate, escrow_address: &str) -> Option<ExpectedTransfer> {
+        let (leg, from, to) = match state {
+            PvPstate::AwaitingSourceReceive => (&self.source, self.source.address.as_str(), escrow_address),
+            PvPstate::AwaitingDestinationReceive => (&self.destination, self.destination.address.as_str(), escrow_address),
+            PvPstate::AwaitingDestinationSend => (&self.source, escrow_address, self.destination.address.as_str()),
+            PvPstate::AwaitingSourceSend => (&self.destination, escrow_address, self.source.address.as_str()),
+            _ => return None
+        };
+        Some(ExpectedTransfer {
+            network_name: leg.network_name.clone(),
+            from: from.to_string(),
+            to: to.to_string(),
+            amount: leg.amount,
+            token_address: leg.token_address.clone(),
+        })
+    }
+
+    /// Returns the legs whose funds are currently held by the escrow, source first.
+    ///
+    /// Legs that have been applied are funded by construction. A leg that has only been
+    /// committed is checked against the chain, since its funds may already be in escrow.
+    /// Fails while the chain cannot tell whether a committed leg landed.
+    pub fn funded_legs(&self, nm: &Networks, escrow_address: &str) -> Result<Vec<FundedLeg>, Error> {
+        let mut funded = Vec::new();
+        match self.state_machine {
+            PvPstate::AwaitingSourceReceiveFinalized if self.is_committed_leg_received(nm, &PvPstate::AwaitingSourceReceive, escrow_address)? => {
+                funded.push(FundedLeg { participant: self.source.clone(), applied: false });
+            },
+            PvPstate::AwaitingDestinationReceive => {
+                funded.push(FundedLeg { participant: self.source.clone(), applied: true });
+            },
+            PvPstate::AwaitingDestinationReceiveFinalized => {
+                funded.push(FundedLeg { participant: self.source.clone(), applied: true });
+                if self.is_committed_leg_received(nm, &PvPstate::AwaitingDestinationReceive, escrow_address)? {
+                    funded.push(FundedLeg { participant: self.destination.clone(), applied: false });
+                }
+            },
+            PvPstate::AwaitingDestinationSend => {
+                funded.push(FundedLeg { participant: self.source.clone(), applied: true });
+                funded.push(FundedLeg { participant: self.destination.clone(), applied: true });
+            },
+            _ => {}
+        }
+        Ok(funded)
+    }
+
+    fn is_committed_leg_received(&self, nm: &Networks, state: &PvPstate, escrow_address: &str) -> Result<bool, Error> {
+        let expected = match self.expected_transfer(state, escrow_address) {
+            Some(e) => e,
+            None => return Ok(false)
+        };
+        for nt in self.network_transactions.iter().filter(|nt| &nt.state == state) {
+            if is_transfer_received(nm, &expected, &nt.tx_hash)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Whether the escrow has started paying out, after which the PvP can no longer be cancelled.
+    pub fn is_payout_started(&self) -> bool {
+        matches!(self.state_machine,
+            PvPstate::AwaitingDestinationSendFinalized |
+            PvPstate::AwaitingSourceSend |
+            PvPstate::AwaitingSourceSendFinalized |
+            PvPstate::Complete)
+    }
+}
+
+/// Checks that `tx_hash` is buried under the number of confirmations configured on the network,
+/// succeeded and was sent by `from`, and returns its receipt.
+///
+/// `InvalidRequest` is only returned once the transaction is final, `verify_transfer` relies on it.
+pub fn verify_receipt(nm: &Networks, network_name: &str, from: &str, tx_hash: &str) -> Result<alloy_rpc_types_eth::TransactionReceipt, Error> {
+    let from = Address::from_str(from)?;
+    let receipt = nm.send::<alloy_rpc_types_eth::TransactionReceipt>(network_name, "eth_getTransactionReceipt", &[&format!("\"{}\"", tx_hash)])?;
+
+    let block_number = match receipt.block_number {
+        Some(b) => b,
+        None => return Err(Error::InvalidState(format!("transaction {} is not mined yet", tx_hash)))
+    };
+    let head: String = nm.send(network_name, "eth_blockNumber", &[])?;
+    let head = u64::from_str_radix(head.trim_start_matches("0x"), 16)?;
+    let required = nm.get_network(network_name)?.get_confirmations().unwrap_or(DEFAULT_CONFIRMATIONS);
+    let confirmations = head.saturating_sub(block_number) + 1;
+    if confirmations < required {
+        return Err(Error::InvalidState(format!("transaction {} has {} confirmations, {} required", tx_hash, confirmations, required)));
+    }
+
+    if !receipt.status() {
+        return Err(Error::InvalidRequest(format!("transaction {} failed on chain", tx_hash)));
+    }
+    if receipt.from != from {
+        return Err(Error::InvalidRequest(format!("transaction {} sent by {} instead of {}", tx_hash, receipt.from, from)));
+    }
+    Ok(receipt)
+}
+
+/// Whether `log` is a `Transfer` of `value` from `from` to `to` emitted by the `token` contract.
+/// Events of any other contract are ignored, a worthless token could otherwise fake a funding.
+pub fn is_token_transfer(log: &alloy_rpc_types_eth::Log, token: Address, from: Address, to: Address, value: U256) -> bool {
+    match log.log_decode::<Transfer>() {
+        Ok(event) => event.inner.address == token && event.inner.data.from == from && event.inner.data.to == to && event.inner.data.value == value,
+        Err(_) => false
+    }
+}
+
+/// Checks the receipt of `tx_hash` against the expected transfer.
+///
+/// On top of the checks of `verify_receipt`, the transaction must move `expected.amount` to
+/// `expected.to` as native value or, for token legs, through a `Transfer` event emitted by
+/// the token contract.
+pub fn verify_transfer(nm: &Networks, expected: &ExpectedTransfer, tx_hash: &str) -> Result<(), Error> {
+    let receipt = verify_receipt(nm, &expected.network_name, &expected.from, tx_hash)?;
+    let from = receipt.from;
+    let to = Address::from_str(&expected.to)?;
+
+    let transferred = match &expected.token_address {
+        Some(token_address) => {
+            let token = Address::from_str(token_address)?;
+            receipt.inner.logs().iter().any(|log| is_token_transfer(log, token, from, to, expected.amount))
+        },
+        None => match receipt.to {
+            Some(receipt_to) if receipt_to == to => {
+                let onchain_tx = nm.send::<alloy_rpc_types_eth::Transaction>(&expected.network_name, "eth_getTransactionByHash", &[&format!("\"{}\"", tx_hash)])?;
+                onchain_tx.value() == expected.amount
+            },
+            _ => false
+        }
+    };
+    if !transferred {
+        return Err(Error::InvalidRequest(format!("transaction {} does not transfer {} from {} to {}", tx_hash, expected.amount, from, to)));
+    }
+    Ok(())
+}
+
+/// Whether the committed transaction `tx_hash` carried the expected transfer.
+///
+/// Returns `false` only for a final transaction that does not, and fails while the chain cannot
+/// tell yet, for instance when it is not mined, lacks confirmations or the node is unreachable.
+pub fn is_transfer_received(nm: &Networks, expected: &ExpectedTransfer, tx_hash: &str) -> Result<bool, Error> {
+    match verify_transfer(nm, expected, tx_hash) {
+        Ok(()) => Ok(true),
+        Err(Error::InvalidRequest(_)) => Ok(false),
+        Err(e) => Err(e.context(format!("failed to check whether {} funded the escrow", tx_hash)))
+    }
+}
+
+/// Points to the gas top-up when an escrow cannot pay for the transfer it sends.
+pub fn escrow_gas_hint(e: Error) -> Error {
+    match e {
+        Error::InsufficientFunds(message) => Error::InsufficientFunds(format!("{}, fund the escrow gas with transaction_fund_escrow_gas", message)),
+        e => e
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Transaction {
     pub id: String,
     pub timestamp: String,
+    pub deadline: Option<u64>,
     pub payment_vs_payment: Option<PaymentVsPayment>,
+    /// Multi-leg settlement, which keeps one escrow per network instead of `escrow_address`.
+    pub settlement: Option<Settlement>,
+    /// Delivery of an ERC-721 or ERC-1155 asset against a payment, settled through `escrow_address`.
+    pub delivery_vs_payment: Option<DeliveryVsPayment>,
     pub escrow_address: String,
 }
 
@@ -64,83 +252,277 @@ impl Display for Transaction {
     }
 }
 
+/// Adds the transaction to the users of the wallets at `addresses`. A user owning several of them takes part only once.
+fn add_participants(tx_id: &str, addresses: &[&str]) -> Result<(), Error> {
+    let mut participants: Vec<String> = Vec::new();
+    for address in addresses {
+        for user in Wallet::load(address)?.get_users() {
+            if !participants.contains(user) {
+                participants.push(user.clone());
+            }
+        }
+    }
+    for user in participants {
+        let mut u = User::load(&user)?;
+        u.add_transaction(tx_id, RoleType::Participant)?;
+    }
+    Ok(())
+}
+
+/// Adds the transaction to the sender, who orchestrates it.
+fn add_orchestrator(tx_id: &str) -> Result<User, Error> {
+    let mut orchestrator = match User::load(&klave::context::get("sender")?) {
+        Ok(u) => u,
+        Err(e) => {
+            return Err(e.context("failed to load orchestrator"));
+        }
+    };
+    orchestrator.add_transaction(tx_id, RoleType::Orchestrator)?;
+    Ok(orchestrator)
+}
+
+/// Creates an escrow wallet on `network_names`, owned by the orchestrator, and returns its address.
+fn create_escrow(tx_id: &str, orchestrator: &mut User, network_names: &[&str]) -> Result<String, Error> {
+    // Escrows are derived from the HD root when there is one, so that they can be recovered from the seed.
+    let mut wallet = match HdRoot::load() {
+        Ok(mut root) => Wallet::derive(&mut root)?,
+        Err(_) => {
+            let (secret_key, public_key) = wallet::generate_keypair(None)?;
+            Wallet::new(&secret_key, &public_key)
+        }
+    };
+    for network_name in network_names {
+        wallet.add_network(network_name)?;
+    }
+    orchestrator.add_wallet(wallet.get_eth_address())?;
+    wallet.add_user(&orchestrator.id)?;
+    wallet.add_transaction(tx_id)?;
+    Ok(wallet.get_eth_address().to_string())
+}
+
 impl Transaction {
-    pub fn new(pvp: &PaymentVsPayment) -> Result<Transaction, Box<dyn std::error::Error>> {
-        let tx_id = klave::crypto::random::get_random_bytes(64).map(|x| hex::encode(x))?;
+    /// Creates a PvP with a single escrow, owned by the orchestrator, on both networks.
+    pub fn new(pvp: &PaymentVsPayment, timeout: u64) -> Result<Transaction, Error> {
+        let tx_id = klave::crypto::random::get_random_bytes(64).map(hex::encode)?;
+        let timestamp = klave::context::get("trusted_time").unwrap_or("0".to_string());
+
+        add_participants(&tx_id, &[&pvp.source.address, &pvp.destination.address])?;
+        let mut orchestrator = add_orchestrator(&tx_id)?;
+        let escrow_address = create_escrow(&tx_id, &mut orchestrator, &[&pvp.source.network_name, &pvp.destination.network_name])?;
+
+        let mut pvp = pvp.clone();
+        pvp.state_machine = PvPstate::AwaitingSourceReceive;
         Ok(Transaction {
-            id: tx_id.clone(),
-            timestamp: klave::context::get("trusted_time").unwrap_or("0".to_string()),
-            payment_vs_payment: {
-                let source_wallet = Wallet::load(&pvp.source.address)?;
-                let destination_wallet = Wallet::load(&pvp.destination.address)?;
-                for user in source_wallet.get_users() {
-                    let mut u = User::load(user)?;
-                    u.add_transaction(&tx_id, RoleType::Participant)?;
-                }
-                for user in destination_wallet.get_users() {
-                    let mut u = User::load(&user)?;
-                    u.add_transaction(&tx_id, RoleType::Participant)?;
-                }
-                let mut post_init_pvp = pvp.clone();
-                post_init_pvp.state_machine = PvPstate::AwaitingSourceReceive;
-                Some(post_init_pvp)
-            },
-            escrow_address: {
-                let (secret_key, public_key) = match wallet::generate_keypair(None) {
-                    Ok((sk, pk)) => (sk, pk),
-                    Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to generate keypair: {}", e));
-                        return Transaction::new(pvp);
-                    }
-                };
-                let mut wallet = Wallet::new(&secret_key, &public_key);
-                wallet.add_network(&pvp.source.network_name)?;    
-                wallet.add_network(&pvp.destination.network_name)?;                
+            id: tx_id,
+            deadline: Some(timestamp.parse::<u64>()? + timeout * NANOSECONDS_PER_SECOND),
+            timestamp,
+            payment_vs_payment: Some(pvp),
+            settlement: None,
+            delivery_vs_payment: None,
+            escrow_address,
+        })
+    }
 
-                let mut swift_user = match User::load(&klave::context::get("sender")?) {
-                    Ok(u) => u,
-                    Err(e) => {
-                        return Err(format!("ERROR: failed to load swift user - {}", e).into());
-                    }
-                };
-                swift_user.add_transaction(&tx_id, RoleType::Orchestrator)?;
-                swift_user.add_wallet(&wallet.get_eth_address().to_string())?;
+    /// Creates a multi-leg settlement with one escrow, owned by the orchestrator, per network.
+    pub fn new_settlement(settlement: &Settlement, timeout: u64) -> Result<Transaction, Error> {
+        let tx_id = klave::crypto::random::get_random_bytes(64).map(hex::encode)?;
+        let timestamp = klave::context::get("trusted_time").unwrap_or("0".to_string());
 
-                wallet.add_user(&swift_user.id)?;
-                wallet.add_transaction(&tx_id)?;
-                wallet.get_eth_address().to_string()
-            },
-        })      
+        let mut addresses: Vec<&str> = Vec::new();
+        for leg in &settlement.legs {
+            addresses.push(&leg.payer.address);
+            addresses.push(&leg.payee);
+        }
+        add_participants(&tx_id, &addresses)?;
+        let mut orchestrator = add_orchestrator(&tx_id)?;
+
+        let mut settlement = settlement.clone();
+        for network_name in settlement.network_names() {
+            let address = create_escrow(&tx_id, &mut orchestrator, &[&network_name])?;
+            settlement.escrows.push(Escrow {
+                network_name,
+                address,
+            });
+        }
+
+        Ok(Transaction {
+            id: tx_id,
+            deadline: Some(timestamp.parse::<u64>()? + timeout * NANOSECONDS_PER_SECOND),
+            timestamp,
+            payment_vs_payment: None,
+            settlement: Some(settlement),
+            delivery_vs_payment: None,
+            escrow_address: String::new(),
+        })
     }
 
-    pub fn load(id: &str) -> Result<Transaction, Box<dyn std::error::Error>> {
-        match klave::ledger::get_table(TRANSACTION_TABLE).get(&id) {        
+    /// Creates a delivery versus payment with a single escrow, owned by the orchestrator, on both networks.
+    pub fn new_delivery_vs_payment(dvp: &DeliveryVsPayment, timeout: u64) -> Result<Transaction, Error> {
+        let tx_id = klave::crypto::random::get_random_bytes(64).map(hex::encode)?;
+        let timestamp = klave::context::get("trusted_time").unwrap_or("0".to_string());
+
+        add_participants(&tx_id, &[&dvp.delivery.address, &dvp.payment.address])?;
+        let mut orchestrator = add_orchestrator(&tx_id)?;
+        let escrow_address = create_escrow(&tx_id, &mut orchestrator, &[&dvp.delivery.network_name, &dvp.payment.network_name])?;
+
+        let mut dvp = dvp.clone();
+        dvp.state_machine = PvPstate::AwaitingSourceReceive;
+        Ok(Transaction {
+            id: tx_id,
+            deadline: Some(timestamp.parse::<u64>()? + timeout * NANOSECONDS_PER_SECOND),
+            timestamp,
+            payment_vs_payment: None,
+            settlement: None,
+            delivery_vs_payment: Some(dvp),
+            escrow_address,
+        })
+    }
+
+    pub fn load(id: &str) -> Result<Transaction, Error> {
+        match klave::ledger::get_table(TRANSACTION_TABLE).get(id) {        
             Ok(v) => {
                 let tx: Transaction = match serde_json::from_slice(&v) {
                     Ok(w) => w,
                     Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to deserialize user: {}", e));
-                        return Err(e.into());
+                        return Err(Error::Serialization(format!("failed to deserialize transaction: {}", e)));
                     }
                 };
                 Ok(tx)
             },
-            Err(e) => Err(e.into())
+            Err(_) => Err(Error::NotFound(format!("transaction '{}' not found", id)))
         }
     }
 
-    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn save(&self) -> Result<(), Error> {
         let serialized_user = match to_string(&self) {
             Ok(s) => s,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to serialize user: {}", e));
-                return Err(e.into());
+                return Err(Error::Serialization(format!("failed to serialize transaction: {}", e)));
             }
         };
-        klave::ledger::get_table(TRANSACTION_TABLE).set(&self.id, &serialized_user.as_bytes())
+        match klave::ledger::get_table(TRANSACTION_TABLE).set(&self.id, serialized_user.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::Ledger(format!("failed to save transaction: {}", e)))
+        }
+    }
+
+    /// Signs and broadcasts the escrow payout owed in `state` and returns its transaction hash.
+    pub fn send_payout(&self, nm: &Networks, state: &PvPstate) -> Result<String, Error> {
+        let pvp = match &self.payment_vs_payment {
+            Some(pvp) => pvp,
+            None => return Err(Error::InvalidState(String::from("transaction does not have a payment")))
+        };
+        match pvp.expected_transfer(state, &self.escrow_address) {
+            Some(payout) => self.send_from_escrow(nm, &payout),
+            None => Err(Error::InvalidState(format!("no payout is owed in state {:?}", state)))
+        }
+    }
+
+    /// Signs and broadcasts the return of a funded leg to its participant and returns its transaction hash.
+    pub fn send_refund(&self, nm: &Networks, participant: &Participant) -> Result<String, Error> {
+        self.send_from_escrow(nm, &ExpectedTransfer {
+            network_name: participant.network_name.clone(),
+            from: self.escrow_address.clone(),
+            to: participant.address.clone(),
+            amount: participant.amount,
+            token_address: participant.token_address.clone(),
+        })
+    }
+
+    /// Signs and broadcasts `transfer` from the escrow it is sent from and returns its transaction hash.
+    pub fn send_from_escrow(&self, nm: &Networks, transfer: &ExpectedTransfer) -> Result<String, Error> {
+        let mut escrow_wallet = Wallet::load(&transfer.from)?;
+        let to = Address::from_str(&transfer.to)?;
+        let escrow_tx = match &transfer.token_address {
+            Some(token_address) => escrow_wallet.build_token_transfer(nm, &transfer.network_name, Address::from_str(token_address)?, to, transfer.amount)?,
+            None => escrow_wallet.build_transfer(nm, &transfer.network_name, to, transfer.amount)?
+        };
+        // Native funds received by the escrow are held for the transaction, the payout releases them.
+        let spend_override = SpendOverride {
+            transaction_id: self.id.clone(),
+            release: match transfer.token_address {
+                Some(_) => U256::ZERO,
+                None => transfer.amount
+            },
+        };
+        escrow_wallet.sign_and_send(nm, &transfer.network_name, escrow_tx, false, Some(&spend_override)).map_err(escrow_gas_hint)
+    }
+
+    /// Escrow holding the legs of the transaction on `network_name`.
+    pub fn escrow_on(&self, network_name: &str) -> Result<&str, Error> {
+        match &self.settlement {
+            Some(settlement) => settlement.escrow_address(network_name),
+            None => Ok(&self.escrow_address)
+        }
+    }
+
+    /// Gas limits of the transfers the escrow still has to send on `network_name`, one per leg
+    /// it pays out or refunds there. A leg is either paid out or refunded, never both.
+    pub fn escrow_gas_limits(&self, network_name: &str) -> Vec<u64> {
+        let gas_limit = |leg: &Participant| match leg.token_address {
+            Some(_) => wallet::TOKEN_TRANSFER_GAS_LIMIT,
+            None => wallet::TRANSFER_GAS_LIMIT
+        };
+        let mut gas_limits = Vec::new();
+        if let Some(settlement) = &self.settlement {
+            for leg in settlement.legs.iter().filter(|l| l.payer.network_name == network_name) {
+                if matches!(leg.state, LegState::AwaitingFunding | LegState::FundingCommitted | LegState::Funded) {
+                    gas_limits.push(gas_limit(&leg.payer));
+                }
+            }
+        } else if let Some(dvp) = &self.delivery_vs_payment {
+            if dvp.delivery.network_name == network_name && !dvp.is_payout_started() && dvp.state_machine != PvPstate::Cancelled {
+                gas_limits.push(wallet::ASSET_TRANSFER_GAS_LIMIT);
+            }
+            if dvp.payment.network_name == network_name && !matches!(dvp.state_machine, PvPstate::AwaitingSourceSendFinalized | PvPstate::Complete | PvPstate::Cancelled) {
+                gas_limits.push(gas_limit(&dvp.payment));
+            }
+        } else if let Some(pvp) = &self.payment_vs_payment {
+            if pvp.source.network_name == network_name && !pvp.is_payout_started() && pvp.state_machine != PvPstate::Cancelled {
+                gas_limits.push(gas_limit(&pvp.source));
+            }
+            if pvp.destination.network_name == network_name && !matches!(pvp.state_machine, PvPstate::AwaitingSourceSendFinalized | PvPstate::Complete | PvPstate::Cancelled) {
+                gas_limits.push(gas_limit(&pvp.destination));
+            }
+        }
+        gas_limits
+    }
+
+    /// Native amount the escrow on `network_name` lacks to pay, at `max_fee_per_gas`, the gas
+    /// of every transfer it still has to send.
+    ///
+    /// Escrows only receive the legs they hold, which are locked for the payout. Their gas is
+    /// paid from the unlocked balance, which the orchestrator tops up with `transaction_fund_escrow_gas`.
+    pub fn escrow_gas_shortfall(&self, nm: &Networks, network_name: &str, max_fee_per_gas: u128) -> Result<U256, Error> {
+        let gas: u64 = self.escrow_gas_limits(network_name).iter().sum();
+        let needed = U256::from(gas) * U256::from(max_fee_per_gas);
+        let escrow_wallet = Wallet::load(self.escrow_on(network_name)?)?;
+        Ok(needed.saturating_sub(escrow_wallet.spendable(nm, network_name, U256::ZERO)?))
+    }
+
+    /// Whether the deadline of the transaction has passed according to `trusted_time`.
+    pub fn is_expired(&self) -> Result<bool, Error> {
+        let now = klave::context::get("trusted_time")?.parse::<u64>()?;
+        match self.deadline {
+            Some(deadline) => Ok(now >= deadline),
+            None => Err(Error::InvalidState(String::from("transaction has no deadline")))
+        }
+    }
+
+    /// Whether the deadline stops the transaction from moving forward. It only bounds the funding
+    /// of the legs: once the escrow pays out, the transaction must be able to complete.
+    pub fn is_funding_expired(&self) -> Result<bool, Error> {
+        let funding_states = [PvPstate::AwaitingSourceReceive, PvPstate::AwaitingDestinationReceive];
+        let funding = match (&self.settlement, &self.delivery_vs_payment, &self.payment_vs_payment) {
+            (Some(settlement), _, _) => settlement.state != SettlementState::Payout,
+            (None, Some(dvp), _) => funding_states.contains(&dvp.state_machine),
+            (None, None, Some(pvp)) => funding_states.contains(&pvp.state_machine),
+            (None, None, None) => true
+        };
+        Ok(funding && self.is_expired()?)
     }
 
-    pub fn process(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn process(&self) -> Result<(), Error> {
         match &self.payment_vs_payment {
             Some(pvp) => {
                 match &pvp.state_machine {
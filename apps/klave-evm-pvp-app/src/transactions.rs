@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::to_string;
 use crate::transaction::{Transaction, TRANSACTION_TABLE};
 use klave;
+use crate::error::Error;
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Transactions {
@@ -27,20 +28,19 @@ impl Transactions {
         }
     }
 
-    pub fn load() -> Result<Transactions, Box<dyn std::error::Error>> {
+    pub fn load() -> Result<Transactions, Error> {
         match klave::ledger::get_table(TRANSACTION_TABLE).get("ALL") {
             Ok(v) => {
                 let wallet: Transactions = match serde_json::from_slice(&v) {
                     Ok(w) => w,
                     Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to parse transaction list: {}", e));
                         return Err(e.into());
                     }
                 };
                 Ok(wallet)
             },
             Err(e) => {
-                Err(e.into())
+                Err(Error::Ledger(e.to_string()))
             }
         }
     }
@@ -54,46 +54,35 @@ impl Transactions {
         }
     }
 
-    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn save(&self) -> Result<(), Error> {
         let serialized_txs = match to_string(&self) {
             Ok(s) => s,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to serialize transaction list: {}", e));
                 return Err(e.into());
             }
         };
-        match klave::ledger::get_table(TRANSACTION_TABLE).set("ALL", &serialized_txs.as_bytes()) {
+        match klave::ledger::get_table(TRANSACTION_TABLE).set("ALL", serialized_txs.as_bytes()) {
             Ok(_) => Ok(()),
-            Err(e) => Err(e.into())
+            Err(e) => Err(Error::Ledger(e.to_string()))
         }
     }
 
-    pub fn add_transaction(&mut self, tx_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn add_transaction(&mut self, tx_id: &str) -> Result<(), Error> {
         //Check if transaction exists
         let mut found = false;
         for n in &self.list {
-            if n == &tx_id {
+            if n == tx_id {
                 found = true;
                 break;
             }
         }
         if found {
-            klave::notifier::send_string(&format!("ERROR: transaction {} already exists", tx_id));
-            return Err("transaction already exists".into());
+            return Err(Error::AlreadyExists("transaction already exists".to_string()));
         }        
 
         self.list.push(tx_id.to_string());
 
-        match self.save() {
-            Ok(_) => {},
-            Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to save transaction list: {}", e));
-                return Err(e.into());
-            }
-        }
-
-        klave::notifier::send_string(&format!("transaction {} added", tx_id));
-        Ok(())
+        self.save()
     }
 
     pub fn get_transaction(&self, name: &str) -> Option<Transaction> {
@@ -101,8 +90,7 @@ impl Transactions {
             if transaction == name {
                 let transaction = match Transaction::load(transaction) {
                     Ok(n) => n,
-                    Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to load transaction: {}", e));
+                    Err(_) => {
                         return None;
                     }
                 };
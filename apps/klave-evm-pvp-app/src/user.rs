@@ -4,7 +4,7 @@ use alloy_primitives::hex;
 use serde::{Deserialize, Serialize};
 use serde_json::to_string;
 use klave;
-use crate::{transaction::Transaction, wallet::Wallet};
+use crate::{error::Error, transaction::Transaction, wallet::Wallet};
 
 pub(crate) const USER_TABLE: &str = "userTable";
 
@@ -15,6 +15,24 @@ pub enum RoleType {
     Participant = 2,
 }
 
+/// Application-wide permission level of a user.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    /// Manages users and roles, and passes every role check.
+    Admin,
+    /// Manages networks and the contract registry.
+    Operator,
+    /// Creates and settles PvP transactions.
+    Orchestrator,
+    /// Uses the wallets it owns.
+    #[default]
+    Participant,
+}
+
+/// Every role, for routes open to any registered user.
+pub(crate) const ANY_ROLE: &[UserRole] = &[UserRole::Admin, UserRole::Operator, UserRole::Orchestrator, UserRole::Participant];
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TransactionRole {
     pub transaction_id: String,
@@ -24,6 +42,8 @@ pub struct TransactionRole {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct User {    
     pub id: String,
+    #[serde(default)]
+    role: UserRole,
     transactions: Vec<TransactionRole>,
     wallets: Vec<String>,    
 }
@@ -43,29 +63,29 @@ impl User {
     pub fn new(id: &str) -> User {
         User {
             id: if id.is_empty() {
-                klave::crypto::random::get_random_bytes(64).map(|x| hex::encode(x)).unwrap()
+                klave::crypto::random::get_random_bytes(64).map(hex::encode).unwrap()
             }
             else {
                 id.to_string()
             },
+            role: UserRole::default(),
             transactions: Vec::new(),
             wallets: Vec::new(),
         }
     }
 
-    pub fn load(id: &str) -> Result<User, Box<dyn std::error::Error>> {
-        match klave::ledger::get_table(USER_TABLE).get(&id) {        
+    pub fn load(id: &str) -> Result<User, Error> {
+        match klave::ledger::get_table(USER_TABLE).get(id) {        
             Ok(v) => {
                 let user: User = match serde_json::from_slice(&v) {
                     Ok(w) => w,
                     Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to deserialize user: {}", e));
-                        return Err(e.into());
+                        return Err(Error::Serialization(format!("failed to deserialize user: {}", e)));
                     }
                 };
                 Ok(user)
             },
-            Err(e) => Err(e.into())
+            Err(_) => Err(Error::NotFound(format!("user '{}' not found", id)))
         }
     }
 
@@ -78,18 +98,20 @@ impl User {
         }
     }
 
-    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn save(&self) -> Result<(), Error> {
         let serialized_user = match to_string(&self) {
             Ok(s) => s,
             Err(e) => {                
-                return Err(format!("ERROR: failed to serialize user: {}", e).into());
+                return Err(Error::Serialization(format!("failed to serialize user: {}", e)));
             }
         };
-        klave::ledger::get_table(USER_TABLE).set(&self.id, &serialized_user.as_bytes())?;
-        Ok(())
+        match klave::ledger::get_table(USER_TABLE).set(&self.id, serialized_user.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::Ledger(format!("failed to save user: {}", e)))
+        }
     }
 
-    pub fn add_wallet(&mut self, address: &str) -> Result<(), Box<dyn std::error::Error>> {        
+    pub fn add_wallet(&mut self, address: &str) -> Result<(), Error> {        
         //Check if wallet exists
         let mut found = false;
         for w in &self.wallets {
@@ -99,14 +121,14 @@ impl User {
             }
         }
         if found {
-            return Err("wallet already exists for this user".into());
+            return Err(Error::AlreadyExists(String::from("wallet already exists for this user")));
         }
 
         //Check if wallet is valid
         let mut existing_wallet = match Wallet::load(address) {
             Ok(w) => w,            
             Err(_) => {
-                return Err("wallet does not exist".into());            
+                return Err(Error::NotFound(String::from("wallet does not exist")));            
             }
         };
         existing_wallet.add_user(&self.id)?;
@@ -116,7 +138,7 @@ impl User {
         Ok(())
     }
 
-    pub fn add_transaction(&mut self, transaction_id: &str, role: RoleType) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn add_transaction(&mut self, transaction_id: &str, role: RoleType) -> Result<(), Error> {
         //Check if transaction exists
         let mut found = false;
         for r in &self.transactions {
@@ -126,15 +148,12 @@ impl User {
             }
         }
         if found {
-            return Err("role already exists for this user".into());
+            return Err(Error::AlreadyExists(String::from("role already exists for this user")));
         }
 
         //Check if wallet is valid
-        match Transaction::load(transaction_id) {
-            Ok(_) => {
-                return Err("transaction does already exist".into());
-            }
-            Err(_) => ()
+        if Transaction::load(transaction_id).is_ok() {
+            return Err(Error::AlreadyExists(String::from("transaction does already exist")));
         }
 
         self.transactions.push(TransactionRole {
@@ -145,6 +164,27 @@ impl User {
         Ok(())
     }
 
+    pub fn get_role(&self) -> UserRole {
+        self.role
+    }
+
+    pub fn set_role(&mut self, role: UserRole) {
+        self.role = role;
+    }
+
+    /// Admins hold every role.
+    pub fn has_role(&self, roles: &[UserRole]) -> bool {
+        self.role == UserRole::Admin || roles.contains(&self.role)
+    }
+
+    pub fn owns_wallet(&self, address: &str) -> bool {
+        self.wallets.iter().any(|w| w.eq_ignore_ascii_case(address))
+    }
+
+    pub fn is_in_transaction(&self, transaction_id: &str) -> bool {
+        self.transactions.iter().any(|r| r.transaction_id == transaction_id)
+    }
+
     pub fn get_wallets(&self) -> Vec<String> {
         self.wallets.clone()
     }
@@ -153,3 +193,25 @@ impl User {
         self.transactions.clone()
     }
 }
+
+/// Loads the sender's user and checks that it holds one of `roles`.
+pub fn authorize(roles: &[UserRole]) -> Result<User, Error> {
+    let sender = klave::context::get("sender")?;
+    let user = match User::load(&sender) {
+        Ok(u) => u,
+        Err(_) => return Err(Error::Unauthorized(format!("user '{}' is not registered", sender)))
+    };
+    if !user.has_role(roles) {
+        return Err(Error::Unauthorized(format!("user '{}' with role {:?} is not allowed to call this route", sender, user.role)));
+    }
+    Ok(user)
+}
+
+/// Loads the sender's user and checks that it owns the wallet at `address`.
+pub fn authorize_wallet(address: &str) -> Result<User, Error> {
+    let user = authorize(ANY_ROLE)?;
+    if !user.owns_wallet(address) {
+        return Err(Error::Unauthorized(format!("wallet {} does not belong to user '{}'", address, user.id)));
+    }
+    Ok(user)
+}
@@ -3,9 +3,10 @@ use klave;
 use std::fmt::{self, Display, Formatter};
 use serde_json::to_string;
 
-use crate::user::User;
+use crate::user::{User, UserRole};
 
 use crate::user::USER_TABLE;
+use crate::error::Error;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Users {
@@ -23,6 +24,12 @@ impl Display for Users {
     }
 }
 
+impl Default for Users {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Users {
     pub fn new() -> Users {
         Users {
@@ -30,7 +37,7 @@ impl Users {
         }
     }
 
-    pub fn add_user(&mut self, id: &str) -> Result<(), Box<dyn std::error::Error>> {        
+    pub fn add_user(&mut self, id: &str) -> Result<(), Error> {        
         //Check if network exists
         let user = User::get(id);
         let mut found = false;
@@ -41,17 +48,17 @@ impl Users {
             }
         }
         if found {
-            return Err("user already exists".into());
+            return Err(Error::AlreadyExists("user already exists".to_string()));
         }
 
         self.list.push(user.id.clone());
         if let Err(e) = self.save() {
-            return Err(format!("failed to save user list: {}", e).into());
+            return Err(Error::Ledger(format!("failed to save user list: {}", e)));
         }
         Ok(())
     }
 
-   pub fn remove_user(&mut self, id: &str) -> Result<(), Box<dyn std::error::Error>> {
+   pub fn remove_user(&mut self, id: &str) -> Result<(), Error> {
         //Check if network exists
         let mut found = false;
         for u in &self.list {
@@ -61,12 +68,12 @@ impl Users {
             }
         }
         if !found {
-            return Err("user does not exist".into());
+            return Err(Error::NotFound("user does not exist".to_string()));
         }
 
         self.list.retain(|x| x != id);
         if let Err(e) = self.save() {
-            return Err(format!("failed to save user list: {}", e).into());
+            return Err(Error::Ledger(format!("failed to save user list: {}", e)));
         }
         Ok(())
     }
@@ -76,8 +83,7 @@ impl Users {
         for id in &self.list {
             let user = match User::load(id) {
                 Ok(n) => n,
-                Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to load user: {}", e));
+                Err(_) => {
                     continue;
                 }
             };
@@ -86,39 +92,41 @@ impl Users {
         users
     }
 
-    pub fn load() -> Result<Users, Box<dyn std::error::Error>> {
+    /// True when no user holds the Admin role: a fresh ledger, or one written
+    /// before roles existed, where every stored user reads back as a participant.
+    pub fn lacks_admin(users: &[User]) -> bool {
+        !users.iter().any(|u| u.get_role() == UserRole::Admin)
+    }
+
+    pub fn load() -> Result<Users, Error> {
         match klave::ledger::get_table(USER_TABLE).get("ALL") {
             Ok(v) => {
                 let user: Users = match serde_json::from_slice(&v) {
                     Ok(w) => w,
                     Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to deserialize user list: {}", e));
                         return Err(e.into());
                     }
                 };
                 Ok(user)
             },
-            Err(e) => Err(e.into())
+            Err(e) => Err(Error::Ledger(e.to_string()))
         }
     }
 
     pub fn get() -> Users {
-        match Users::load() {
-            Ok(nm) => nm,
-            Err(_) => {
-                Users::new()
-            }
-        }
+        Users::load().unwrap_or_default()
     }
 
-    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn save(&self) -> Result<(), Error> {
         let serialized_users = match to_string(&self) {
             Ok(s) => s,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to serialize users list: {}", e));
                 return Err(e.into());
             }
         };
-        klave::ledger::get_table(USER_TABLE).set("ALL", &serialized_users.as_bytes())
+        match klave::ledger::get_table(USER_TABLE).set("ALL", serialized_users.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::Ledger(format!("failed to save user list: {}", e)))
+        }
     }
 }
@@ -1,38 +1,46 @@
 use std::{fmt::{self, Display, Formatter}, str::FromStr};
 
-use alloy_consensus::TxEip1559;
+use alloy_consensus::{SignableTransaction, TxEip1559, TxEip2930, TxLegacy};
 use alloy_network::TxSignerSync;
-use alloy_signer::k256::{elliptic_curve::sec1::ToEncodedPoint, PublicKey, SecretKey};
+use alloy_signer::{k256::{elliptic_curve::sec1::ToEncodedPoint, PublicKey, SecretKey}, SignerSync};
+use alloy_dyn_abi::TypedData;
 use alloy_signer_local::PrivateKeySigner;
 use serde::{Deserialize, Serialize};
-use serde_json::to_string;
+use serde_json::{to_string, Value};
 use alloy_consensus::transaction::RlpEcdsaTx;
-use alloy_primitives::{hex, keccak256, Address, U256};
-use klave::{self, crypto::subtle::{self, CryptoKey}};
-use crate::klave_networks::networks::Networks;
+use alloy_primitives::{hex, keccak256, Address, Bytes, PrimitiveSignature, TxKind, U256};
+use klave;
+use crate::klave_networks::{fees, http, network::TransactionType, networks::Networks};
+use crate::solidity::transferCall;
+use crate::dvp::Asset;
+use crate::error::Error;
+use crate::hd_wallet::HdRoot;
+use crate::lock_proof::{self, LockAttestation};
+use alloy_sol_types::SolCall;
 
 pub(crate) const WALLET_TABLE: &str = "walletTable";
+pub(crate) const TRANSFER_GAS_LIMIT: u64 = 21_000;
+pub(crate) const TOKEN_TRANSFER_GAS_LIMIT: u64 = 100_000;
+pub(crate) const ASSET_TRANSFER_GAS_LIMIT: u64 = 200_000;
 
-pub fn generate_keypair(secret_key_str: Option<&str>) -> Result<(SecretKey, PublicKey), Box<dyn std::error::Error>> {
+pub fn generate_keypair(secret_key_str: Option<&str>) -> Result<(SecretKey, PublicKey), Error> {
     match secret_key_str {
         Some(secret_key_str) => {
             let secret_key_hex = match hex::decode(secret_key_str) {
                 Ok(v) => v,
                 Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to decode secret key: {}", e));
-                    return Err(e.into());
+                    return Err(Error::InvalidRequest(format!("failed to decode secret key: {}", e)));
                 }
             };
 
             let secret_key = match SecretKey::from_slice(&secret_key_hex) {
                 Ok(v) => v,
                 Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to create secret key: {}", e));
-                    return Err(e.into());
+                    return Err(Error::Crypto(format!("failed to create secret key: {}", e)));
                 }
             };
             let public_key = secret_key.public_key();
-            return Ok((secret_key, public_key));
+            Ok((secret_key, public_key))
         }
         None => {
             Ok(match klave::crypto::random::get_random_bytes(size_of::<SecretKey>() as i32)
@@ -43,8 +51,7 @@ pub fn generate_keypair(secret_key_str: Option<&str>) -> Result<(SecretKey, Publ
             }) {
                 Ok(v) => v,
                 Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to generate keypair: {}", e));
-                    return Err(e.into());
+                    return Err(Error::Crypto(format!("failed to generate keypair: {}", e)));
                 }
             })
         }
@@ -63,7 +70,15 @@ pub struct LocalNetwork {
     network_name: String,
     locked_amount: U256,
     minted_amount: U256,
-    burned_amount: U256
+    burned_amount: U256,
+    /// Next nonce to use on this network, initialised from the pending transaction count.
+    pending_nonce: Option<u64>
+}
+
+impl LocalNetwork {
+    pub fn get_network_name(&self) -> &str {
+        &self.network_name
+    }
 }
 
 impl Display for LocalNetwork {
@@ -77,6 +92,14 @@ impl Display for LocalNetwork {
     }
 }
 
+/// Lets a PvP escrow payout spend funds it holds for `transaction_id`. `release` is taken off
+/// the network lock for the spend check and released once the payout is broadcast.
+#[derive(Debug, Clone)]
+pub struct SpendOverride {
+    pub transaction_id: String,
+    pub release: U256,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Wallet {
     eth_address: String,
@@ -84,13 +107,23 @@ pub struct Wallet {
     public_key: String,
     networks: Vec<LocalNetwork>,
     users: Vec<String>,
-    transactions: Vec<String>
+    transactions: Vec<String>,
+    /// Index under `m/44'/60'/0'/0/` when the key was derived from the HD root.
+    #[serde(default)]
+    derivation_index: Option<u32>
 }
 
+/// The secret key is left out, so that the serialisation can be returned by queries.
 impl Display for Wallet {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", match serde_json::to_string(self) {
-            Ok(s) => s,
+        let redacted = serde_json::to_value(self).map(|mut v| {
+            if let Value::Object(m) = &mut v {
+                m.remove("secret_key");
+            }
+            v
+        });
+        write!(f, "{}", match redacted {
+            Ok(s) => s.to_string(),
             Err(e) => {
                 format!("ERROR: failed to serialize Wallet: {}", e)
             }
@@ -100,11 +133,11 @@ impl Display for Wallet {
 
 impl Wallet {
     pub fn new(secret_key: &SecretKey, public_key: &PublicKey) -> Wallet {
-        let addr: Address = eth_address(&public_key);
+        let addr: Address = eth_address(public_key);
         Wallet {
             secret_key: {
                 let bytes = secret_key.to_bytes(); 
-                hex::encode(bytes.to_vec())
+                hex::encode(bytes)
             },
             public_key: {
                 let uncompressed_public_key = public_key.to_encoded_point(false); // false for uncompressed
@@ -114,38 +147,48 @@ impl Wallet {
             eth_address: addr.to_string(),
             networks: Vec::new(),
             users: Vec::new(),
-            transactions: Vec::new()
+            transactions: Vec::new(),
+            derivation_index: None
         }
     }
 
-    pub fn load(eth_address: &str) -> Result<Wallet, Box<dyn std::error::Error>> {
-        match klave::ledger::get_table(WALLET_TABLE).get(&eth_address) {        
+    /// Creates a wallet from the next key of the HD root.
+    pub fn derive(root: &mut HdRoot) -> Result<Wallet, Error> {
+        let (secret_key, public_key, index) = root.derive_next()?;
+        let mut wallet = Wallet::new(&secret_key, &public_key);
+        wallet.derivation_index = Some(index);
+        Ok(wallet)
+    }
+
+    pub fn load(eth_address: &str) -> Result<Wallet, Error> {
+        match klave::ledger::get_table(WALLET_TABLE).get(eth_address) {        
             Ok(v) => {
                 let wallet: Wallet = match serde_json::from_slice(&v) {
                     Ok(w) => w,
                     Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to deserialize wallet: {}", e));
-                        return Err(e.into());
+                        return Err(Error::Serialization(format!("failed to deserialize wallet: {}", e)));
                     }
                 };
                 Ok(wallet)
             },
-            Err(e) => Err(e.into())
+            Err(_) => Err(Error::NotFound(format!("wallet '{}' not found", eth_address)))
         }
     }
 
-    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn save(&self) -> Result<(), Error> {
         let serialized_wallet = match to_string(&self) {
             Ok(s) => s,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to serialize wallet: {}", e));
-                return Err(e.into());
+                return Err(Error::Serialization(format!("failed to serialize wallet: {}", e)));
             }
         };
-        klave::ledger::get_table(WALLET_TABLE).set(&self.eth_address, &serialized_wallet.as_bytes())
+        match klave::ledger::get_table(WALLET_TABLE).set(&self.eth_address, serialized_wallet.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::Ledger(format!("failed to save wallet: {}", e)))
+        }
     }
 
-    pub fn add_network(&mut self, network_name: &str) -> Result<(), Box<dyn std::error::Error>> {        
+    pub fn add_network(&mut self, network_name: &str) -> Result<(), Error> {        
         //Check if network already exists
         if self.networks.iter().find(|x| x.network_name == network_name).is_some() {
             return Ok(());
@@ -154,20 +197,21 @@ impl Wallet {
         //Check if network is valid
         let nm = Networks::load()?;
         if !nm.get_networks().contains(&network_name.to_string()) {
-            return Err("Network not found".into());
+            return Err(Error::NotFound(format!("network '{}' not found", network_name)));
         }
 
         self.networks.push(LocalNetwork {
             network_name: network_name.to_string(),
             locked_amount: U256::ZERO,
             minted_amount: U256::ZERO,
-            burned_amount: U256::ZERO
+            burned_amount: U256::ZERO,
+            pending_nonce: None
         });
         self.save()?;
         Ok(())
     }
 
-    pub fn add_user(&mut self, user_id: &str) -> Result<(), Box<dyn std::error::Error>> {        
+    pub fn add_user(&mut self, user_id: &str) -> Result<(), Error> {        
         //Check if user already exists
         if self.users.iter().find(|x| x.as_str() == user_id).is_some() {
             return Ok(());
@@ -178,7 +222,7 @@ impl Wallet {
         Ok(())
     }
 
-    pub fn add_transaction(&mut self, transaction_id: &str) -> Result<(), Box<dyn std::error::Error>> {        
+    pub fn add_transaction(&mut self, transaction_id: &str) -> Result<(), Error> {        
         //Check if transaction already exists
         if self.transactions.iter().find(|x| x.as_str() == transaction_id).is_some() {
             return Ok(());
@@ -189,98 +233,89 @@ impl Wallet {
         Ok(())
     }
 
-    pub fn lock(&mut self, network_name: &str, value: U256, balance: U256) -> Result<String, Box<dyn std::error::Error>> {
-        if !value.is_zero() && !self.can_lock(network_name, balance, value) {
-            return Err("Insufficient balance".into());
+    /// Locks `value` on `network_name` and returns a `LockAttestation` of the new locked amount, signed by the enclave.
+    pub fn lock(&mut self, nm: &Networks, network_name: &str, value: U256) -> Result<Value, Error> {
+        if !value.is_zero() && !self.can_lock(network_name, self.fetch_balance(nm, network_name)?, value) {
+            return Err(Error::InsufficientFunds(String::from("Insufficient balance")));
         }
         match self.networks.iter_mut().find(|x| x.network_name == network_name) {
             Some(local_network) => {
                 local_network.locked_amount += value;
             },
-            None => return Err("Network not found".into())
+            None => return Err(Error::NotFound(format!("network '{}' not found for wallet", network_name)))
         }
-        let crypto_key = self.get_crypto_key()?;
         self.save()?;
-
-        let local_network = self.networks.iter().find(|x| x.network_name == network_name).unwrap();
-
-        //Create a proof of lock
-        let sign_ec_params = subtle::EcdsaParams { hash: "SHA2-256".to_string() };
-        let sign_algorithm= subtle::SignAlgorithm::Ecdsa(sign_ec_params);        
-        let signature = match subtle::sign(
-            &sign_algorithm, 
-            &crypto_key, 
-            &serde_json::to_string(&format!("{}:{}:{}:{}:{}", 
-                klave::context::get("trusted_time").unwrap(),
-                self.eth_address, 
-                local_network.network_name, 
-                value,
-                local_network.locked_amount,                 
-            )).unwrap().into_bytes()) {
-            Ok(result) => result,
-            Err(err) => {
-                klave::notifier::send_string(&err.to_string());
-                return Err(err.into());
-            }
-        };
-        Ok(hex::encode(&signature))
+        self.lock_proof(network_name, "lock", value)
     }
 
-    pub fn unlock(&mut self, network_name: &str, value: U256) -> Result<String, Box<dyn std::error::Error>> {
+    /// Unlocks `value` on `network_name` and returns a `LockAttestation` of the new locked amount, signed by the enclave.
+    pub fn unlock(&mut self, network_name: &str, value: U256) -> Result<Value, Error> {
         match self.networks.iter_mut().find(|x| x.network_name == network_name) {
             Some(local_network) => {
                 if local_network.locked_amount < value {
-                    return Err("Insufficient locked amount".into());
+                    return Err(Error::InsufficientFunds(String::from("Insufficient locked amount")));
                 }
                 local_network.locked_amount -= value;
             },
-            None => return Err("Network not found".into())
+            None => return Err(Error::NotFound(format!("network '{}' not found for wallet", network_name)))
         }
-
-        let crypto_key = self.get_crypto_key()?;
         self.save()?;
+        self.lock_proof(network_name, "unlock", value)
+    }
 
-        let local_network = self.networks.iter().find(|x| x.network_name == network_name).unwrap();
-
-        //Create a proof of lock
-        let sign_ec_params = subtle::EcdsaParams { hash: "SHA2-256".to_string() };
-        let sign_algorithm = subtle::SignAlgorithm::Ecdsa(sign_ec_params);
-        let signature = match subtle::sign(
-            &sign_algorithm, 
-            &crypto_key, 
-            &serde_json::to_string(&format!("{}:{}:{}:{}:{}", 
-                klave::context::get("trusted_time").unwrap(),
-                self.eth_address, 
-                local_network.network_name, 
-                value,
-                local_network.locked_amount,                 
-            )).unwrap().into_bytes()) {
-            Ok(result) => result,
-            Err(err) => {
-                klave::notifier::send_string(&err.to_string());
-                return Err(err.into());
-            }
+    fn lock_proof(&self, network_name: &str, action: &str, value: U256) -> Result<Value, Error> {
+        let local_network = match self.networks.iter().find(|x| x.network_name == network_name) {
+            Some(n) => n,
+            None => return Err(Error::NotFound(format!("network '{}' not found for wallet", network_name)))
         };
-        Ok(hex::encode(&signature))
+        let attestation = LockAttestation::create(
+            Address::from_str(&self.eth_address)?,
+            &local_network.network_name,
+            action,
+            value,
+            local_network.locked_amount,
+            klave::context::get("trusted_time")?.parse::<u64>()?);
+        lock_proof::attest(&attestation)
+    }
+
+    /// Locks `value` on `network_name` without a proof, for funds held by an escrow on behalf of a PvP.
+    pub fn hold(&mut self, network_name: &str, value: U256) -> Result<(), Error> {
+        match self.networks.iter_mut().find(|x| x.network_name == network_name) {
+            Some(local_network) => {
+                local_network.locked_amount += value;
+            },
+            None => return Err(Error::NotFound(format!("network '{}' not found for wallet", network_name)))
+        }
+        self.save()
     }
 
-    pub fn mint(&mut self, network_name: &str, value: &U256) -> Result<(), Box<dyn std::error::Error>> {
+    fn release(&mut self, network_name: &str, value: U256) -> Result<(), Error> {
+        match self.networks.iter_mut().find(|x| x.network_name == network_name) {
+            Some(local_network) => {
+                local_network.locked_amount = local_network.locked_amount.saturating_sub(value);
+            },
+            None => return Err(Error::NotFound(format!("network '{}' not found for wallet", network_name)))
+        }
+        self.save()
+    }
+
+    pub fn mint(&mut self, network_name: &str, value: &U256) -> Result<(), Error> {
         match self.networks.iter_mut().find(|x| x.network_name == network_name) {
             Some(local_network) => {
                 local_network.minted_amount += value;
             },
-            None => return Err("Network not found.".into())
+            None => return Err(Error::NotFound(format!("network '{}' not found for wallet", network_name)))
         }
         self.save()?;
         Ok(())
     }
 
-    pub fn burn(&mut self, network_name: &str, value: &U256) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn burn(&mut self, network_name: &str, value: &U256) -> Result<(), Error> {
         match self.networks.iter_mut().find(|x| x.network_name == network_name) {
             Some(local_network) => {
                 local_network.burned_amount += value;
             },
-            None => return Err("Network not found.".into())
+            None => return Err(Error::NotFound(format!("network '{}' not found for wallet", network_name)))
         }
         self.save()?;
         Ok(())
@@ -302,90 +337,218 @@ impl Wallet {
         &self.networks
     }
 
+    pub fn get_derivation_index(&self) -> Option<u32> {
+        self.derivation_index
+    }
+
     pub fn get_users(&self) -> &Vec<String> {
         &self.users
     }
 
-    pub fn get_transactions(&self) -> &Vec<String> {
-        &self.transactions
+    /// Escrows hold the funds of the transactions they were created for and only move them through settlement.
+    pub fn ensure_not_escrow(&self) -> Result<(), Error> {
+        if self.transactions.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Unauthorized(format!("wallet '{}' is the escrow of a transaction", self.eth_address)))
+        }
     }
 
-    fn get_crypto_key(&mut self) -> Result<CryptoKey, Box<dyn std::error::Error>> {
-        match subtle::load_key(&self.eth_address) {
-            Ok(crypto_key) => Ok(crypto_key),
-            Err(_e) => {        
-                let ec_params = subtle::EcKeyGenParams { named_curve: "P-256".to_string() };
-                let gen_algorithm = subtle::KeyGenAlgorithm::Ecc(ec_params);
-                let crypto_key = match subtle::import_key("raw", &hex::decode(self.secret_key.clone()).unwrap(), &gen_algorithm, false, &["sign"]) {
-                    Ok(result) => result,
-                    Err(err) => {
-                        klave::notifier::send_string(&err.to_string());
-                        return Err(err.into());
-                    }
-                };
-                match subtle::save_key(&crypto_key, &self.eth_address) {
-                    Ok(_) => {},
-                    Err(err) => {
-                        klave::notifier::send_string(&err.to_string());
-                        return Err(err.into());
-                    }
-                };
-                Ok(crypto_key)
-            }
-        }
+    pub fn get_transactions(&self) -> &Vec<String> {
+        &self.transactions
     }
 
-    pub fn get_balance(&self, nm: &Networks, network_name:  &str) -> Result<String, Box<dyn std::error::Error>> {                
-        let result = nm.send(network_name, &"eth_getBalance", &[&format!("\"{}\"",&self.eth_address), "\"latest\""])?;
+    pub fn get_balance(&self, nm: &Networks, network_name:  &str) -> Result<String, Error> {                
+        let result = nm.send(network_name, "eth_getBalance", &[&format!("\"{}\"",&self.eth_address), "\"latest\""])?;
         Ok(result)
     }        
 
+    /// On-chain balance of the wallet on `network_name`, in wei.
+    pub fn fetch_balance(&self, nm: &Networks, network_name: &str) -> Result<U256, Error> {
+        Ok(U256::from_str(&self.get_balance(nm, network_name)?)?)
+    }
+
     pub fn can_lock(&self, network_name: &str, balance: U256, value: U256) -> bool {
         let locked_amount: U256 = match self.networks.iter().find(|x| x.network_name == network_name) {
             Some(local_network) => local_network.locked_amount,
             None => U256::ZERO
         };
-        balance - locked_amount >= value
+        balance.saturating_sub(locked_amount) >= value
+    }
+
+    /// Reconciles the bookkeeping of `network_name` against the on-chain balance.
+    /// Drift is reported when the locked amount, or the net amount minted into the wallet,
+    /// is no longer backed by the balance.
+    pub fn position(&self, nm: &Networks, network_name: &str) -> Result<Value, Error> {
+        let local_network = match self.networks.iter().find(|x| x.network_name == network_name) {
+            Some(n) => n,
+            None => return Err(Error::NotFound(format!("network '{}' not found for wallet", network_name)))
+        };
+        let balance = self.fetch_balance(nm, network_name)?;
+        let net_minted = match local_network.minted_amount >= local_network.burned_amount {
+            true => format!("{:#x}", local_network.minted_amount - local_network.burned_amount),
+            false => format!("-{:#x}", local_network.burned_amount - local_network.minted_amount)
+        };
+
+        let mut drift = Vec::new();
+        if local_network.locked_amount > balance {
+            drift.push(format!("locked amount {:#x} exceeds balance {:#x}", local_network.locked_amount, balance));
+        }
+        if local_network.minted_amount > local_network.burned_amount && local_network.minted_amount - local_network.burned_amount > balance {
+            drift.push(format!("net minted amount {} exceeds balance {:#x}", net_minted, balance));
+        }
+
+        Ok(serde_json::json!({
+            "network_name": network_name,
+            "balance": balance,
+            "locked_amount": local_network.locked_amount,
+            "minted_amount": local_network.minted_amount,
+            "burned_amount": local_network.burned_amount,
+            "net_minted": net_minted,
+            "spendable": balance.saturating_sub(local_network.locked_amount),
+            "in_sync": drift.is_empty(),
+            "drift": drift
+        }))
     }
 
     pub fn can_spend(&self, nm: &Networks, network_name: &str, value: U256) -> bool {
+        match self.spendable(nm, network_name, U256::ZERO) {
+            Ok(spendable) => spendable >= value,
+            Err(_) => false
+        }
+    }
+
+    /// Balance minus the locked amount, of which `released` is considered already unlocked.
+    pub fn spendable(&self, nm: &Networks, network_name: &str, released: U256) -> Result<U256, Error> {
         let locked_amount: U256 = match self.networks.iter().find(|x| x.network_name == network_name) {
             Some(local_network) => local_network.locked_amount,
             None => U256::ZERO
         };
-        let balance = match U256::from_str(&
-            match self.get_balance(nm, network_name) {
-                Ok(v) => v,
-                Err(e) => {
-                    klave::notifier::send_string(&format!("ERROR: failed to get balance: {}", e));
-                    return false;
+        let balance = self.fetch_balance(nm, network_name)?;
+        Ok(balance.saturating_sub(locked_amount.saturating_sub(released)))
+    }
+
+    /// Refuses `transaction` when its value plus its maximum gas cost exceeds the spendable balance.
+    fn check_spend(&self, nm: &Networks, network_name: &str, transaction: &TxEip1559, spend_override: Option<&SpendOverride>) -> Result<(), Error> {
+        let released = match spend_override {
+            Some(o) => {
+                if !self.transactions.iter().any(|t| t == &o.transaction_id) {
+                    return Err(Error::Unauthorized(format!("wallet is not the escrow of transaction '{}'", o.transaction_id)));
                 }
-            }) {
-            Ok(v) => v,
-            Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to parse balance: {}", e));
-                return false;
-            }
+                o.release
+            },
+            None => U256::ZERO
+        };
+        let max_cost = transaction.value + U256::from(transaction.gas_limit) * U256::from(transaction.max_fee_per_gas);
+        let spendable = self.spendable(nm, network_name, released)?;
+        if max_cost > spendable {
+            return Err(Error::InsufficientFunds(format!("Insufficient spendable balance: {:#x} needed, {:#x} available", max_cost, spendable)));
+        }
+        Ok(())
+    }
+
+    /// Builds a native transfer from this wallet with chain id, nonce and fees resolved from the network.
+    pub fn build_transfer(&mut self, nm: &Networks, network_name: &str, to: Address, value: U256) -> Result<TxEip1559, Error> {
+        self.build_call(nm, network_name, to, value, Bytes::new(), TRANSFER_GAS_LIMIT)
+    }
+
+    /// Builds an ERC-20 `transfer` of `value` tokens of the `token` contract to `to`.
+    pub fn build_token_transfer(&mut self, nm: &Networks, network_name: &str, token: Address, to: Address, value: U256) -> Result<TxEip1559, Error> {
+        let input = transferCall::new((to, value)).abi_encode();
+        self.build_call(nm, network_name, token, U256::ZERO, input.into(), TOKEN_TRANSFER_GAS_LIMIT)
+    }
+
+    /// Builds the `safeTransferFrom` of an ERC-721 or ERC-1155 `asset` held by this wallet to `to`.
+    pub fn build_asset_transfer(&mut self, nm: &Networks, network_name: &str, asset: &Asset, to: Address) -> Result<TxEip1559, Error> {
+        let input = asset.transfer_input(Address::from_str(&self.eth_address)?, to);
+        self.build_call(nm, network_name, Address::from_str(&asset.contract_address)?, U256::ZERO, input.into(), ASSET_TRANSFER_GAS_LIMIT)
+    }
+
+    fn build_call(&mut self, nm: &Networks, network_name: &str, to: Address, value: U256, input: Bytes, gas_limit: u64) -> Result<TxEip1559, Error> {
+        let chain_id = match nm.get_network(network_name)?.get_chain_id() {
+            Some(c) => c,
+            None => http::parse_quantity(&nm.send::<String>(network_name, "eth_chainId", &[])?)? as u64
+        };
+        let nonce = self.next_nonce(nm, network_name)?;
+        let fees = fees::estimate_fees(nm, network_name)?;
+
+        Ok(TxEip1559 {
+            chain_id,
+            nonce,
+            gas_limit,
+            to: TxKind::Call(to),
+            value,
+            input,
+            max_fee_per_gas: fees.max_fee_per_gas,
+            max_priority_fee_per_gas: fees.max_priority_fee_per_gas,
+            ..Default::default()
+        })
+    }
+
+    /// Returns the nonce the next transaction on `network_name` should use.
+    ///
+    /// The counter is initialised from `eth_getTransactionCount` with the `pending` tag and is
+    /// advanced by `sign_and_send` once a transaction has been broadcast.
+    pub fn next_nonce(&mut self, nm: &Networks, network_name: &str) -> Result<u64, Error> {
+        let pending_nonce = match self.networks.iter().find(|x| x.network_name == network_name) {
+            Some(local_network) => local_network.pending_nonce,
+            None => return Err(Error::NotFound(format!("network '{}' not found for wallet", network_name)))
         };
-        balance - locked_amount >= value
+        match pending_nonce {
+            Some(nonce) => Ok(nonce),
+            None => self.resync_nonce(nm, network_name)
+        }
+    }
+
+    /// Resets the nonce counter of `network_name` to the pending transaction count on chain.
+    pub fn resync_nonce(&mut self, nm: &Networks, network_name: &str) -> Result<u64, Error> {
+        let nonce: String = nm.send(network_name, "eth_getTransactionCount", &[&format!("\"{}\"", &self.eth_address), "\"pending\""])?;
+        let nonce = http::parse_quantity(&nonce)? as u64;
+        match self.networks.iter_mut().find(|x| x.network_name == network_name) {
+            Some(local_network) => {
+                local_network.pending_nonce = Some(nonce);
+            },
+            None => return Err(Error::NotFound(format!("network '{}' not found for wallet", network_name)))
+        }
+        self.save()?;
+        Ok(nonce)
+    }
+
+    fn advance_nonce(&mut self, network_name: &str, used_nonce: u64) -> Result<(), Error> {
+        match self.networks.iter_mut().find(|x| x.network_name == network_name) {
+            Some(local_network) => {
+                if local_network.pending_nonce.is_none_or(|n| n <= used_nonce) {
+                    local_network.pending_nonce = Some(used_nonce + 1);
+                }
+            },
+            None => return Err(Error::NotFound(format!("network '{}' not found for wallet", network_name)))
+        }
+        self.save()
+    }
+
+    /// Signs `message` with the EIP-191 `personal_sign` prefix.
+    pub fn sign_message(&self, message: &[u8]) -> Result<PrimitiveSignature, Error> {
+        let local_signer = self.secret_key.parse::<PrivateKeySigner>()?;
+        Ok(local_signer.sign_message_sync(message)?)
+    }
+
+    /// Signs the EIP-712 hash of `typed_data`.
+    pub fn sign_typed_data(&self, typed_data: &TypedData) -> Result<PrimitiveSignature, Error> {
+        let local_signer = self.secret_key.parse::<PrivateKeySigner>()?;
+        Ok(local_signer.sign_dynamic_typed_data_sync(typed_data)?)
     }
 
     pub fn sign(&mut self,   
-        mut transaction: TxEip1559
-    ) -> Result<String, Box<dyn std::error::Error>> {
+        transaction: TxEip1559,
+        tx_type: TransactionType
+    ) -> Result<String, Error> {
 
         // Instantiate a signer.
         let local_signer= self.secret_key
             .parse::<PrivateKeySigner>().unwrap();
 
-        // if !transaction.value.is_zero() && !self.can_spend(nm, transaction.value) {
-        //     return Err("Insufficient balance".into());
-        // }
-
-        // Sign it.
-        let signature = local_signer.sign_transaction_sync(&mut transaction).unwrap();
-        let mut encoded_tx = Vec::new();
-        transaction.eip2718_encode(&signature, &mut encoded_tx);
+        // Sign it with the requested envelope.
+        let encoded_tx = encode_signed(&local_signer, transaction, tx_type)?;
         let rlp_hex = format!("\"{}\"", hex::encode_prefixed(encoded_tx));
         Ok(rlp_hex)
     }
@@ -393,32 +556,83 @@ impl Wallet {
     pub fn sign_and_send(&mut self,   
         nm: &Networks,   
         network_name: &str,
-        mut transaction: TxEip1559,
-        trace: bool
-    ) -> Result<String, Box<dyn std::error::Error>> {
+        transaction: TxEip1559,
+        trace: bool,
+        spend_override: Option<&SpendOverride>
+    ) -> Result<String, Error> {
 
         // Instantiate a signer.
         let local_signer= self.secret_key
             .parse::<PrivateKeySigner>().unwrap();
 
-        // if !transaction.value.is_zero() && !self.can_spend(nm, network_name, transaction.value) {
-        //     return Err("Insufficient balance".into());
-        // }
+        // Locked funds can only be spent by an escrow payout releasing them.
+        self.check_spend(nm, network_name, &transaction, spend_override)?;
 
-        // Sign it.
-        let signature = local_signer.sign_transaction_sync(&mut transaction).unwrap();
-        let mut encoded_tx = Vec::new();
-        transaction.eip2718_encode(&signature, &mut encoded_tx);
+        // Sign it with the envelope configured for the network.
+        let tx_type = nm.get_network(network_name)?.get_tx_type().unwrap_or_default();
+        let nonce = transaction.nonce;
+        let encoded_tx = encode_signed(&local_signer, transaction, tx_type)?;
         let rlp_hex = format!("\"{}\"", hex::encode_prefixed(encoded_tx));
 
         let result = nm.send(network_name, match trace {
-            true => &"trace_rawTransaction",
-            false => &"eth_sendRawTransaction"
+            true => "trace_rawTransaction",
+            false => "eth_sendRawTransaction"
         }, &[&rlp_hex])?;
+
+        // The transaction is already broadcast, bookkeeping failures must not turn it into an error.
+        if !trace {
+            let _ = self.advance_nonce(network_name, nonce);
+            if let Some(o) = spend_override {
+                let _ = self.release(network_name, o.release);
+            }
+        }
         Ok(result)
     }
 }
 
+/// Converts `transaction` to the envelope selected by `tx_type`, signs it and returns its EIP-2718 encoding.
+///
+/// Legacy and EIP-2930 envelopes pay `max_fee_per_gas` as their gas price. Legacy transactions
+/// are replay protected through EIP-155 and cannot carry an access list.
+fn encode_signed(signer: &PrivateKeySigner, transaction: TxEip1559, tx_type: TransactionType) -> Result<Vec<u8>, Error> {
+    match tx_type {
+        TransactionType::Legacy => {
+            if !transaction.access_list.is_empty() {
+                return Err(Error::InvalidRequest(String::from("Legacy transactions do not support access lists")));
+            }
+            sign_envelope(signer, TxLegacy {
+                chain_id: Some(transaction.chain_id),
+                nonce: transaction.nonce,
+                gas_price: transaction.max_fee_per_gas,
+                gas_limit: transaction.gas_limit,
+                to: transaction.to,
+                value: transaction.value,
+                input: transaction.input,
+            })
+        },
+        TransactionType::Eip2930 => sign_envelope(signer, TxEip2930 {
+            chain_id: transaction.chain_id,
+            nonce: transaction.nonce,
+            gas_price: transaction.max_fee_per_gas,
+            gas_limit: transaction.gas_limit,
+            to: transaction.to,
+            value: transaction.value,
+            access_list: transaction.access_list,
+            input: transaction.input,
+        }),
+        TransactionType::Eip1559 => sign_envelope(signer, transaction)
+    }
+}
+
+fn sign_envelope<T>(signer: &PrivateKeySigner, mut transaction: T) -> Result<Vec<u8>, Error>
+where
+    T: SignableTransaction<PrimitiveSignature> + RlpEcdsaTx
+{
+    let signature = signer.sign_transaction_sync(&mut transaction)?;
+    let mut encoded_tx = Vec::new();
+    transaction.eip2718_encode(&signature, &mut encoded_tx);
+    Ok(encoded_tx)
+}
 
 #[test]
 fn test_convert_public_key_to_wallet_address(){
@@ -440,3 +654,23 @@ fn test_convert_public_key_to_wallet_address(){
     let computed_address = addr.to_string();
     assert_eq!(computed_address.to_lowercase(), String::from("0x8CA23339DCD606267E466E12F8BFD1593E983E3A").to_lowercase());
 }
+
+#[test]
+fn test_encode_signed_envelopes(){
+    let signer = "0x89D7C6BB9F58F1EECDE6009243B6B3D968277B37A92B4D3C3D5C167E979BCF55".parse::<PrivateKeySigner>().unwrap();
+    let transaction = TxEip1559 {
+        chain_id: 1337,
+        gas_limit: TRANSFER_GAS_LIMIT,
+        to: TxKind::Call(Address::ZERO),
+        max_fee_per_gas: 1_000_000_000,
+        ..Default::default()
+    };
+
+    // Legacy transactions are a bare RLP list, typed transactions are prefixed with their type.
+    let legacy = encode_signed(&signer, transaction.clone(), TransactionType::Legacy).unwrap();
+    assert!(legacy[0] >= 0xc0);
+    let eip2930 = encode_signed(&signer, transaction.clone(), TransactionType::Eip2930).unwrap();
+    assert_eq!(eip2930[0], 0x01);
+    let eip1559 = encode_signed(&signer, transaction, TransactionType::Eip1559).unwrap();
+    assert_eq!(eip1559[0], 0x02);
+}
@@ -4,6 +4,7 @@ use std::fmt::{self, Display, Formatter};
 use serde_json::to_string;
 
 use crate::wallet::WALLET_TABLE;
+use crate::error::Error;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WalletCreationInfo {
@@ -27,6 +28,12 @@ impl Display for Wallets {
     }
 }
 
+impl Default for Wallets {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Wallets {
     pub fn new() -> Wallets {
         Wallets {
@@ -34,7 +41,7 @@ impl Wallets {
         }
     }
 
-    pub fn add_address(&mut self, address: &str) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn add_address(&mut self, address: &str) -> Result<(), Error> {
         //Check if network exists
         let mut found = false;
         for w in &self.list {
@@ -44,7 +51,7 @@ impl Wallets {
             }
         }
         if found {
-            return Err("wallet already exists".into());
+            return Err(Error::AlreadyExists("wallet already exists".to_string()));
         }        
 
         let info = WalletCreationInfo {
@@ -54,7 +61,7 @@ impl Wallets {
 
         self.list.push(info);
         if let Err(e) = self.save() {
-            return Err(format!("failed to save wallet list: {}", e).into());
+            return Err(Error::Ledger(format!("failed to save wallet list: {}", e)));
         }
         Ok(())
     }
@@ -63,39 +70,35 @@ impl Wallets {
         &self.list
     }
 
-    pub fn load() -> Result<Wallets, Box<dyn std::error::Error>> {
+    pub fn load() -> Result<Wallets, Error> {
         match klave::ledger::get_table(WALLET_TABLE).get("ALL") {
             Ok(v) => {
                 let wallet: Wallets = match serde_json::from_slice(&v) {
                     Ok(w) => w,
                     Err(e) => {
-                        klave::notifier::send_string(&format!("ERROR: failed to deserialize wallet list: {}", e));
                         return Err(e.into());
                     }
                 };
                 Ok(wallet)
             },
-            Err(e) => Err(e.into())
+            Err(e) => Err(Error::Ledger(e.to_string()))
         }
     }
 
     pub fn get() -> Wallets {
-        match Wallets::load() {
-            Ok(nm) => nm,
-            Err(_) => {
-                Wallets::new()
-            }
-        }
+        Wallets::load().unwrap_or_default()
     }
 
-    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn save(&self) -> Result<(), Error> {
         let serialized_wallet_list = match to_string(&self) {
             Ok(s) => s,
             Err(e) => {
-                klave::notifier::send_string(&format!("ERROR: failed to serialize wallet list: {}", e));
                 return Err(e.into());
             }
         };
-        klave::ledger::get_table(WALLET_TABLE).set("ALL", &serialized_wallet_list.as_bytes())
+        match klave::ledger::get_table(WALLET_TABLE).set("ALL", serialized_wallet_list.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::Ledger(format!("failed to save wallet list: {}", e)))
+        }
     }
 }